@@ -0,0 +1,155 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use prometheus_wireguard_exporter::{peer_entry_hashmap_try_from, Options, WireGuard};
+use std::convert::TryFrom;
+
+const PEERS: [usize; 3] = [10, 100, 1000];
+
+fn public_key(interface: usize, peer: usize) -> String {
+    // 32 bytes, base64 encoded, like the real ones
+    format!("{:0>43}=", format!("{}x{}", interface, peer))
+}
+
+// A wg show all dump of the given interfaces, each with the given peers.
+// Every other peer has two allowed ips and every tenth one has never
+// connected.
+fn synthetic_dump(interfaces: usize, peers: usize) -> String {
+    let mut dump = String::new();
+    for i in 0..interfaces {
+        dump.push_str(&format!(
+            "wg{}\tprivate_key\t{}\t{}\toff\n",
+            i,
+            public_key(i, 0),
+            51820 + i
+        ));
+        for p in 1..=peers {
+            let allowed_ips = if p % 2 == 0 {
+                format!(
+                    "10.{}.{}.{}/32,fd00:{}::{:x}/128",
+                    i,
+                    p / 256,
+                    p % 256,
+                    i,
+                    p
+                )
+            } else {
+                format!("10.{}.{}.{}/32", i, p / 256, p % 256)
+            };
+            let (endpoint, latest_handshake) = if p % 10 == 0 {
+                ("(none)".to_owned(), 0)
+            } else {
+                (
+                    format!("192.0.2.{}:{}", p % 256, 40000 + p),
+                    1_700_000_000 + p,
+                )
+            };
+            dump.push_str(&format!(
+                "wg{}\t{}\t(none)\t{}\t{}\t{}\t{}\t{}\t25\n",
+                i,
+                public_key(i, p),
+                endpoint,
+                allowed_ips,
+                latest_handshake,
+                p * 1024,
+                p * 2048
+            ));
+        }
+    }
+    dump
+}
+
+// The config file naming every peer of the dump, half with friendly_name
+// and half with friendly_json.
+fn synthetic_config(interfaces: usize, peers: usize) -> String {
+    let mut config = String::new();
+    for i in 0..interfaces {
+        for p in 1..=peers {
+            if p % 2 == 0 {
+                config.push_str(&format!(
+                    "[Peer]\n# friendly_name = peer {} of wg{}\n",
+                    p, i
+                ));
+            } else {
+                config.push_str(&format!(
+                    "[Peer]\n# friendly_json = {{\"username\":\"user{}\",\"id\":{}}}\n",
+                    p, p
+                ));
+            }
+            config.push_str(&format!(
+                "PublicKey = {}\nAllowedIPs = 10.{}.{}.{}/32\n\n",
+                public_key(i, p),
+                i,
+                p / 256,
+                p % 256
+            ));
+        }
+    }
+    config
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_dump");
+    for peers in PEERS {
+        let dump = synthetic_dump(1, peers);
+        group.bench_with_input(BenchmarkId::from_parameter(peers), &dump, |b, dump| {
+            b.iter(|| WireGuard::try_from(dump.as_str()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_render(c: &mut Criterion) {
+    let joined = Options::default();
+    let mut split = Options::default();
+    split.separate_allowed_ips = true;
+    split.export_remote_ip_and_port = true;
+
+    let mut group = c.benchmark_group("render_with_names");
+    for peers in PEERS {
+        let wg = WireGuard::try_from(synthetic_dump(1, peers).as_str()).unwrap();
+        let config = synthetic_config(1, peers);
+        let pehm = peer_entry_hashmap_try_from(&config).unwrap();
+
+        group.bench_with_input(BenchmarkId::new("joined", peers), &wg, |b, wg| {
+            b.iter(|| wg.render_with_names(Some(&pehm), &joined))
+        });
+        group.bench_with_input(BenchmarkId::new("split", peers), &wg, |b, wg| {
+            b.iter(|| wg.render_with_names(Some(&pehm), &split))
+        });
+    }
+    group.finish();
+}
+
+// the path of -i with more than one interface: every interface is
+// parsed on its own and merged in a single WireGuard
+fn bench_merge(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merge");
+    for peers in PEERS {
+        let dumps: Vec<String> = (0..4)
+            .map(|i| {
+                synthetic_dump(i + 1, peers)
+                    .lines()
+                    .filter(|line| line.starts_with(&format!("wg{}\t", i)))
+                    .map(|line| format!("{}\n", line))
+                    .collect()
+            })
+            .collect();
+        group.bench_with_input(BenchmarkId::from_parameter(peers), &dumps, |b, dumps| {
+            b.iter(|| {
+                let mut wg_accumulator: Option<WireGuard> = None;
+                for dump in dumps {
+                    let wg = WireGuard::try_from(dump.as_str()).unwrap();
+                    if let Some(wg_accumulator) = &mut wg_accumulator {
+                        wg_accumulator.merge(&wg);
+                    } else {
+                        wg_accumulator = Some(wg);
+                    }
+                }
+                wg_accumulator
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse, bench_render, bench_merge);
+criterion_main!(benches);
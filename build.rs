@@ -0,0 +1,20 @@
+use std::process::Command;
+
+// Bakes the rustc version in the binary for wireguard_exporter_build_info.
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    // rustc --version prints rustc 1.65.0 (897e37553 2022-11-02)
+    let rustc_version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .and_then(|version| version.split_whitespace().nth(1).map(|v| v.to_owned()))
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    println!(
+        "cargo:rustc-env=PROMETHEUS_WIREGUARD_EXPORTER_RUSTC_VERSION={}",
+        rustc_version
+    );
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,109 @@
+use crate::wireguard::SecureString;
+use http::HeaderValue;
+use sha2::{Digest, Sha256};
+
+// The credentials accepted on the metrics endpoints. When both the token and
+// the user and password are configured either of them is enough.
+#[derive(Debug, Clone, Default)]
+pub struct Authorization {
+    // the expected Authorization header values
+    bearer: Option<SecureString>,
+    basic: Option<SecureString>,
+}
+
+impl Authorization {
+    // basic_auth is in the user:password format
+    pub fn new(auth_token: Option<&str>, basic_auth: Option<&str>) -> Self {
+        Authorization {
+            bearer: auth_token.map(|token| format!("Bearer {}", token).as_str().into()),
+            basic: basic_auth.map(|basic_auth| {
+                format!("Basic {}", base64::encode(basic_auth))
+                    .as_str()
+                    .into()
+            }),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.bearer.is_some() || self.basic.is_some()
+    }
+
+    // The challenge returned along with 401 Unauthorized
+    pub fn www_authenticate(&self) -> &'static str {
+        if self.basic.is_some() {
+            "Basic realm=\"prometheus_wireguard_exporter\""
+        } else {
+            "Bearer"
+        }
+    }
+
+    pub fn is_authorized(&self, authorization: Option<&HeaderValue>) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+
+        let authorization = match authorization {
+            Some(authorization) => authorization.as_bytes(),
+            None => return false,
+        };
+        // no short circuit, both are always compared
+        let bearer = self
+            .bearer
+            .as_ref()
+            .is_some_and(|bearer| constant_time_eq(bearer.expose().as_bytes(), authorization));
+        let basic = self
+            .basic
+            .as_ref()
+            .is_some_and(|basic| constant_time_eq(basic.expose().as_bytes(), authorization));
+        bearer | basic
+    }
+}
+
+// The digests are compared in place of the values so the time taken does not
+// depend on the length of the values either.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    Sha256::digest(a)
+        .iter()
+        .zip(Sha256::digest(b).iter())
+        .fold(0, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorization() {
+        let header = |value: &str| HeaderValue::from_str(value).unwrap();
+
+        let authorization = Authorization::default();
+        assert!(!authorization.is_enabled());
+        assert!(authorization.is_authorized(None));
+
+        let authorization = Authorization::new(Some("s3cr3t"), None);
+        assert!(authorization.is_authorized(Some(&header("Bearer s3cr3t"))));
+        assert!(!authorization.is_authorized(Some(&header("Bearer s3cr3"))));
+        assert!(!authorization.is_authorized(Some(&header("s3cr3t"))));
+        assert!(!authorization.is_authorized(None));
+        assert_eq!(authorization.www_authenticate(), "Bearer");
+
+        // prometheus:s3cr3t
+        let authorization = Authorization::new(Some("s3cr3t"), Some("prometheus:s3cr3t"));
+        assert!(authorization.is_authorized(Some(&header("Basic cHJvbWV0aGV1czpzM2NyM3Q="))));
+        assert!(authorization.is_authorized(Some(&header("Bearer s3cr3t"))));
+        assert!(!authorization.is_authorized(Some(&header("Basic cHJvbWV0aGV1czpzM2NyM3U="))));
+        assert_eq!(
+            authorization.www_authenticate(),
+            "Basic realm=\"prometheus_wireguard_exporter\""
+        );
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
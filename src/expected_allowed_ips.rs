@@ -0,0 +1,79 @@
+use crate::exporter_error::ExpectedAllowedIpsParseError;
+use crate::wireguard::canonical_allowed_ips;
+use log::debug;
+use std::collections::HashMap;
+
+// The security baseline: the canonical allowed ips every listed peer
+// is expected to have, keyed by public key.
+pub type ExpectedAllowedIpsMap = HashMap<String, String>;
+
+// The file is a JSON object keyed by public key, the allowed ips are
+// either a comma separated string or an array of strings, for example:
+// { "2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=": ["10.70.0.2/32"] }
+pub fn expected_allowed_ips_try_from(
+    txt: &str,
+) -> Result<ExpectedAllowedIpsMap, ExpectedAllowedIpsParseError> {
+    let json: serde_json::Value = serde_json::from_str(txt)?;
+    let peers = json
+        .as_object()
+        .ok_or(ExpectedAllowedIpsParseError::NotAnObject)?;
+
+    let mut expected_allowed_ips_map = ExpectedAllowedIpsMap::new();
+    for (public_key, value) in peers {
+        let invalid_value = || ExpectedAllowedIpsParseError::InvalidValue {
+            public_key: public_key.to_owned(),
+        };
+
+        let allowed_ips = match value {
+            serde_json::Value::String(allowed_ips) => allowed_ips.to_owned(),
+            serde_json::Value::Array(allowed_ips) => allowed_ips
+                .iter()
+                .map(|allowed_ip| allowed_ip.as_str().ok_or_else(invalid_value))
+                .collect::<Result<Vec<_>, _>>()?
+                .join(","),
+            _ => return Err(invalid_value()),
+        };
+
+        expected_allowed_ips_map.insert(public_key.to_owned(), canonical_allowed_ips(&allowed_ips));
+    }
+
+    debug!(
+        "expected_allowed_ips_try_from returning {:?}",
+        expected_allowed_ips_map
+    );
+    Ok(expected_allowed_ips_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        const TEXT: &str = r#"{
+    "2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=": ["10.70.0.66/32", "10.70.0.2/32"],
+    "qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=": "10.70.0.3/32"
+}"#;
+        let expected_allowed_ips_map = expected_allowed_ips_try_from(TEXT).unwrap();
+        assert_eq!(
+            expected_allowed_ips_map["2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk="],
+            "10.70.0.2/32,10.70.0.66/32"
+        );
+        assert_eq!(
+            expected_allowed_ips_map["qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU="],
+            "10.70.0.3/32"
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(
+            expected_allowed_ips_try_from("[]"),
+            Err(ExpectedAllowedIpsParseError::NotAnObject)
+        ));
+        assert!(matches!(
+            expected_allowed_ips_try_from(r#"{ "key": [1] }"#),
+            Err(ExpectedAllowedIpsParseError::InvalidValue { .. })
+        ));
+    }
+}
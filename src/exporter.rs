@@ -0,0 +1,1681 @@
+use anyhow::Context;
+use clap::{crate_authors, crate_name, crate_version, value_parser, Arg, ArgAction};
+use futures::stream::{self, StreamExt};
+use hyper::{Body, Request};
+use log::{debug, info, trace, warn};
+use options::Options;
+
+use crate::{
+    dump_analysis, exporter_error, http_server, maintenance_window, options, remote_host_cache,
+    scrape_state, site_extractor, stdio, tls, wireguard, wireguard_config,
+};
+
+use dump_analysis::analyze_dump;
+use exporter_error::ExporterError;
+use scrape_state::ScrapeGuard;
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::io::Read;
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use tokio::signal::unix::{signal, SignalKind};
+use wireguard::{
+    escape_label_value, is_reserved_label_name, is_valid_label_name, render_build_info,
+    render_concurrent_scrapes, render_config_parse_errors, render_draining,
+    render_dump_line_counts, render_invalid_public_keys, render_scrape_duration,
+    render_scrape_modes, render_up, to_openmetrics, DumpLineCounts, ScrapeMode, WireGuard,
+};
+use wireguard_config::{merge_names_file, names_file_try_from, parse_config_files};
+
+// The command line of wg with the specified arguments, the program is the
+// first item.
+fn wg_argv<'a>(args: &[&'a str], options: &'a Options) -> Vec<&'a str> {
+    let wg = options.wg_path.as_deref().unwrap_or("wg");
+    let mut argv = Vec::new();
+    if options.prepend_sudo {
+        argv.push("sudo");
+    }
+    argv.push(wg);
+    argv.extend_from_slice(args);
+    argv
+}
+
+// Runs wg with the specified arguments. Returns None if wg did not finish
+// within wg_timeout_seconds.
+async fn run_wg(
+    args: &[&str],
+    options: &Options,
+) -> Result<Option<std::process::Output>, std::io::Error> {
+    let argv = wg_argv(args, options);
+    debug!("running {:?}", argv);
+    // kill_on_drop kills a wg that is still running when the timeout
+    // drops the future
+    let output = Command::new(argv[0])
+        .args(&argv[1..])
+        .kill_on_drop(true)
+        .output();
+    if options.wg_timeout_seconds == 0 {
+        output.await.map(Some)
+    } else {
+        match tokio::time::timeout(Duration::from_secs(options.wg_timeout_seconds), output).await {
+            Ok(output) => output.map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+// Runs wg show <interface> dump and returns its stdout.
+async fn run_wg_show_dump(
+    interface_to_handle: &str,
+    options: &Options,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let output = run_wg(&["show", interface_to_handle, "dump"], options)
+        .await?
+        .ok_or_else(|| ExporterError::WgTimeout {
+            interface: interface_to_handle.to_owned(),
+            timeout: options.wg_timeout_seconds,
+        })?;
+
+    Ok(check_wg_output(interface_to_handle, output)?)
+}
+
+// Runs wg show interfaces and returns the names of the interfaces.
+async fn run_wg_show_interfaces(
+    options: &Options,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let output = run_wg(&["show", "interfaces"], options).await?.ok_or(
+        ExporterError::WgShowInterfacesTimeout {
+            timeout: options.wg_timeout_seconds,
+        },
+    )?;
+
+    if !output.status.success() {
+        return Err(Box::new(ExporterError::WgShowInterfacesFailed {
+            exit_code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        }));
+    }
+
+    Ok(String::from_utf8(output.stdout)?
+        .split_whitespace()
+        .map(|interface| interface.to_owned())
+        .collect())
+}
+
+// With --enumerate_interfaces all is replaced by the interfaces listed by
+// wg show interfaces, so they are dumped one by one. If they cannot be
+// listed, or there are none, all is dumped as usual.
+async fn resolve_interfaces_to_handle(
+    interfaces_to_handle: &[String],
+    options: &Options,
+) -> Vec<String> {
+    if !options.enumerate_interfaces
+        || options.dump_file.is_some()
+        || options.stdin_dump.is_some()
+        || interfaces_to_handle != ["all"]
+    {
+        return interfaces_to_handle.to_vec();
+    }
+
+    match run_wg_show_interfaces(options).await {
+        Ok(interfaces) if !interfaces.is_empty() => interfaces,
+        Ok(_) => interfaces_to_handle.to_vec(),
+        Err(e) => {
+            warn!("failed to list the interfaces, dumping all: {}", e);
+            interfaces_to_handle.to_vec()
+        }
+    }
+}
+
+// Returns the stdout of wg, failing if wg exited with an error.
+fn check_wg_output(
+    interface_to_handle: &str,
+    output: std::process::Output,
+) -> Result<String, ExporterError> {
+    let output_stderr_str = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+    trace!(
+        "wg show {} dump stderr == {}",
+        interface_to_handle,
+        output_stderr_str
+    );
+
+    if !output.status.success() {
+        return Err(ExporterError::WgCommandFailed {
+            interface: interface_to_handle.to_owned(),
+            exit_code: output.status.code(),
+            stderr: output_stderr_str,
+        });
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+// The wg invocations run concurrently (up to wg_concurrency at a time). The
+// results, along with their interface, are returned in the same order of
+// interfaces_to_handle so the merge stays deterministic.
+async fn collect_dumps(
+    interfaces_to_handle: &[String],
+    options: &Arc<Options>,
+) -> Vec<(
+    String,
+    Result<String, Box<dyn std::error::Error + Send + Sync>>,
+)> {
+    // the dump read from stdin is a wg show all dump: it is handled once and
+    // every requested interface gets it, to be filtered once parsed
+    if options.stdin_dump.is_some() {
+        let dump = collect_dump("all".to_owned(), options.clone())
+            .await
+            .map_err(|e| e.to_string());
+        return interfaces_to_handle
+            .iter()
+            .map(|interface_to_handle| {
+                (
+                    interface_to_handle.to_owned(),
+                    dump.clone().map_err(|e| e.into()),
+                )
+            })
+            .collect();
+    }
+
+    stream::iter(resolve_interfaces_to_handle(interfaces_to_handle, options).await)
+        .map(|interface_to_handle| async {
+            let dump = collect_dump(interface_to_handle.clone(), options.clone()).await;
+            (interface_to_handle, dump)
+        })
+        .buffered(options.wg_concurrency as usize)
+        .collect::<Vec<_>>()
+        .await
+}
+
+// Same as collect_dumps but, with cache_seconds, the dumps are reused for
+// cache_seconds after the collection. Also returns whether they were reused.
+async fn collect_dumps_cached(
+    interfaces_to_handle: &[String],
+    options: &Arc<Options>,
+) -> (
+    Vec<(
+        String,
+        Result<String, Box<dyn std::error::Error + Send + Sync>>,
+    )>,
+    bool,
+) {
+    if options.cache_seconds == 0 {
+        return (collect_dumps(interfaces_to_handle, options).await, false);
+    }
+
+    let to_result = |dumps: &[(String, Result<String, String>)]| {
+        dumps
+            .iter()
+            .map(|(interface, dump)| (interface.to_owned(), dump.clone().map_err(|e| e.into())))
+            .collect()
+    };
+
+    let mut dump_cache = options.dump_cache.lock().await;
+    if let Some(dumps) = dump_cache.get(Instant::now(), Duration::from_secs(options.cache_seconds))
+    {
+        debug!("reusing the cached wg dumps");
+        return (to_result(dumps), true);
+    }
+
+    let dumps = collect_dumps(interfaces_to_handle, options)
+        .await
+        .into_iter()
+        .map(|(interface, dump)| (interface, dump.map_err(|e| e.to_string())))
+        .collect::<Vec<_>>();
+    let result = to_result(&dumps);
+    dump_cache.set(Instant::now(), dumps);
+    (result, false)
+}
+
+// Fetches the wg show all dump output from the remote agent
+async fn fetch_remote_dump(
+    url: String,
+    options: Arc<Options>,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let fetch = async {
+        let response = hyper::Client::new().get(url.parse()?).await?;
+        if !response.status().is_success() {
+            return Err(Box::new(ExporterError::RemoteAgentStatus {
+                url: url.clone(),
+                status: response.status(),
+            })
+            .into());
+        }
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(String::from_utf8(body.to_vec())?)
+    };
+
+    let dump = tokio::time::timeout(
+        Duration::from_secs(options.remote_agent_timeout_seconds),
+        fetch,
+    )
+    .await
+    .map_err(|_| ExporterError::RemoteAgentTimeout {
+        url: url.clone(),
+        timeout: options.remote_agent_timeout_seconds,
+    })??;
+    trace!("remote agent {} dump == {}", url, dump);
+
+    // the agent is not trusted with the private keys either
+    Ok(if options.no_private_key_readout {
+        wireguard::redact_private_keys(&dump, true)
+    } else {
+        dump
+    })
+}
+
+async fn collect_dump(
+    interface_to_handle: String,
+    options: Arc<Options>,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let output_stdout_str = if let Some(stdin_dump) = &options.stdin_dump {
+        stdin_dump.clone()
+    } else if let Some(dump_file) = &options.dump_file {
+        debug!("reading {} instead of running wg", dump_file);
+        std::fs::read_to_string(dump_file)
+            .with_context(|| format!("failed to read dump file {}", dump_file))?
+    } else {
+        run_wg_show_dump(&interface_to_handle, &options).await?
+    };
+
+    let output_stdout_str = if let Some(field_separator) = options.field_separator {
+        wireguard::normalize_field_separator(
+            &output_stdout_str,
+            field_separator,
+            interface_to_handle == "all",
+        )
+    } else {
+        output_stdout_str
+    };
+    // in hardened mode the private key is dropped before anything else
+    // can read or log it
+    let output_stdout_str = if options.no_private_key_readout {
+        wireguard::redact_private_keys(&output_stdout_str, interface_to_handle == "all")
+    } else {
+        output_stdout_str
+    };
+    trace!(
+        "wg show {} dump stdout == {}",
+        interface_to_handle,
+        output_stdout_str
+    );
+    // the output of wg show is different if we use all or we specify an interface.
+    // In the first case the first column will be the interface name. In the second case
+    // the interface name will be omitted. We need to compensate for the skew somehow (one
+    // column less in the second case). We solve this prepending the interface name in every
+    // line so the output of the second case will be equal to the first case.
+    let output_stdout_str = if interface_to_handle != "all" {
+        debug!("injecting {} to the wg show output", interface_to_handle);
+        let mut result = String::new();
+        for s in output_stdout_str.lines() {
+            result.push_str(&format!("{}\t{}\n", interface_to_handle, s));
+        }
+        result
+    } else {
+        output_stdout_str
+    };
+
+    Ok(output_stdout_str)
+}
+
+// Keeps the requested interface of the parsed stdin dump, which holds all of
+// them. If the interface is not there it fails, as wg show would.
+fn filter_stdin_dump(
+    (mut wg, mut counts): (WireGuard, DumpLineCounts),
+    interface_to_handle: &str,
+) -> Result<(WireGuard, DumpLineCounts), Box<dyn std::error::Error + Send + Sync>> {
+    wg.interfaces
+        .retain(|interface, _| interface == interface_to_handle);
+    counts.retain(|interface, _| interface == interface_to_handle);
+    if wg.interfaces.is_empty() {
+        return Err(Box::new(ExporterError::InterfaceNotInStdinDump {
+            interface: interface_to_handle.to_owned(),
+        }));
+    }
+    Ok((wg, counts))
+}
+
+pub(crate) async fn perform_request(
+    req: Request<Body>,
+    options: Arc<Options>,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let (_scrape_guard, concurrent_scrapes) = ScrapeGuard::enter(&options.concurrent_scrapes);
+    let started = Instant::now();
+
+    // with remote agents the local wg is queried only if explicitly asked
+    let interfaces_to_handle = match (&options.interfaces, &options.remote_agent_urls) {
+        (Some(interfaces_str), _) => interfaces_str.clone(),
+        (None, Some(_)) => vec![],
+        (None, None) => vec!["all".to_owned()],
+    };
+    log::trace!("interfaces_to_handle == {:?}", interfaces_to_handle);
+
+    let peer_entry_contents = options
+        .extract_names_config_files
+        .as_ref()
+        .map(|files| {
+            files // if we have values
+                .iter() // for each value
+                .map(|file| Ok((file.to_owned(), std::fs::read_to_string(file as &str)?))) // read the contents into a String
+                .collect::<Result<Vec<(String, String)>, std::io::Error>>() // And transform it into a vec (stopping in case of errors)
+        })
+        .transpose()
+        .with_context(|| "failed to read peer config file")?; // bail out if there was an error
+
+    // a friendly description that cannot be parsed is skipped and counted
+    // instead of failing the whole scrape
+    let (mut peer_entry_hashmap, config_parse_errors) = match peer_entry_contents
+        .as_ref()
+        .map(|config_files| parse_config_files(config_files))
+        .transpose()?
+    {
+        Some((peer_entry_hashmap, parse_errors)) => (
+            Some(peer_entry_hashmap),
+            Some(
+                options
+                    .scrape_state
+                    .lock()
+                    .unwrap()
+                    .observe_config_parse_errors(parse_errors),
+            ),
+        ),
+        None => (None, None),
+    };
+
+    if let Some(names_file) = &options.names_file {
+        let contents = std::fs::read_to_string(names_file)
+            .with_context(|| format!("failed to read names file {}", names_file))?;
+        let names = names_file_try_from(names_file, &contents)?;
+
+        match &mut peer_entry_hashmap {
+            Some(peer_entry_hashmap) => merge_names_file(peer_entry_hashmap, names),
+            None => peer_entry_hashmap = Some(names),
+        }
+    }
+
+    trace!("peer_entry_hashmap == {:#?}", peer_entry_hashmap);
+
+    let (dumps, from_cache) = collect_dumps_cached(&interfaces_to_handle, &options).await;
+
+    let mut wg_accumulator: Option<WireGuard> = None;
+    let mut scrape_modes = BTreeMap::new();
+    let mut up = BTreeMap::new();
+    let mut dump_line_counts = DumpLineCounts::new();
+
+    for (interface_to_handle, dump) in dumps {
+        // a failing interface does not fail the whole scrape, it is
+        // reported in wireguard_up instead
+        let parsed =
+            dump.and_then(|dump| Ok(WireGuard::parse_dump(&dump, options.skip_malformed_lines)?));
+        let parsed = if options.stdin_dump.is_some() && interface_to_handle != "all" {
+            parsed.and_then(|parsed| filter_stdin_dump(parsed, &interface_to_handle))
+        } else {
+            parsed
+        };
+        let mut wg = match parsed {
+            Ok((wg, counts)) => {
+                up.insert(interface_to_handle.to_owned(), true);
+                for (interface, count) in counts {
+                    let dump_line_count = dump_line_counts.entry(interface).or_default();
+                    dump_line_count.parsed += count.parsed;
+                    dump_line_count.skipped += count.skipped;
+                }
+                wg
+            }
+            Err(e) => {
+                warn!("failed to collect {} wg data: {}", interface_to_handle, e);
+                up.insert(interface_to_handle.to_owned(), false);
+                continue;
+            }
+        };
+
+        // the counters of a cached dump must not feed the rates again
+        if from_cache {
+            wg.set_from_cache();
+        }
+
+        let scrape_mode = if interface_to_handle == "all" {
+            ScrapeMode::All
+        } else {
+            ScrapeMode::Explicit
+        };
+        for interface in wg.interfaces.keys() {
+            scrape_modes.insert(interface.to_owned(), scrape_mode);
+        }
+
+        if let Some(wg_accumulator) = &mut wg_accumulator {
+            wg_accumulator.merge(&wg);
+        } else {
+            wg_accumulator = Some(wg);
+        };
+    }
+
+    let mut source_up = BTreeMap::new();
+    if let Some(remote_agent_urls) = &options.remote_agent_urls {
+        let remote_dumps = stream::iter(remote_agent_urls.clone())
+            .map(|url| fetch_remote_dump(url, options.clone()))
+            .buffered(options.wg_concurrency as usize)
+            .collect::<Vec<_>>()
+            .await;
+
+        for (url, dump) in remote_agent_urls.iter().zip(remote_dumps) {
+            let parsed = dump
+                .and_then(|dump| Ok(WireGuard::parse_dump(&dump, options.skip_malformed_lines)?));
+            let mut wg = match parsed {
+                Ok((wg, _)) => {
+                    source_up.insert(url.to_owned(), true);
+                    wg
+                }
+                Err(e) => {
+                    warn!("failed to collect wg data from remote agent {}: {}", url, e);
+                    source_up.insert(url.to_owned(), false);
+                    continue;
+                }
+            };
+            wg.set_source(url);
+
+            if let Some(wg_accumulator) = &mut wg_accumulator {
+                wg_accumulator.merge(&wg);
+            } else {
+                wg_accumulator = Some(wg);
+            };
+        }
+    }
+
+    if up.values().chain(source_up.values()).any(|up| *up) {
+        options.collected.store(true, Ordering::SeqCst);
+    }
+
+    let mut wg_accumulator = wg_accumulator.unwrap_or_default();
+
+    // the exclusion happens after the dump so new interfaces are exported
+    // unless excluded
+    if let Some(exclude_interfaces) = &options.exclude_interfaces {
+        wg_accumulator.exclude_interfaces(exclude_interfaces);
+        scrape_modes.retain(|interface, _| !exclude_interfaces.contains(interface));
+        dump_line_counts.retain(|interface, _| !exclude_interfaces.contains(interface));
+    }
+
+    if options.resolve_remote_ip {
+        remote_host_cache::resolve_remote_hosts(&wg_accumulator, &options).await;
+    }
+
+    if let Some(peer_entry_hashmap) = &peer_entry_hashmap {
+        wg_accumulator.check_duplicate_friendly_names(peer_entry_hashmap, &options)?;
+    }
+
+    if http_server::is_metrics_json_path(req.uri().path(), &options) {
+        return Ok(wg_accumulator.render_json(peer_entry_hashmap.as_ref(), &options)?);
+    }
+
+    let mut rendered = wg_accumulator.render_with_names(peer_entry_hashmap.as_ref(), &options);
+
+    rendered.push('\n');
+    rendered.push_str(&render_up(&up, &source_up, &options));
+
+    rendered.push('\n');
+    rendered.push_str(&render_build_info(&options));
+
+    if let Some(config_parse_errors) = config_parse_errors {
+        rendered.push('\n');
+        rendered.push_str(&render_config_parse_errors(config_parse_errors, &options));
+    }
+
+    if options.validate_public_keys {
+        let invalid_public_keys = options
+            .scrape_state
+            .lock()
+            .unwrap()
+            .observe_invalid_public_keys(wg_accumulator.invalid_public_keys());
+        rendered.push('\n');
+        rendered.push_str(&render_invalid_public_keys(invalid_public_keys, &options));
+    }
+
+    if options.export_dump_line_counts {
+        rendered.push('\n');
+        rendered.push_str(&render_dump_line_counts(&dump_line_counts, &options));
+    }
+
+    if options.export_scrape_mode {
+        rendered.push('\n');
+        rendered.push_str(&render_scrape_modes(&scrape_modes, &options));
+    }
+
+    if options.export_concurrent_scrapes {
+        rendered.push('\n');
+        rendered.push_str(&render_concurrent_scrapes(concurrent_scrapes, &options));
+    }
+
+    if options.shutdown_grace_period > 0 {
+        rendered.push('\n');
+        rendered.push_str(&render_draining(
+            options.draining.load(Ordering::SeqCst),
+            &options,
+        ));
+    }
+
+    if let Some(tls_config) = options.tls_config.read().unwrap().as_ref() {
+        rendered.push('\n');
+        rendered.push_str(&tls::render_tls_cert_expiry(
+            tls_config.cert_not_after,
+            &options,
+        ));
+    }
+
+    // after the other families, so it includes their rendering. Only the
+    // OpenMetrics conversion, which needs the whole output, is not included
+    if options.export_scrape_duration {
+        rendered.push('\n');
+        rendered.push_str(&render_scrape_duration(started.elapsed(), &options));
+    }
+
+    if options.openmetrics {
+        rendered = to_openmetrics(&rendered, options.openmetrics_handshake_timestamps);
+    }
+
+    Ok(rendered)
+}
+
+async fn reload_on_sighup(options: Options) -> Result<(), std::io::Error> {
+    let mut sighup = signal(SignalKind::hangup())?;
+
+    while sighup.recv().await.is_some() {
+        info!("SIGHUP received, reloading the public key filter lists");
+
+        // in case of errors we keep the previous lists
+        if let Err(e) = options.load_peer_filter() {
+            warn!("failed to reload the public key filter lists: {}", e);
+        }
+
+        if options.geoip_db_files.is_some() {
+            info!("SIGHUP received, reloading the GeoIP databases");
+            options.load_geoip();
+        }
+
+        if options.tls_cert_file.is_some() {
+            info!("SIGHUP received, reloading the TLS certificate and key");
+
+            // in case of errors we keep serving the previous certificate
+            if let Err(e) = options.load_tls_config() {
+                warn!("failed to reload the TLS certificate and key: {}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// On SIGTERM (or SIGINT) the exporter keeps serving the scrapes, flagged as
+// draining, for the grace period and then exits.
+async fn drain_on_shutdown(options: Options) -> Result<(), std::io::Error> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+
+    tokio::select! {
+        _ = sigterm.recv() => info!("SIGTERM received"),
+        _ = sigint.recv() => info!("SIGINT received"),
+    }
+
+    info!(
+        "draining for {} seconds before exiting",
+        options.shutdown_grace_period
+    );
+    options.draining.store(true, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_secs(options.shutdown_grace_period)).await;
+
+    info!("grace period expired, exiting");
+    std::process::exit(0);
+}
+
+// The command line of the exporter.
+pub(crate) fn command() -> clap::Command {
+    clap::Command::new(crate_name!())
+        .version(crate_version!())
+        .author(crate_authors!("\n"))
+        .arg(
+            Arg::new("addr")
+                .short('l')
+                .long("address")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_ADDRESS")
+                .value_parser(value_parser!(IpAddr))
+                .help("exporter address. Can be specified more than once to listen on more addresses (for example 0.0.0.0 and ::)")
+                .action(ArgAction::Append)
+                .default_value("0.0.0.0")
+        )
+        .arg(
+            Arg::new("port")
+                .short('p')
+                .long("port")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_PORT")
+                // 0 would listen on a random port
+                .value_parser(value_parser!(u16).range(1..))
+                .help("exporter port")
+                .default_value("9586")
+        )
+        .arg(
+            Arg::new("metrics_path")
+                .long("metrics_path")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_METRICS_PATH")
+                .value_parser(|s: &str| {
+                    if !s.starts_with('/') {
+                        Err("must start with /")
+                    } else if s == "/" || s == "/health" || s == "/ready" {
+                        Err("/, /health and /ready are reserved for the probes")
+                    } else {
+                        Ok(s.to_owned())
+                    }
+                })
+                .help("URL path of the metrics, the JSON is at the same path followed by .json")
+                .default_value(http_server::METRICS_PATH)
+        )
+        .arg(
+            Arg::new("external_path_prefix")
+                .long("external_path_prefix")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXTERNAL_PATH_PREFIX")
+                .value_parser(|s: &str| {
+                    if !s.starts_with('/') {
+                        Err("must start with /")
+                    } else {
+                        Ok(s.trim_end_matches('/').to_owned())
+                    }
+                })
+                .help("path prefix of a reverse proxy in front of the exporter: every path is served with or without it")
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_VERBOSE_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("verbose logging")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("prepend_sudo")
+                .short('a')
+                .long("prepend_sudo")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_PREPEND_SUDO_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("Prepend sudo to the wg show commands")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("separate_allowed_ips")
+                .short('s')
+                .long("separate_allowed_ips")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_SEPARATE_ALLOWED_IPS_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("separate allowed ips and ports")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_remote_ip_and_port")
+                .short('r')
+                .long("export_remote_ip_and_port")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_REMOTE_IP_AND_PORT_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports peer's remote ip and port as labels (if available)")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("endpoint_label")
+                .long("endpoint_label")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_ENDPOINT_LABEL_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports peer's remote endpoint as a single endpoint label (if available)")
+                .default_value("false")
+                .conflicts_with("export_remote_ip_and_port")
+        )
+        .arg(
+            Arg::new("extract_names_config_files")
+                .short('n')
+                .long("extract_names_config_files")
+                .num_args(0..)
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_CONFIG_FILE_NAMES")
+                .help("If set, the exporter will look in the specified WireGuard config file for peer names (must be in [Peer] definition and be a comment). Multiple files are supported.")
+                .use_value_delimiter(false))
+        .arg(
+            Arg::new("interfaces")
+                .short('i')
+                .long("interfaces")
+                .num_args(0..)
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_INTERFACES")
+                .help("If set specifies the interface passed to the wg show command. It is relative to the same position config_file. In not specified, all will be passed.")
+                .use_value_delimiter(false))
+        .arg(
+            Arg::new("enumerate_interfaces")
+                .long("enumerate_interfaces")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_ENUMERATE_INTERFACES_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("without -i, lists the interfaces with wg show interfaces and dumps them one by one instead of running wg show all dump")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("no_interface_label")
+                .long("no_interface_label")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_NO_INTERFACE_LABEL_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("drops the interface label from every series. Requires a single interface passed with -i")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("exclude_interfaces")
+                .long("exclude_interface")
+                .num_args(0..)
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXCLUDE_INTERFACES")
+                .help("If set, the specified interfaces are not exported. Multiple interfaces are supported.")
+                .use_value_delimiter(false))
+        .arg(
+            Arg::new("resolve_remote_ip")
+                .long("resolve_remote_ip")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_RESOLVE_REMOTE_IP_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("adds the remote_host label with the reverse DNS name of the remote ip (requires -r)")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("resolve_remote_ip_ttl_seconds")
+                .long("resolve_remote_ip_ttl_seconds")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_RESOLVE_REMOTE_IP_TTL_SECONDS")
+                .value_parser(value_parser!(u64))
+                .help("seconds the reverse DNS names of the remote ips are cached")
+                .default_value("300")
+        )
+        .arg(
+            Arg::new("geoip_db_files")
+                .long("geoip_db")
+                .num_args(0..)
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_GEOIP_DB_FILES")
+                .help("MaxMind databases (.mmdb) used to add the remote_country and remote_asn labels (requires -r). Multiple files are supported.")
+                .use_value_delimiter(false))
+        .arg(
+            Arg::new("static_labels")
+                .long("label")
+                .action(ArgAction::Append)
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_LABELS")
+                .value_parser(|label: &str| match label.split_once('=') {
+                    Some((name, _)) if !is_valid_label_name(name) => {
+                        Err("the label name must match [a-zA-Z_][a-zA-Z0-9_]* and cannot start with __")
+                    }
+                    Some((name, _)) if is_reserved_label_name(name) => {
+                        Err("the label name is already used by the exporter")
+                    }
+                    Some((name, value)) => {
+                        Ok((name.to_owned(), escape_label_value(value).into_owned()))
+                    }
+                    None => Err("expected key=value"),
+                })
+                .help("label added to every series, as key=value. Multiple labels are supported.")
+                .use_value_delimiter(false))
+        .arg(
+            Arg::new("openmetrics")
+                .long("openmetrics")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_OPENMETRICS_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the metrics in the OpenMetrics text format instead of the Prometheus one")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("openmetrics_handshake_timestamps")
+                .long("openmetrics_handshake_timestamps")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_OPENMETRICS_HANDSHAKE_TIMESTAMPS_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("uses the latest handshake as the timestamp of the wireguard_latest_handshake_seconds samples. Requires --openmetrics.")
+                .default_value("false")
+                .requires("openmetrics")
+        )
+        .arg(
+            Arg::new("cache_seconds")
+                .long("cache_seconds")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_CACHE_SECONDS")
+                .value_parser(value_parser!(u64))
+                .help("seconds the wg output is reused by the following scrapes, 0 to run wg on every scrape")
+                .default_value("0")
+        )
+        .arg(
+            Arg::new("auth_token")
+                .long("auth_token")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_AUTH_TOKEN")
+                .help("if set, the scrapes must send the Authorization: Bearer <token> header")
+        )
+        .arg(
+            Arg::new("basic_auth")
+                .long("basic_auth")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_BASIC_AUTH")
+                .value_parser(|s: &str| {
+                    if s.contains(':') {
+                        Ok(s.to_owned())
+                    } else {
+                        Err("must be in the user:password format")
+                    }
+                })
+                .help("if set (as user:password), the scrapes must authenticate with HTTP basic authentication")
+        )
+        .arg(
+            Arg::new("tls_cert_file")
+                .long("tls_cert")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_TLS_CERT")
+                .requires("tls_key_file")
+                .help("PEM certificate (chain) to serve the metrics over HTTPS, requires --tls_key. Reloaded on SIGHUP")
+        )
+        .arg(
+            Arg::new("tls_key_file")
+                .long("tls_key")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_TLS_KEY")
+                .requires("tls_cert_file")
+                .help("PEM private key of --tls_cert. Reloaded on SIGHUP")
+        )
+        .arg(
+            Arg::new("export_latest_handshake_delay")
+                .short('d')
+                .long("export_latest_handshake_delay")
+                .env("EXPORT_LATEST_HANDSHAKE_DELAY")
+                .value_parser(value_parser!(bool))
+                .help("exports runtime calculated latest handshake delay")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("skip_unsupported_json")
+                .long("skip_unsupported_json")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_SKIP_UNSUPPORTED_JSON_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("skips the friendly_json values that cannot be represented as labels (nulls, arrays and objects are flattened). If false they are exported as \"unsupported_json_value\"")
+                .default_value("true")
+        )
+        .arg(
+            Arg::new("wg_concurrency")
+                .long("wg_concurrency")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_WG_CONCURRENCY")
+                .value_parser(value_parser!(u16).range(1..))
+                .help("maximum number of wg show commands run concurrently when multiple interfaces are specified")
+                .default_value("4")
+        )
+        .arg(
+            Arg::new("export_scrape_mode")
+                .long("export_scrape_mode")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_SCRAPE_MODE_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports whether each interface was scraped with wg show all or with an explicit interface name (diagnostic)")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("maintenance_window")
+                .long("maintenance_window")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_MAINTENANCE_WINDOW")
+                .value_parser(value_parser!(maintenance_window::MaintenanceWindow))
+                .help("daily maintenance window in the HH:MM-HH:MM format (host timezone). If set, the wireguard_maintenance_active metric will be 1 within the window and 0 outside")
+        )
+        .arg(
+            Arg::new("export_counter_resets")
+                .long("export_counter_resets")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_COUNTER_RESETS_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports how many times the byte counters of each peer went backwards since the exporter started")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_bytes_per_second")
+                .long("export_bytes_per_second")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_BYTES_PER_SECOND_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the bytes sent and received per second by each peer since the previous scrape")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_configured_allowed_ips")
+                .long("export_configured_allowed_ips")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_CONFIGURED_ALLOWED_IPS_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the AllowedIPs of every peer found in the config files specified with -n, even if the peer is not live")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("site_from_allowed_ip")
+                .long("site_from_allowed_ip")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_SITE_FROM_ALLOWED_IP")
+                .value_parser(value_parser!(site_extractor::SiteExtractor))
+                .help("adds the site label taken from the specified octet of the first allowed IPv4 of the peer. The format is octet:N where N is between 1 and 4")
+        )
+        .arg(
+            Arg::new("export_handshake_label")
+                .long("export_handshake_label")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_HANDSHAKE_LABEL_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("adds the handshake_time label (RFC3339, UTC) to the latest handshake metric. Beware: the label changes at every handshake")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_unique_remote_ips")
+                .long("export_unique_remote_ips")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_UNIQUE_REMOTE_IPS_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the number of distinct remote ips per interface (requires -r)")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_interfaces_total")
+                .long("export_interfaces_total")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_INTERFACES_TOTAL_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the number of interfaces")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("no_private_key_readout")
+                .long("no_private_key_readout")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_NO_PRIVATE_KEY_READOUT_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("replaces the private key in the wg output with a placeholder as soon as it is read")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("interface_options_file")
+                .long("interface_options_file")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_INTERFACE_OPTIONS_FILE")
+                .help("JSON file with the per-interface overrides of -a and -r and the interface aliases")
+        )
+        .arg(
+            Arg::new("shutdown_grace_period")
+                .long("shutdown_grace_period")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_SHUTDOWN_GRACE_PERIOD")
+                .value_parser(value_parser!(u64))
+                .help("seconds to keep serving the scrapes after SIGTERM/SIGINT, exporting wireguard_exporter_draining 1. 0 exits immediately")
+                .default_value("0")
+        )
+        .arg(
+            Arg::new("field_separator")
+                .long("field_separator")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_FIELD_SEPARATOR")
+                .value_parser(["tab", "space", "comma"])
+                .help("field separator of the wg output. Change it only if wg is a wrapper that does not use tabs")
+                .default_value("tab")
+        )
+        .arg(
+            Arg::new("export_name_changes")
+                .long("export_name_changes")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_NAME_CHANGES_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports how many times the friendly name of each peer changed in the config files (requires -n)")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("handshake_timeout_seconds")
+                .long("handshake_timeout_seconds")
+                .action(ArgAction::Append)
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_HANDSHAKE_TIMEOUT_SECONDS")
+                .value_parser(|timeout: &str| {
+                    let (interface, seconds) = match timeout.split_once('=') {
+                        Some((interface, seconds)) => (Some(interface.to_owned()), seconds),
+                        None => (None, timeout),
+                    };
+                    seconds
+                        .parse::<u64>()
+                        .map(|seconds| (interface, seconds))
+                        .map_err(|_| "expected seconds or interface=seconds")
+                })
+                .help("a peer is considered connected if its latest handshake is at most this old. interface=seconds sets the timeout of a single interface. Multiple values are supported.")
+                .default_value("180")
+                .use_value_delimiter(false)
+        )
+        .arg(
+            Arg::new("connected_only")
+                .long("connected_only")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_CONNECTED_ONLY_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the peer metrics only for the connected peers. The peer counts are exported in wireguard_peers_total")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("skip_never_connected")
+                .long("skip_never_connected")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_SKIP_NEVER_CONNECTED_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("skips the peers that never had a handshake. The peer counts are exported in wireguard_peers_total")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("count_never_connected")
+                .long("count_never_connected")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_COUNT_NEVER_CONNECTED_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("counts the peers skipped by --skip_never_connected in wireguard_peers_total")
+                .default_value("true")
+        )
+        .arg(
+            Arg::new("max_peers_per_interface")
+                .long("max_peers_per_interface")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_MAX_PEERS_PER_INTERFACE")
+                .value_parser(value_parser!(usize))
+                .help("exports at most this many peers of each interface, 0 for no limit. The truncated interfaces are marked in wireguard_peers_truncated")
+                .default_value("0")
+        )
+        .arg(
+            Arg::new("export_concurrent_scrapes")
+                .long("export_concurrent_scrapes")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_CONCURRENT_SCRAPES_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the number of scrapes being served at the time of the collection")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_scrape_duration")
+                .long("export_scrape_duration")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_SCRAPE_DURATION_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports how long the scrape took, running wg and rendering included")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("dump_file")
+                .long("dump_file")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_DUMP_FILE")
+                .help("reads the wg show all dump output from the specified file at every scrape instead of running wg")
+        )
+        .arg(
+            Arg::new("canonical_allowed_ips")
+                .long("canonical_allowed_ips")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_CANONICAL_ALLOWED_IPS_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("sorts and deduplicates the allowed ips in the allowed_ips label")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_first_seen")
+                .long("export_first_seen")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_FIRST_SEEN_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports when each peer was seen for the first time since the exporter started")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("wg_path")
+                .long("wg_path")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_WG_PATH")
+                .help("path of the wg binary. If not specified wg is searched in the PATH")
+        )
+        .arg(
+            Arg::new("wg_timeout_seconds")
+                .long("wg_timeout_seconds")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_WG_TIMEOUT_SECONDS")
+                .value_parser(value_parser!(u64))
+                .help("seconds to wait for wg before killing it and considering the interface down, 0 to wait forever")
+                .default_value("0")
+        )
+        .arg(
+            Arg::new("name_comma_replacement")
+                .long("name_comma_replacement")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_NAME_COMMA_REPLACEMENT")
+                .help("replaces the commas in the friendly names with the specified string")
+        )
+        .arg(
+            Arg::new("skip_malformed_lines")
+                .long("skip_malformed_lines")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_SKIP_MALFORMED_LINES_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("skips the malformed lines of the wg output instead of failing the interface")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_dump_line_counts")
+                .long("export_dump_line_counts")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_DUMP_LINE_COUNTS_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the number of parsed and skipped lines of the wg output per interface")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_listen_port_and_fwmark")
+                .long("export_listen_port_and_fwmark")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_LISTEN_PORT_AND_FWMARK_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the listen port and the fwmark of the interfaces")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_preshared_key_presence")
+                .long("export_preshared_key_presence")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_PRESHARED_KEY_PRESENCE_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports whether each peer has a preshared key")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_allowed_ips_count")
+                .long("export_allowed_ips_count")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_ALLOWED_IPS_COUNT_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the number of allowed ips of each peer")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_peer_connected")
+                .long("export_peer_connected")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_PEER_CONNECTED_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports whether each peer is connected (see handshake_timeout_seconds)")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_persistent_keepalive")
+                .long("export_persistent_keepalive")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_PERSISTENT_KEEPALIVE_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the persistent keepalive interval of the peers")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_configured_peers")
+                .long("export_configured_peers")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_CONFIGURED_PEERS_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the number of peers in the config files and the ones missing from wg (requires -n)")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_unnamed_peers")
+                .long("export_unnamed_peers")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_UNNAMED_PEERS_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports wireguard_peer_unnamed for each peer missing from the config files (requires -n)")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("on_duplicate_name")
+                .long("on_duplicate_name")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_ON_DUPLICATE_NAME")
+                .value_parser(["warn", "suffix", "fail"])
+                .help("what to do when more than one peer has the same friendly_name: log a warning, append the start of the public key to the name or fail the scrape")
+                .default_value("warn")
+        )
+        .arg(
+            Arg::new("names_file")
+                .long("names_file")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_NAMES_FILE")
+                .help("JSON file mapping the public keys to the friendly names. Takes precedence over the friendly_name of the config files")
+        )
+        .arg(
+            Arg::new("hash_public_keys")
+                .long("hash_public_keys")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_HASH_PUBLIC_KEYS_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the first 12 hex digits of the SHA-256 of the public keys instead of the public keys")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("remote_agent_urls")
+                .long("remote_agent_url")
+                .conflicts_with("no_interface_label")
+                .num_args(0..)
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_REMOTE_AGENT_URLS")
+                .help("If set, the exporter fetches the wg show all dump output from the specified HTTP URLs too. Multiple URLs are supported.")
+                .use_value_delimiter(false))
+        .arg(
+            Arg::new("remote_agent_timeout_seconds")
+                .long("remote_agent_timeout_seconds")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_REMOTE_AGENT_TIMEOUT_SECONDS")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("seconds to wait for a remote agent before considering it down")
+                .default_value("10")
+        )
+        .arg(
+            Arg::new("export_peer_info")
+                .long("export_peer_info")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_PEER_INFO_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the descriptive labels of the peers only in wireguard_peer_info, the other metrics keep just interface and public_key")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_server_public_key")
+                .long("export_server_public_key")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_SERVER_PUBLIC_KEY_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("adds the server_public_key label, the public key of the interface, to the peer metrics")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_avg_bytes_per_peer")
+                .long("export_avg_bytes_per_peer")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_AVG_BYTES_PER_PEER_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the average bytes sent and received per peer of each interface")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_peers_by_family")
+                .long("export_peers_by_family")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_PEERS_BY_FAMILY_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the number of peers of each interface with IPv4 and with IPv6 allowed ips")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("validate_public_keys")
+                .long("validate_public_keys")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_VALIDATE_PUBLIC_KEYS_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("counts and logs the public keys that do not decode as 32 bytes of base64")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("export_peers_by_connection_age")
+                .long("export_peers_by_connection_age")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_PEERS_BY_CONNECTION_AGE_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the number of peers of each interface by how long they have been continuously present")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("connection_age_buckets")
+                .long("connection_age_buckets")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_CONNECTION_AGE_BUCKETS")
+                .value_parser(value_parser!(u64))
+                .num_args(1..)
+                .value_delimiter(',')
+                .help("comma separated upper bounds, in seconds, of the buckets of wireguard_peers_by_connection_age")
+                .default_value("3600,86400")
+        )
+        .arg(
+            Arg::new("export_handshake_age_histogram")
+                .long("export_handshake_age_histogram")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPORT_HANDSHAKE_AGE_HISTOGRAM_ENABLED")
+                .value_parser(value_parser!(bool))
+                .help("exports the histogram of the seconds since the latest handshake of the peers of each interface")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("expected_allowed_ips_file")
+                .long("expected_allowed_ips")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXPECTED_ALLOWED_IPS_FILE")
+                .help("JSON file with the allowed ips expected for each public key. If set, the exporter flags the peers whose allowed ips differ")
+        )
+        .arg(
+            Arg::new("include_public_keys_file")
+                .long("include_public_keys_file")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_INCLUDE_PUBLIC_KEYS_FILE")
+                .help("If set, only the peers whose public key is listed in the specified file (one per line) will be exported. The file is reloaded on SIGHUP.")
+        )
+        .arg(
+            Arg::new("exclude_public_keys_file")
+                .long("exclude_public_keys_file")
+                .env("PROMETHEUS_WIREGUARD_EXPORTER_EXCLUDE_PUBLIC_KEYS_FILE")
+                .help("If set, the peers whose public key is listed in the specified file (one per line) will not be exported. The file is reloaded on SIGHUP.")
+        )
+        .arg(
+            Arg::new("stdio")
+                .long("stdio")
+                .value_parser(value_parser!(bool))
+                .help("serves a single request over stdin/stdout and exits (for inetd/xinetd or SSH ForceCommand). If no request is received the metrics are printed as they are")
+                .default_value("false")
+        )
+        .arg(
+            Arg::new("stdin")
+                .long("stdin")
+                .value_parser(value_parser!(bool))
+                .help("reads the wg show all dump output from stdin once, prints the metrics to stdout and exits")
+                .default_value("false")
+                .conflicts_with_all(["stdio", "dump_file"])
+        )
+        .arg(
+            Arg::new("oneshot")
+                .long("oneshot")
+                .value_parser(value_parser!(bool))
+                .help("collects the metrics once, prints them to stdout and exits (for example for the node_exporter textfile collector)")
+                .default_value("false")
+                .conflicts_with_all(["stdio", "stdin"])
+        )
+        .arg(
+            Arg::new("analyze_dump")
+                .long("analyze_dump")
+                .help("Reads a wg show all dump output from the specified file, prints a per-line breakdown of how the exporter parses it and exits. Useful when reporting parsing bugs.")
+        )
+}
+
+// The same label twice in a series is rejected by Prometheus.
+fn check_static_labels(static_labels: &[(String, String)]) -> Result<(), ExporterError> {
+    let mut names = HashSet::new();
+    match static_labels.iter().find(|(name, _)| !names.insert(name)) {
+        Some((name, _)) => Err(ExporterError::DuplicateStaticLabel { name: name.clone() }),
+        None => Ok(()),
+    }
+}
+
+// Without the label the series of two interfaces would collide: the only
+// interface is the one passed with -i, whatever the dump contains.
+fn check_no_interface_label(options: &Options) -> Result<(), ExporterError> {
+    match options.interfaces.as_deref() {
+        Some([_]) => Ok(()),
+        _ if !options.no_interface_label => Ok(()),
+        interfaces => Err(ExporterError::NoInterfaceLabelInterfaces {
+            interfaces: interfaces.unwrap_or_default().to_vec(),
+        }),
+    }
+}
+
+// Runs the exporter as specified on the command line: serves the metrics
+// over HTTP until it is stopped, unless one of the run-once modes is used.
+pub async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let matches = command().get_matches();
+
+    let mut options = Options::from_claps(&matches);
+
+    if let Some(analyze_dump_file) = &options.analyze_dump {
+        let dump = std::fs::read_to_string(analyze_dump_file)
+            .with_context(|| format!("failed to read dump file {}", analyze_dump_file))?;
+        print!("{}", analyze_dump(&dump));
+        return Ok(());
+    }
+
+    if options.verbose {
+        env::set_var(
+            "RUST_LOG",
+            format!("{}=trace,prometheus_exporter_base=trace", crate_name!()),
+        );
+    } else {
+        env::set_var(
+            "RUST_LOG",
+            format!("{}=info,prometheus_exporter_base=info", crate_name!()),
+        );
+    }
+    env_logger::init();
+
+    check_static_labels(&options.static_labels).with_context(|| "invalid --label")?;
+
+    check_no_interface_label(&options).with_context(|| "invalid --no_interface_label")?;
+
+    options
+        .load_interface_options()
+        .with_context(|| "failed to load the interface options file")?;
+    options
+        .load_expected_allowed_ips()
+        .with_context(|| "failed to load the expected allowed ips file")?;
+    options.load_geoip();
+
+    if options.stdin {
+        let mut dump = String::new();
+        std::io::stdin()
+            .read_to_string(&mut dump)
+            .with_context(|| "failed to read the dump from stdin")?;
+        options.stdin_dump = Some(dump);
+        options
+            .load_peer_filter()
+            .with_context(|| "failed to load the public key filter lists")?;
+        return stdio::print_metrics(Arc::new(options)).await;
+    }
+
+    if options.oneshot {
+        options
+            .load_peer_filter()
+            .with_context(|| "failed to load the public key filter lists")?;
+        return stdio::print_metrics(Arc::new(options)).await;
+    }
+
+    if options.stdio {
+        // stdout carries the response so we skip the startup messages
+        options
+            .load_peer_filter()
+            .with_context(|| "failed to load the public key filter lists")?;
+        return stdio::serve_stdio(Arc::new(options)).await;
+    }
+
+    info!(
+        "{} v{} starting...",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+    info!("using options: {:?}", options);
+
+    options
+        .load_peer_filter()
+        .with_context(|| "failed to load the public key filter lists")?;
+    options
+        .load_tls_config()
+        .with_context(|| "failed to load the TLS certificate and key")?;
+    tokio::spawn(reload_on_sighup(options.clone()));
+
+    if options.shutdown_grace_period > 0 {
+        tokio::spawn(drain_on_shutdown(options.clone()));
+    }
+
+    let bind: u16 = *matches.get_one("port").unwrap();
+    let addrs: Vec<std::net::SocketAddr> = matches
+        .get_many::<IpAddr>("addr")
+        .unwrap()
+        .map(|ip| (*ip, bind).into())
+        .collect();
+
+    let scheme = if options.tls_cert_file.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    for addr in addrs.iter() {
+        info!(
+            "starting exporter on {}://{}{}",
+            scheme,
+            addr,
+            http_server::metrics_path(&options)
+        );
+    }
+
+    http_server::run_server(&addrs, Arc::new(options)).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options_default() {
+        // the defaults of Options are the ones of the command line
+        let matches = command().try_get_matches_from([crate_name!()]).unwrap();
+        assert_eq!(
+            format!("{:?}", Options::from_claps(&matches)),
+            format!("{:?}", Options::default())
+        );
+    }
+
+    #[test]
+    fn test_static_labels() {
+        let matches = command()
+            .try_get_matches_from([crate_name!(), "--label", "region=eu \"west\""])
+            .unwrap();
+        assert_eq!(
+            Options::from_claps(&matches).static_labels,
+            vec![("region".to_owned(), "eu \\\"west\\\"".to_owned())]
+        );
+
+        // the names of the labels of the exporter
+        for label in ["interface=wg0", "le=1", "allowed_ip_0=10.0.0.1"] {
+            let e = command()
+                .try_get_matches_from([crate_name!(), "--label", label])
+                .unwrap_err();
+            assert!(e.to_string().contains("already used by the exporter"));
+        }
+
+        let static_labels = vec![
+            ("region".to_owned(), "eu".to_owned()),
+            ("host".to_owned(), "vpn1".to_owned()),
+            ("region".to_owned(), "us".to_owned()),
+        ];
+        assert!(matches!(
+            check_static_labels(&static_labels),
+            Err(ExporterError::DuplicateStaticLabel { name }) if name == "region"
+        ));
+        assert!(check_static_labels(&static_labels[..2]).is_ok());
+    }
+
+    #[test]
+    fn test_check_no_interface_label() {
+        let check = |args: &[&str]| {
+            let matches = command()
+                .try_get_matches_from([crate_name!()].iter().chain(args))
+                .unwrap();
+            check_no_interface_label(&Options::from_claps(&matches))
+        };
+
+        assert!(check(&[]).is_ok());
+        assert!(check(&["--no_interface_label", "true", "-i", "wg0"]).is_ok());
+        // without -i the dump can have any number of interfaces
+        assert!(matches!(
+            check(&["--no_interface_label", "true"]),
+            Err(ExporterError::NoInterfaceLabelInterfaces { interfaces }) if interfaces.is_empty()
+        ));
+        assert!(matches!(
+            check(&["--no_interface_label", "true", "-i", "wg0", "wg1"]),
+            Err(ExporterError::NoInterfaceLabelInterfaces { interfaces }) if interfaces == ["wg0", "wg1"]
+        ));
+    }
+
+    #[test]
+    fn test_check_wg_output() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::{ExitStatus, Output};
+
+        let output = Output {
+            status: ExitStatus::from_raw(0),
+            stdout: b"wg0\tdump".to_vec(),
+            stderr: Vec::new(),
+        };
+        assert_eq!(check_wg_output("wg0", output).unwrap(), "wg0\tdump");
+
+        // exit code 1 (the raw status is the waitpid one)
+        let output = Output {
+            status: ExitStatus::from_raw(1 << 8),
+            stdout: Vec::new(),
+            stderr: b"Unable to access interface: No such device\n".to_vec(),
+        };
+        match check_wg_output("wg9", output) {
+            Err(ExporterError::WgCommandFailed {
+                interface,
+                exit_code,
+                stderr,
+            }) => {
+                assert_eq!(interface, "wg9");
+                assert_eq!(exit_code, Some(1));
+                assert_eq!(stderr, "Unable to access interface: No such device");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_wg_argv() {
+        let options = Options::default();
+        assert_eq!(
+            wg_argv(&["show", "all", "dump"], &options),
+            vec!["wg", "show", "all", "dump"]
+        );
+
+        let options = Options {
+            prepend_sudo: true,
+            wg_path: Some("/run/current-system/sw/bin/wg".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            wg_argv(&["show", "wg0", "dump"], &options),
+            vec![
+                "sudo",
+                "/run/current-system/sw/bin/wg",
+                "show",
+                "wg0",
+                "dump"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_interfaces_to_handle() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let wg_path = std::env::temp_dir().join(format!("interfaces_wg_{}", std::process::id()));
+        std::fs::write(&wg_path, "#!/bin/sh\necho wg0 wg1\n").unwrap();
+        std::fs::set_permissions(&wg_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut options = Options {
+            wg_path: Some(wg_path.to_str().unwrap().to_owned()),
+            enumerate_interfaces: true,
+            ..Default::default()
+        };
+        let all = vec!["all".to_owned()];
+        let enumerated = resolve_interfaces_to_handle(&all, &options).await;
+        // -i is used as is
+        let explicit = resolve_interfaces_to_handle(&["wg2".to_owned()], &options).await;
+        options.wg_path = Some("/nonexistent/wg".to_owned());
+        let failed = resolve_interfaces_to_handle(&all, &options).await;
+        std::fs::remove_file(&wg_path).unwrap();
+
+        assert_eq!(enumerated, vec!["wg0", "wg1"]);
+        assert_eq!(explicit, vec!["wg2"]);
+        assert_eq!(failed, all);
+    }
+
+    #[tokio::test]
+    async fn test_perform_request_stdin_interfaces() {
+        const TEXT: &str = "wg0\t(none)\tL2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\t51820\toff
+wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t10.211.123.112:51820\t10.70.0.2/32\t1\t2\t3\toff
+wg1\t(none)\tGd3Qj2C2Y1J3EhpL7XtAQZOC5D2mtSrF3F0zxx3xiW8=\t51821\toff
+wg1\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t10.211.123.113:51820\t10.70.0.3/32\t1\t2\t3\toff
+";
+
+        let options = Arc::new(Options {
+            interfaces: Some(vec!["wg0".to_owned(), "wg1".to_owned(), "wg2".to_owned()]),
+            stdin_dump: Some(TEXT.to_owned()),
+            ..Default::default()
+        });
+        let request = Request::get("/metrics").body(Body::empty()).unwrap();
+        let metrics = perform_request(request, options).await.unwrap();
+
+        // every interface keeps its own peers, once
+        assert_eq!(
+            metrics
+                .matches("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\"")
+                .count(),
+            1
+        );
+        assert_eq!(
+            metrics
+                .matches("wireguard_sent_bytes_total{interface=\"wg1\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\"")
+                .count(),
+            1
+        );
+        assert!(metrics.contains("wireguard_interface_sent_bytes_total{interface=\"wg1\"} 3\n"));
+        // wg2 is not in the dump
+        assert!(metrics.contains("wireguard_up{interface=\"wg2\"} 0\n"));
+    }
+
+    #[tokio::test]
+    async fn test_run_wg_show_dump_timeout() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let wg_path = std::env::temp_dir().join(format!("hanging_wg_{}", std::process::id()));
+        std::fs::write(&wg_path, "#!/bin/sh\nexec sleep 30\n").unwrap();
+        std::fs::set_permissions(&wg_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let options = Options {
+            wg_path: Some(wg_path.to_str().unwrap().to_owned()),
+            wg_timeout_seconds: 1,
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let result = run_wg_show_dump("wg0", &options).await;
+        std::fs::remove_file(&wg_path).unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(10));
+        match result.map_err(|e| e.downcast::<ExporterError>()) {
+            Err(Ok(e)) => match *e {
+                ExporterError::WgTimeout { interface, timeout } => {
+                    assert_eq!(interface, "wg0");
+                    assert_eq!(timeout, 1);
+                }
+                e => panic!("unexpected error {:?}", e),
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -5,7 +5,7 @@ pub enum FriendlyDescritionParseError {
     #[error("unsupported header")]
     UnsupportedHeader(String),
 
-    #[error("json parse error")]
+    #[error("json parse error: {0}")]
     SerdeJsonError(#[from] serde_json::Error),
 }
 
@@ -17,8 +17,99 @@ pub enum PeerEntryParseError {
     #[error("AllowedIPs entry not found in lines: {:?}", lines)]
     AllowedIPsEntryNotFound { lines: Vec<String> },
 
-    #[error("Friendly description parse error")]
-    FriendlyDescritionParseError(#[from] FriendlyDescritionParseError),
+    #[error(
+        "cannot read PublicKeyFile {} referenced by {}: {}",
+        path,
+        config_file.as_deref().unwrap_or("the config"),
+        e
+    )]
+    PublicKeyFileReadError {
+        // None when the config was not read from a file
+        config_file: Option<String>,
+        path: String,
+        e: std::io::Error,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum MaintenanceWindowParseError {
+    #[error("maintenance window must be in the HH:MM-HH:MM format, got {}", window)]
+    InvalidFormat { window: String },
+
+    #[error("invalid time {} in maintenance window: {}", time, e)]
+    InvalidTime {
+        time: String,
+        e: chrono::format::ParseError,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum SiteExtractorParseError {
+    #[error(
+        "site extraction must be in the octet:N format (N between 1 and 4), got {}",
+        s
+    )]
+    InvalidFormat { s: String },
+}
+
+#[derive(Debug, Error)]
+pub enum InterfaceOptionsParseError {
+    #[error("the interface options must be a JSON object keyed by interface name")]
+    NotAnObject,
+
+    #[error("unsupported field {} for interface {}", field, interface)]
+    UnsupportedField { interface: String, field: String },
+
+    #[error("invalid value of field {} for interface {}", field, interface)]
+    InvalidValue { interface: String, field: String },
+
+    #[error("json parse error")]
+    SerdeJsonError(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum ExpectedAllowedIpsParseError {
+    #[error("the expected allowed ips must be a JSON object keyed by public key")]
+    NotAnObject,
+
+    #[error("invalid expected allowed ips of the peer {}", public_key)]
+    InvalidValue { public_key: String },
+
+    #[error("json parse error")]
+    SerdeJsonError(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum TlsConfigError {
+    #[error("cannot read {}: {}", file, e)]
+    IO { file: String, e: std::io::Error },
+
+    #[error("no PEM certificate found in {}", file)]
+    NoCertificate { file: String },
+
+    #[error("invalid certificate in {}", file)]
+    InvalidCertificate { file: String },
+
+    #[error("no PEM private key found in {}", file)]
+    NoPrivateKey { file: String },
+
+    #[error("rustls error: {0}")]
+    Rustls(#[from] tokio_rustls::rustls::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum NamesFileParseError {
+    #[error("unsupported names file {}, only .json files are supported", file)]
+    UnsupportedExtension { file: String },
+
+    #[error("the names file must be a JSON object keyed by public key")]
+    NotAnObject,
+
+    #[error("the name of the peer {} must be a string", public_key)]
+    InvalidName { public_key: String },
+
+    #[error("json parse error")]
+    SerdeJsonError(#[from] serde_json::Error),
 }
 
 #[derive(Debug, Error)]
@@ -50,6 +141,66 @@ pub enum ExporterError {
 
     #[error("PeerEntry parse error: {}", e)]
     PeerEntryParseError { e: PeerEntryParseError },
+
+    #[error("friendly_name {} is shared by the peers {:?}", name, public_keys)]
+    DuplicateFriendlyName {
+        name: String,
+        public_keys: Vec<String>,
+    },
+
+    #[error("remote agent {} returned {}", url, status)]
+    RemoteAgentStatus {
+        url: String,
+        status: http::StatusCode,
+    },
+
+    #[error("remote agent {} did not answer within {} seconds", url, timeout)]
+    RemoteAgentTimeout { url: String, timeout: u64 },
+
+    #[error("wg show {} dump did not finish within {} seconds", interface, timeout)]
+    WgTimeout { interface: String, timeout: u64 },
+
+    #[error("wg show interfaces did not finish within {} seconds", timeout)]
+    WgShowInterfacesTimeout { timeout: u64 },
+
+    #[error("wg show interfaces failed (exit code {:?}): {}", exit_code, stderr)]
+    WgShowInterfacesFailed {
+        exit_code: Option<i32>,
+        stderr: String,
+    },
+
+    #[error(
+        "--no_interface_label requires a single interface passed with -i, found {:?}",
+        interfaces
+    )]
+    NoInterfaceLabelInterfaces { interfaces: Vec<String> },
+
+    #[error("--label {} is specified more than once", name)]
+    DuplicateStaticLabel { name: String },
+
+    #[error("cannot listen on {}: {}", addr, e)]
+    Bind {
+        addr: std::net::SocketAddr,
+        e: std::io::Error,
+    },
+
+    #[error("malformed wg dump line {}: {} columns", line_no, columns)]
+    MalformedDumpLine { line_no: usize, columns: usize },
+
+    #[error("interface {} is not in the dump read from stdin", interface)]
+    InterfaceNotInStdinDump { interface: String },
+
+    #[error(
+        "wg show {} dump failed (exit code {:?}): {}",
+        interface,
+        exit_code,
+        stderr
+    )]
+    WgCommandFailed {
+        interface: String,
+        exit_code: Option<i32>,
+        stderr: String,
+    },
 }
 
 impl From<PeerEntryParseError> for ExporterError {
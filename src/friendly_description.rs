@@ -14,7 +14,7 @@ impl<'a> TryFrom<(&'a str, &'a str)> for FriendlyDescription<'a> {
 
     fn try_from((header_name, value): (&'a str, &'a str)) -> Result<Self, Self::Error> {
         Ok(match header_name {
-            "friendly_name" => FriendlyDescription::Name(value.replace('\"', "\\\"").into()),
+            "friendly_name" => FriendlyDescription::Name(value.into()),
             "friendly_json" => {
                 let ret: HashMap<&str, serde_json::Value> = serde_json::from_str(value)?;
                 FriendlyDescription::Json(ret)
@@ -42,10 +42,15 @@ mod tests {
     }
 
     #[test]
-    fn test_escape_friendly_name() {
-        const TO_ESCAPE: &str = r#"man this is a quote ""#;
-        const ESCAPED: &str = r#"man this is a quote \""#;
-        let fd: FriendlyDescription = ("friendly_name", TO_ESCAPE).try_into().unwrap();
-        assert_eq!(fd, FriendlyDescription::Name(ESCAPED.into()));
+    fn test_friendly_name_kept_as_is() {
+        // escaped only when rendered as a label
+        for name in [
+            r#"man this is a quote ""#,
+            r#"C:\vpn\laptop"#,
+            "first\nsecond",
+        ] {
+            let fd: FriendlyDescription = ("friendly_name", name).try_into().unwrap();
+            assert_eq!(fd, FriendlyDescription::Name(name.into()));
+        }
     }
 }
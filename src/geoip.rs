@@ -0,0 +1,119 @@
+use log::{debug, warn};
+use maxminddb::{geoip2, Reader};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+// the cache is dropped when it grows past this, so the ips of the roaming
+// peers do not pile up
+const MAX_CACHED_IPS: usize = 65536;
+
+// Where a remote ip is, as far as the databases know.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GeoIpInfo {
+    // ISO 3166-1 country code, for example IT
+    pub country: Option<String>,
+    // autonomous system number, for example 3269
+    pub asn: Option<String>,
+}
+
+// The MaxMind databases of --geoip_db, shared between requests so every
+// remote ip is looked up once. Both the country and the ASN are looked up
+// in every database so they can come from the same database or from two
+// (for example GeoLite2-Country and GeoLite2-ASN).
+#[derive(Debug, Default)]
+pub struct GeoIp {
+    readers: Vec<Reader<Vec<u8>>>,
+    cache: HashMap<String, GeoIpInfo>,
+}
+
+impl GeoIp {
+    // A database that cannot be opened is logged and skipped, the scrape
+    // goes on without its labels.
+    pub fn open(files: &[String]) -> GeoIp {
+        let readers = files
+            .iter()
+            .filter_map(|file| match Reader::open_readfile(file) {
+                Ok(reader) => Some(reader),
+                Err(e) => {
+                    warn!("cannot open the GeoIP database {}: {}", file, e);
+                    None
+                }
+            })
+            .collect();
+
+        GeoIp {
+            readers,
+            cache: HashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.readers.is_empty()
+    }
+
+    pub fn lookup(&mut self, remote_ip: &str) -> &GeoIpInfo {
+        if self.cache.len() >= MAX_CACHED_IPS && !self.cache.contains_key(remote_ip) {
+            self.cache.clear();
+        }
+
+        let readers = &self.readers;
+        self.cache.entry(remote_ip.to_owned()).or_insert_with(|| {
+            match remote_ip.parse::<IpAddr>() {
+                Ok(addr) => lookup_readers(readers, addr),
+                Err(_) => GeoIpInfo::default(),
+            }
+        })
+    }
+}
+
+fn lookup_readers(readers: &[Reader<Vec<u8>>], addr: IpAddr) -> GeoIpInfo {
+    let mut info = GeoIpInfo::default();
+
+    for reader in readers {
+        if info.country.is_none() {
+            match reader.lookup::<geoip2::Country>(addr) {
+                Ok(country) => {
+                    info.country = country
+                        .country
+                        .and_then(|country| country.iso_code)
+                        .map(|iso_code| iso_code.to_owned())
+                }
+                Err(e) => debug!("no country for {}: {}", addr, e),
+            }
+        }
+        if info.asn.is_none() {
+            match reader.lookup::<geoip2::Asn>(addr) {
+                Ok(asn) => info.asn = asn.autonomous_system_number.map(|asn| asn.to_string()),
+                Err(e) => debug!("no ASN for {}: {}", addr, e),
+            }
+        }
+    }
+
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_database() {
+        let mut geoip = GeoIp::open(&["/nonexistent/GeoLite2-Country.mmdb".to_owned()]);
+        assert!(!geoip.is_enabled());
+        assert_eq!(geoip.lookup("192.0.2.1"), &GeoIpInfo::default());
+    }
+
+    #[test]
+    fn test_lookup_cache() {
+        let mut geoip = GeoIp::default();
+        let info = GeoIpInfo {
+            country: Some("IT".to_owned()),
+            asn: Some("3269".to_owned()),
+        };
+        geoip.cache.insert("192.0.2.1".to_owned(), info.clone());
+
+        assert_eq!(geoip.lookup("192.0.2.1"), &info);
+        assert_eq!(geoip.lookup("not an ip"), &GeoIpInfo::default());
+        assert_eq!(geoip.cache.len(), 2);
+    }
+}
@@ -0,0 +1,518 @@
+use crate::exporter::perform_request;
+use crate::exporter_error::ExporterError;
+use crate::options::Options;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::future::try_join_all;
+use hyper::header::{
+    HeaderMap, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE, VARY,
+    WWW_AUTHENTICATE,
+};
+use hyper::server::conn::{AddrIncoming, Http};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{debug, info, trace, warn};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+// the default of --metrics_path
+pub(crate) const METRICS_PATH: &str = "/metrics";
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+// with --openmetrics
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+// the same data of the metrics path, as JSON, is at the metrics path
+// followed by this suffix
+const JSON_SUFFIX: &str = ".json";
+// for the liveness probes, they always answer ok
+const LIVENESS_PATHS: [&str; 2] = ["/", "/health"];
+// for the readiness probe, it answers ok once wg has been collected
+const READINESS_PATH: &str = "/ready";
+
+pub(crate) fn metrics_path(options: &Options) -> &str {
+    options.metrics_path.as_deref().unwrap_or(METRICS_PATH)
+}
+
+pub(crate) fn metrics_content_type(options: &Options) -> &'static str {
+    if options.openmetrics {
+        OPENMETRICS_CONTENT_TYPE
+    } else {
+        PROMETHEUS_CONTENT_TYPE
+    }
+}
+
+// With --external_path_prefix the paths are served both as the reverse
+// proxy forwards them when it strips the prefix and when it does not.
+fn strip_external_path_prefix<'a>(path: &'a str, options: &Options) -> &'a str {
+    match options
+        .external_path_prefix
+        .as_deref()
+        .and_then(|prefix| path.strip_prefix(prefix))
+    {
+        Some("") => "/",
+        Some(stripped) if stripped.starts_with('/') => stripped,
+        _ => path,
+    }
+}
+
+pub(crate) fn is_metrics_json_path(path: &str, options: &Options) -> bool {
+    strip_external_path_prefix(path, options).strip_suffix(JSON_SUFFIX)
+        == Some(metrics_path(options))
+}
+
+// Whether the client accepts a gzip response, that is Accept-Encoding lists
+// gzip (or *) without q=0.
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';').map(|param| param.trim());
+            let name = params.next().unwrap_or_default();
+            (name.eq_ignore_ascii_case("gzip") || name == "*")
+                && !params.any(|param| {
+                    param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0)
+                })
+        })
+}
+
+fn gzip(body: &str) -> Result<Vec<u8>, std::io::Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body.as_bytes())?;
+    encoder.finish()
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn serve_request(
+    req: Request<Body>,
+    options: Arc<Options>,
+) -> Result<Response<Body>, hyper::Error> {
+    trace!(
+        "serve_request req.uri() == {}, req.method() == {}",
+        req.uri().path(),
+        req.method()
+    );
+
+    let path = strip_external_path_prefix(req.uri().path(), &options);
+    let content_type = if path == metrics_path(&options) {
+        metrics_content_type(&options)
+    } else if is_metrics_json_path(path, &options) {
+        "application/json"
+    } else if LIVENESS_PATHS.contains(&path) {
+        return Ok(Response::new(Body::from("ok")));
+    } else if path == READINESS_PATH {
+        return Ok(serve_ready(&options));
+    } else {
+        return Ok(empty_response(StatusCode::NOT_FOUND));
+    };
+    if !options
+        .authorization
+        .is_authorized(req.headers().get(AUTHORIZATION))
+    {
+        return Ok(Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(WWW_AUTHENTICATE, options.authorization.www_authenticate())
+            .body(Body::empty())
+            .unwrap());
+    }
+    if req.method() != Method::GET {
+        return Ok(empty_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    let accepts_gzip = accepts_gzip(req.headers());
+
+    Ok(match perform_request(req, options).await {
+        Ok(body) if accepts_gzip => match gzip(&body) {
+            Ok(body) => Response::builder()
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, content_type)
+                .header(CONTENT_ENCODING, "gzip")
+                .header(VARY, "Accept-Encoding")
+                .body(Body::from(body))
+                .unwrap(),
+            Err(e) => {
+                warn!("cannot gzip the response: {}", e);
+                empty_response(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        },
+        Ok(body) => Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, content_type)
+            .header(VARY, "Accept-Encoding")
+            .body(Body::from(body))
+            .unwrap(),
+        Err(e) => {
+            warn!("internal server error == {:?}", e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(e.to_string()))
+                .unwrap()
+        }
+    })
+}
+
+// Ready once a scrape has collected wg successfully and until the shutdown
+// starts. The probe only reads the flags: it never runs wg itself, so it
+// costs nothing and does not count as a scrape.
+fn serve_ready(options: &Options) -> Response<Body> {
+    if options.collected.load(Ordering::SeqCst) && !options.draining.load(Ordering::SeqCst) {
+        Response::new(Body::from("ok"))
+    } else {
+        empty_response(StatusCode::SERVICE_UNAVAILABLE)
+    }
+}
+
+// With more than one address the IPv6 ones do not accept IPv4 connections,
+// so :: and 0.0.0.0 can be listened on together.
+fn bind(addr: SocketAddr, only_v6: bool) -> Result<TcpListener, std::io::Error> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() && only_v6 {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+// Every address is bound before serving any of them, so the exporter does
+// not start at all if one of them is not available.
+pub(crate) async fn run_server(
+    addrs: &[SocketAddr],
+    options: Arc<Options>,
+) -> Result<(), ExporterError> {
+    let listeners = addrs
+        .iter()
+        .map(|addr| {
+            bind(*addr, addrs.len() > 1).map_err(|e| ExporterError::Bind { addr: *addr, e })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let tls = options.tls_config.read().unwrap().is_some();
+
+    try_join_all(listeners.into_iter().map(|listener| {
+        let options = options.clone();
+        async move {
+            if tls {
+                serve_tls(listener, options).await
+            } else {
+                serve(listener, options).await
+            }
+        }
+    }))
+    .await?;
+
+    Ok(())
+}
+
+async fn serve(listener: TcpListener, options: Arc<Options>) -> Result<(), ExporterError> {
+    info!(
+        "Listening on http://{}{}",
+        listener.local_addr()?,
+        metrics_path(&options)
+    );
+
+    let make_service = make_service_fn(move |_| {
+        let options = options.clone();
+        async move { Ok::<_, hyper::Error>(service_fn(move |req| serve_request(req, options.clone()))) }
+    });
+
+    Ok(Server::builder(AddrIncoming::from_listener(listener)?)
+        .serve(make_service)
+        .await?)
+}
+
+async fn serve_tls(listener: TcpListener, options: Arc<Options>) -> Result<(), ExporterError> {
+    info!(
+        "Listening on https://{}{}",
+        listener.local_addr()?,
+        metrics_path(&options)
+    );
+
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // the error (typically too many open files) is likely to
+                // come back at once, so do not spin on it
+                warn!("failed to accept a connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+
+        // read on every connection since the certificate can be reloaded on SIGHUP
+        let server_config = match options.tls_config.read().unwrap().as_ref() {
+            Some(tls_config) => tls_config.server_config.clone(),
+            None => continue,
+        };
+        let options = options.clone();
+
+        tokio::spawn(async move {
+            let stream = match TlsAcceptor::from(server_config).accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("TLS handshake with {} failed: {}", remote_addr, e);
+                    return;
+                }
+            };
+
+            let service = service_fn(move |req| serve_request(req, options.clone()));
+            if let Err(e) = Http::new().serve_connection(stream, service).await {
+                debug!("connection with {} failed: {}", remote_addr, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Authorization;
+
+    #[tokio::test]
+    async fn test_run_server_bind_error() {
+        let listener = bind("127.0.0.1:0".parse().unwrap(), false).unwrap();
+        let taken = listener.local_addr().unwrap();
+
+        let free = bind("127.0.0.1:0".parse().unwrap(), false)
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        match run_server(&[free, taken], Arc::new(Options::default())).await {
+            Err(ExporterError::Bind { addr, .. }) => assert_eq!(addr, taken),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_request_unknown_path() {
+        let req = Request::builder()
+            .uri("/other")
+            .body(Body::empty())
+            .unwrap();
+        let response = serve_request(req, Arc::new(Options::default()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_serve_request_metrics_path() {
+        let options = Arc::new(Options {
+            metrics_path: Some("/wireguard/metrics".to_owned()),
+            authorization: Authorization::new(Some("s3cr3t"), None),
+            ..Default::default()
+        });
+        assert!(is_metrics_json_path("/wireguard/metrics.json", &options));
+        assert!(!is_metrics_json_path("/metrics.json", &options));
+
+        let req = Request::builder()
+            .uri("/metrics")
+            .body(Body::empty())
+            .unwrap();
+        let response = serve_request(req, options.clone()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let req = Request::builder()
+            .uri("/wireguard/metrics")
+            .body(Body::empty())
+            .unwrap();
+        let response = serve_request(req, options.clone()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // the liveness probes do not authenticate
+        for path in LIVENESS_PATHS {
+            let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+            let response = serve_request(req, options.clone()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(&body[..], b"ok");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_request_external_path_prefix() {
+        let options = Arc::new(Options {
+            external_path_prefix: Some("/wg-exporter".to_owned()),
+            authorization: Authorization::new(Some("s3cr3t"), None),
+            ..Default::default()
+        });
+        assert!(is_metrics_json_path("/wg-exporter/metrics.json", &options));
+        assert!(is_metrics_json_path("/metrics.json", &options));
+        assert!(!is_metrics_json_path("/wg-exportermetrics.json", &options));
+
+        // with and without the prefix
+        for (path, status) in [
+            ("/wg-exporter/metrics", StatusCode::UNAUTHORIZED),
+            ("/metrics", StatusCode::UNAUTHORIZED),
+            ("/wg-exporter", StatusCode::OK),
+            ("/wg-exporter/health", StatusCode::OK),
+            ("/health", StatusCode::OK),
+            ("/wg-exporter/other", StatusCode::NOT_FOUND),
+            ("/wg-exportermetrics", StatusCode::NOT_FOUND),
+        ] {
+            let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+            let response = serve_request(req, options.clone()).await.unwrap();
+            assert_eq!(response.status(), status, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_accepts_gzip() {
+        let accepts = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT_ENCODING, value.parse().unwrap());
+            accepts_gzip(&headers)
+        };
+
+        assert!(!accepts_gzip(&HeaderMap::new()));
+        assert!(accepts("gzip"));
+        assert!(accepts("identity, GZIP;q=0.5"));
+        assert!(accepts("*"));
+        assert!(!accepts("deflate, br"));
+        assert!(!accepts("gzip;q=0"));
+    }
+
+    #[tokio::test]
+    async fn test_serve_request_gzip() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let dump_file = std::env::temp_dir().join(format!("gzip_dump_{}", std::process::id()));
+        std::fs::write(
+            &dump_file,
+            "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t3\t2\toff\n",
+        )
+        .unwrap();
+        let options = Arc::new(Options {
+            dump_file: Some(dump_file.to_str().unwrap().to_owned()),
+            ..Default::default()
+        });
+
+        let req = Request::builder()
+            .uri(METRICS_PATH)
+            .body(Body::empty())
+            .unwrap();
+        let response = serve_request(req, options.clone()).await.unwrap();
+        assert_eq!(response.headers().get(CONTENT_ENCODING), None);
+        let plain = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        let req = Request::builder()
+            .uri(METRICS_PATH)
+            .header(ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let response = serve_request(req, options).await.unwrap();
+        std::fs::remove_file(&dump_file).unwrap();
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+        let compressed = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let mut decompressed = Vec::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+
+        assert!(String::from_utf8_lossy(&plain).contains("wireguard_sent_bytes_total"));
+        assert_eq!(decompressed, plain);
+    }
+
+    #[tokio::test]
+    async fn test_serve_request_ready() {
+        let ready = |options: Arc<Options>| async move {
+            let req = Request::builder()
+                .uri(READINESS_PATH)
+                .body(Body::empty())
+                .unwrap();
+            serve_request(req, options).await.unwrap().status()
+        };
+
+        let dump_file = std::env::temp_dir().join(format!("ready_dump_{}", std::process::id()));
+        std::fs::write(
+            &dump_file,
+            "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t3\t2\toff\n",
+        )
+        .unwrap();
+        let options = Arc::new(Options {
+            dump_file: Some(dump_file.to_str().unwrap().to_owned()),
+            ..Default::default()
+        });
+
+        // the probe does not collect by itself
+        assert_eq!(
+            ready(options.clone()).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert!(!options.collected.load(Ordering::SeqCst));
+
+        // a scrape does
+        let req = Request::builder()
+            .uri(METRICS_PATH)
+            .body(Body::empty())
+            .unwrap();
+        serve_request(req, options.clone()).await.unwrap();
+        std::fs::remove_file(&dump_file).unwrap();
+        assert_eq!(ready(options.clone()).await, StatusCode::OK);
+
+        options.draining.store(true, Ordering::SeqCst);
+        assert_eq!(ready(options).await, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_serve_request_unauthorized() {
+        let options = Arc::new(Options {
+            authorization: Authorization::new(Some("s3cr3t"), None),
+            ..Default::default()
+        });
+
+        let req = Request::builder()
+            .uri(METRICS_PATH)
+            .body(Body::empty())
+            .unwrap();
+        let response = serve_request(req, options.clone()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[WWW_AUTHENTICATE], "Bearer");
+
+        let req = Request::builder()
+            .uri(METRICS_PATH)
+            .header(AUTHORIZATION, "Bearer wrong")
+            .body(Body::empty())
+            .unwrap();
+        let response = serve_request(req, options.clone()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // authorized, the other checks follow
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(METRICS_PATH)
+            .header(AUTHORIZATION, "Bearer s3cr3t")
+            .body(Body::empty())
+            .unwrap();
+        let response = serve_request(req, options).await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn test_serve_request_wrong_method() {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/metrics.json")
+            .body(Body::empty())
+            .unwrap();
+        let response = serve_request(req, Arc::new(Options::default()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+}
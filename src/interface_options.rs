@@ -0,0 +1,131 @@
+use crate::exporter_error::InterfaceOptionsParseError;
+use log::debug;
+use std::collections::HashMap;
+
+// Per-interface overrides of the global options. Every field left
+// unspecified falls back to the corresponding global setting.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InterfaceOptions {
+    pub separate_allowed_ips: Option<bool>,
+    pub export_remote_ip_and_port: Option<bool>,
+    // exported in the interface label in place of the interface name
+    pub alias: Option<String>,
+}
+
+pub type InterfaceOptionsMap = HashMap<String, InterfaceOptions>;
+
+fn bool_field(
+    interface: &str,
+    json: &serde_json::Map<String, serde_json::Value>,
+    field: &str,
+) -> Result<Option<bool>, InterfaceOptionsParseError> {
+    match json.get(field) {
+        None => Ok(None),
+        Some(serde_json::Value::Bool(b)) => Ok(Some(*b)),
+        Some(_) => Err(InterfaceOptionsParseError::InvalidValue {
+            interface: interface.to_owned(),
+            field: field.to_owned(),
+        }),
+    }
+}
+
+// The file is a JSON object keyed by interface name, for example:
+// { "wg0": { "separate_allowed_ips": true, "alias": "office" } }
+pub fn interface_options_try_from(
+    txt: &str,
+) -> Result<InterfaceOptionsMap, InterfaceOptionsParseError> {
+    let json: serde_json::Value = serde_json::from_str(txt)?;
+    let interfaces = json
+        .as_object()
+        .ok_or(InterfaceOptionsParseError::NotAnObject)?;
+
+    let mut interface_options_map = InterfaceOptionsMap::new();
+    for (interface, value) in interfaces {
+        let json = value
+            .as_object()
+            .ok_or_else(|| InterfaceOptionsParseError::InvalidValue {
+                interface: interface.to_owned(),
+                field: interface.to_owned(),
+            })?;
+
+        if let Some(field) = json.keys().find(|field| {
+            !["separate_allowed_ips", "export_remote_ip_and_port", "alias"]
+                .contains(&field.as_str())
+        }) {
+            return Err(InterfaceOptionsParseError::UnsupportedField {
+                interface: interface.to_owned(),
+                field: field.to_owned(),
+            });
+        }
+
+        let alias = match json.get("alias") {
+            None => None,
+            Some(serde_json::Value::String(alias)) => Some(alias.to_owned()),
+            Some(_) => {
+                return Err(InterfaceOptionsParseError::InvalidValue {
+                    interface: interface.to_owned(),
+                    field: "alias".to_owned(),
+                })
+            }
+        };
+
+        interface_options_map.insert(
+            interface.to_owned(),
+            InterfaceOptions {
+                separate_allowed_ips: bool_field(interface, json, "separate_allowed_ips")?,
+                export_remote_ip_and_port: bool_field(
+                    interface,
+                    json,
+                    "export_remote_ip_and_port",
+                )?,
+                alias,
+            },
+        );
+    }
+
+    debug!(
+        "interface_options_try_from returning {:?}",
+        interface_options_map
+    );
+    Ok(interface_options_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = r#"{
+    "wg0": { "separate_allowed_ips": true, "alias": "office" },
+    "wg1": { "export_remote_ip_and_port": false }
+}"#;
+
+    #[test]
+    fn test_parse() {
+        let iom = interface_options_try_from(TEXT).unwrap();
+        assert_eq!(iom.len(), 2);
+        assert_eq!(
+            iom["wg0"],
+            InterfaceOptions {
+                separate_allowed_ips: Some(true),
+                export_remote_ip_and_port: None,
+                alias: Some("office".to_owned()),
+            }
+        );
+        assert_eq!(
+            iom["wg1"],
+            InterfaceOptions {
+                separate_allowed_ips: None,
+                export_remote_ip_and_port: Some(false),
+                alias: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(interface_options_try_from("[]").is_err());
+        assert!(interface_options_try_from(r#"{"wg0": {"alias": 1}}"#).is_err());
+        assert!(interface_options_try_from(r#"{"wg0": {"separate_allowed_ips": "yes"}}"#).is_err());
+        assert!(interface_options_try_from(r#"{"wg0": {"prefix": "x"}}"#).is_err());
+    }
+}
@@ -0,0 +1,43 @@
+//! The parsing and the rendering of the exporter, usable without running
+//! it. For example:
+//!
+//! ```
+//! use prometheus_wireguard_exporter::{Options, WireGuard};
+//! use std::convert::TryFrom;
+//!
+//! let dump = "wg0\tprivate_key\tpublic_key\t51820\toff\n\
+//!             wg0\tpeer_public_key\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff\n";
+//! let wg = WireGuard::try_from(dump).unwrap();
+//! let metrics = wg.render_with_names(None, &Options::default());
+//! assert!(metrics.contains("wireguard_sent_bytes_total"));
+//! ```
+//!
+//! The binary is a thin wrapper around [`run`], which runs `wg` and serves
+//! the rendered metrics over HTTP.
+
+pub(crate) mod auth;
+pub(crate) mod dump_analysis;
+pub(crate) mod expected_allowed_ips;
+pub(crate) mod exporter;
+pub(crate) mod exporter_error;
+pub(crate) mod friendly_description;
+pub(crate) mod geoip;
+pub(crate) mod http_server;
+pub(crate) mod interface_options;
+pub(crate) mod maintenance_window;
+pub(crate) mod options;
+pub(crate) mod peer_filter;
+pub(crate) mod remote_host_cache;
+pub(crate) mod scrape_state;
+pub(crate) mod site_extractor;
+pub(crate) mod stdio;
+pub(crate) mod tls;
+pub(crate) mod wireguard;
+pub(crate) mod wireguard_config;
+
+pub use exporter::run;
+pub use exporter_error::ExporterError;
+pub use friendly_description::FriendlyDescription;
+pub use options::Options;
+pub use wireguard::{Endpoint, LocalEndpoint, RemoteEndpoint, WireGuard};
+pub use wireguard_config::{peer_entry_hashmap_try_from, PeerEntry, PeerEntryHashMap};
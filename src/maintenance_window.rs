@@ -0,0 +1,94 @@
+use crate::exporter_error::MaintenanceWindowParseError;
+use chrono::NaiveTime;
+use std::str::FromStr;
+
+// A daily time window (in the host timezone) during which the exporter
+// flags the maintenance as active. The start is inclusive, the end
+// exclusive. If the end comes before the start the window spans midnight
+// (for example 23:00-01:00).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+fn parse_time(s: &str) -> Result<NaiveTime, MaintenanceWindowParseError> {
+    NaiveTime::parse_from_str(s.trim(), "%H:%M").map_err(|e| {
+        MaintenanceWindowParseError::InvalidTime {
+            time: s.to_owned(),
+            e,
+        }
+    })
+}
+
+impl FromStr for MaintenanceWindow {
+    type Err = MaintenanceWindowParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) =
+            s.split_once('-')
+                .ok_or_else(|| MaintenanceWindowParseError::InvalidFormat {
+                    window: s.to_owned(),
+                })?;
+
+        Ok(MaintenanceWindow {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+}
+
+impl MaintenanceWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            // the window spans midnight
+            time >= self.start || time < self.end
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.contains(chrono::Local::now().time())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(h: u32, m: u32, s: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, s).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let mw: MaintenanceWindow = "02:00-04:30".parse().unwrap();
+        assert_eq!(mw.start, time(2, 0, 0));
+        assert_eq!(mw.end, time(4, 30, 0));
+
+        assert!("02:00".parse::<MaintenanceWindow>().is_err());
+        assert!("02:00-25:00".parse::<MaintenanceWindow>().is_err());
+        assert!("two-four".parse::<MaintenanceWindow>().is_err());
+    }
+
+    #[test]
+    fn test_contains_boundaries() {
+        let mw: MaintenanceWindow = "02:00-04:00".parse().unwrap();
+        assert!(!mw.contains(time(1, 59, 59)));
+        assert!(mw.contains(time(2, 0, 0)));
+        assert!(mw.contains(time(3, 59, 59)));
+        assert!(!mw.contains(time(4, 0, 0)));
+    }
+
+    #[test]
+    fn test_contains_across_midnight() {
+        let mw: MaintenanceWindow = "23:00-01:00".parse().unwrap();
+        assert!(!mw.contains(time(22, 59, 59)));
+        assert!(mw.contains(time(23, 0, 0)));
+        assert!(mw.contains(time(0, 0, 0)));
+        assert!(mw.contains(time(0, 59, 59)));
+        assert!(!mw.contains(time(1, 0, 0)));
+        assert!(!mw.contains(time(12, 0, 0)));
+    }
+}
@@ -1,18 +1,250 @@
+use crate::auth::Authorization;
+use crate::expected_allowed_ips::{expected_allowed_ips_try_from, ExpectedAllowedIpsMap};
+use crate::exporter_error::TlsConfigError;
+use crate::geoip::GeoIp;
+use crate::http_server::METRICS_PATH;
+use crate::interface_options::{interface_options_try_from, InterfaceOptions, InterfaceOptionsMap};
+use crate::maintenance_window::MaintenanceWindow;
+use crate::peer_filter::PeerFilter;
+use crate::remote_host_cache::RemoteHostCache;
+use crate::scrape_state::{DumpCache, ScrapeState};
+use crate::site_extractor::SiteExtractor;
+use crate::tls::TlsConfig;
+use crate::wireguard::DuplicateNameAction;
 use clap::parser::ValuesRef;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::{Arc, Mutex, RwLock};
 
 #[derive(Debug, Clone)]
-pub(crate) struct Options {
+pub struct Options {
     pub verbose: bool,
     pub prepend_sudo: bool,
     pub separate_allowed_ips: bool,
     pub extract_names_config_files: Option<Vec<String>>,
     pub interfaces: Option<Vec<String>>,
+    pub exclude_interfaces: Option<Vec<String>>,
+    pub enumerate_interfaces: bool,
+    pub no_interface_label: bool,
     pub export_remote_ip_and_port: bool,
+    pub endpoint_label: bool,
     pub export_latest_handshake_delay: bool,
+    pub analyze_dump: Option<String>,
+    pub skip_unsupported_json: bool,
+    pub wg_concurrency: u16,
+    pub export_scrape_mode: bool,
+    pub(crate) maintenance_window: Option<MaintenanceWindow>,
+    pub export_counter_resets: bool,
+    pub export_bytes_per_second: bool,
+    pub export_configured_allowed_ips: bool,
+    pub stdio: bool,
+    pub stdin: bool,
+    pub oneshot: bool,
+    // the dump read from stdin with --stdin, used instead of running wg
+    pub(crate) stdin_dump: Option<String>,
+    pub(crate) site_from_allowed_ip: Option<SiteExtractor>,
+    pub export_handshake_label: bool,
+    pub export_unique_remote_ips: bool,
+    pub export_interfaces_total: bool,
+    pub no_private_key_readout: bool,
+    pub interface_options_file: Option<String>,
+    pub(crate) interface_options: InterfaceOptionsMap,
+    pub shutdown_grace_period: u64,
+    // None is the standard tab separator
+    pub field_separator: Option<char>,
+    pub export_name_changes: bool,
+    pub handshake_timeout_seconds: u64,
+    // the interface=seconds values of --handshake_timeout_seconds
+    pub interface_handshake_timeout_seconds: HashMap<String, u64>,
+    pub connected_only: bool,
+    pub skip_never_connected: bool,
+    // whether the peers skipped by skip_never_connected are in wireguard_peers_total
+    pub count_never_connected: bool,
+    // 0 is unlimited
+    pub max_peers_per_interface: usize,
+    pub export_concurrent_scrapes: bool,
+    // number of scrapes in progress
+    pub(crate) concurrent_scrapes: Arc<AtomicUsize>,
+    pub export_scrape_duration: bool,
+    pub dump_file: Option<String>,
+    pub canonical_allowed_ips: bool,
+    pub export_first_seen: bool,
+    pub wg_path: Option<String>,
+    // 0 is no timeout
+    pub wg_timeout_seconds: u64,
+    pub name_comma_replacement: Option<String>,
+    pub skip_malformed_lines: bool,
+    pub export_dump_line_counts: bool,
+    pub export_persistent_keepalive: bool,
+    pub export_preshared_key_presence: bool,
+    pub export_allowed_ips_count: bool,
+    pub export_peer_connected: bool,
+    pub export_listen_port_and_fwmark: bool,
+    pub export_unnamed_peers: bool,
+    pub export_configured_peers: bool,
+    pub(crate) on_duplicate_name: DuplicateNameAction,
+    pub names_file: Option<String>,
+    pub hash_public_keys: bool,
+    pub remote_agent_urls: Option<Vec<String>>,
+    pub export_peer_info: bool,
+    pub export_server_public_key: bool,
+    pub export_avg_bytes_per_peer: bool,
+    pub export_handshake_age_histogram: bool,
+    pub export_peers_by_connection_age: bool,
+    pub connection_age_buckets: Vec<u64>,
+    pub export_peers_by_family: bool,
+    pub validate_public_keys: bool,
+    pub expected_allowed_ips_file: Option<String>,
+    pub(crate) expected_allowed_ips: ExpectedAllowedIpsMap,
+    pub remote_agent_timeout_seconds: u64,
+    // set when the shutdown signal is received
+    pub(crate) draining: Arc<AtomicBool>,
+    // set once wg (or a remote agent) has been collected successfully
+    pub(crate) collected: Arc<AtomicBool>,
+    // shared between requests to track the peers across scrapes
+    pub(crate) scrape_state: Arc<Mutex<ScrapeState>>,
+    pub cache_seconds: u64,
+    // async since it is held while wg runs, so the concurrent scrapes
+    // wait for the same collection
+    pub(crate) dump_cache: Arc<tokio::sync::Mutex<DumpCache>>,
+    pub resolve_remote_ip: bool,
+    pub resolve_remote_ip_ttl_seconds: u64,
+    // shared between requests so the lookups are cached
+    pub(crate) remote_host_cache: Arc<Mutex<RemoteHostCache>>,
+    pub geoip_db_files: Option<Vec<String>>,
+    // shared between requests so the lookups are cached
+    pub(crate) geoip: Arc<Mutex<GeoIp>>,
+    pub include_public_keys_file: Option<String>,
+    pub exclude_public_keys_file: Option<String>,
+    // shared between requests so it can be reloaded on SIGHUP
+    pub(crate) peer_filter: Arc<RwLock<PeerFilter>>,
+    pub tls_cert_file: Option<String>,
+    pub tls_key_file: Option<String>,
+    // None is the default /metrics
+    pub metrics_path: Option<String>,
+    // --external_path_prefix, without the trailing /
+    pub external_path_prefix: Option<String>,
+    // --label, in command line order. The values are escaped as label values
+    pub static_labels: Vec<(String, String)>,
+    pub openmetrics: bool,
+    pub openmetrics_handshake_timestamps: bool,
+    // shared between requests so it can be reloaded on SIGHUP
+    pub(crate) tls_config: Arc<RwLock<Option<TlsConfig>>>,
+    pub(crate) authorization: Authorization,
+}
+
+// The same defaults of the command line, so the library renders what the
+// exporter does when no option is specified.
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            verbose: false,
+            prepend_sudo: false,
+            separate_allowed_ips: false,
+            extract_names_config_files: None,
+            interfaces: None,
+            exclude_interfaces: None,
+            enumerate_interfaces: false,
+            no_interface_label: false,
+            export_remote_ip_and_port: false,
+            endpoint_label: false,
+            export_latest_handshake_delay: false,
+            analyze_dump: None,
+            skip_unsupported_json: true,
+            wg_concurrency: 4,
+            export_scrape_mode: false,
+            maintenance_window: None,
+            export_counter_resets: false,
+            export_bytes_per_second: false,
+            export_configured_allowed_ips: false,
+            stdio: false,
+            stdin: false,
+            oneshot: false,
+            stdin_dump: None,
+            site_from_allowed_ip: None,
+            export_handshake_label: false,
+            export_unique_remote_ips: false,
+            export_interfaces_total: false,
+            no_private_key_readout: false,
+            interface_options_file: None,
+            interface_options: InterfaceOptionsMap::new(),
+            shutdown_grace_period: 0,
+            field_separator: None,
+            export_name_changes: false,
+            handshake_timeout_seconds: 180,
+            interface_handshake_timeout_seconds: HashMap::new(),
+            connected_only: false,
+            skip_never_connected: false,
+            count_never_connected: true,
+            max_peers_per_interface: 0,
+            export_concurrent_scrapes: false,
+            concurrent_scrapes: Arc::new(AtomicUsize::new(0)),
+            export_scrape_duration: false,
+            dump_file: None,
+            canonical_allowed_ips: false,
+            export_first_seen: false,
+            wg_path: None,
+            wg_timeout_seconds: 0,
+            name_comma_replacement: None,
+            skip_malformed_lines: false,
+            export_dump_line_counts: false,
+            export_persistent_keepalive: false,
+            export_preshared_key_presence: false,
+            export_allowed_ips_count: false,
+            export_peer_connected: false,
+            export_listen_port_and_fwmark: false,
+            export_unnamed_peers: false,
+            export_configured_peers: false,
+            on_duplicate_name: DuplicateNameAction::Warn,
+            names_file: None,
+            hash_public_keys: false,
+            remote_agent_urls: None,
+            export_peer_info: false,
+            export_server_public_key: false,
+            export_avg_bytes_per_peer: false,
+            export_handshake_age_histogram: false,
+            export_peers_by_connection_age: false,
+            connection_age_buckets: vec![3600, 86400],
+            export_peers_by_family: false,
+            validate_public_keys: false,
+            expected_allowed_ips_file: None,
+            expected_allowed_ips: ExpectedAllowedIpsMap::new(),
+            remote_agent_timeout_seconds: 10,
+            draining: Arc::new(AtomicBool::new(false)),
+            collected: Arc::new(AtomicBool::new(false)),
+            scrape_state: Arc::new(Mutex::new(ScrapeState::default())),
+            cache_seconds: 0,
+            dump_cache: Arc::new(tokio::sync::Mutex::new(DumpCache::default())),
+            resolve_remote_ip: false,
+            resolve_remote_ip_ttl_seconds: 300,
+            remote_host_cache: Arc::new(Mutex::new(RemoteHostCache::default())),
+            geoip_db_files: None,
+            geoip: Arc::new(Mutex::new(GeoIp::default())),
+            include_public_keys_file: None,
+            exclude_public_keys_file: None,
+            peer_filter: Arc::new(RwLock::new(PeerFilter::default())),
+            tls_cert_file: None,
+            tls_key_file: None,
+            metrics_path: Some(METRICS_PATH.to_owned()),
+            external_path_prefix: None,
+            static_labels: Vec::new(),
+            openmetrics: false,
+            openmetrics_handshake_timestamps: false,
+            tls_config: Arc::new(RwLock::new(None)),
+            authorization: Authorization::new(None, None),
+        }
+    }
 }
 
 impl Options {
     pub fn from_claps(matches: &clap::ArgMatches) -> Options {
+        let mut connection_age_buckets: Vec<u64> = matches
+            .get_many("connection_age_buckets")
+            .map(|e: ValuesRef<'_, u64>| e.copied().collect())
+            .unwrap_or_default();
+        connection_age_buckets.sort_unstable();
+        connection_age_buckets.dedup();
+
         let options = Options {
             verbose: *matches.get_one("verbose").unwrap_or(&false),
             prepend_sudo: *matches.get_one("prepend_sudo").unwrap_or(&false),
@@ -23,14 +255,224 @@ impl Options {
             interfaces: matches
                 .get_many("interfaces")
                 .map(|e: ValuesRef<'_, String>| e.into_iter().map(|a| a.to_string()).collect()),
+            exclude_interfaces: matches
+                .get_many("exclude_interfaces")
+                .map(|e: ValuesRef<'_, String>| e.into_iter().map(|a| a.to_string()).collect()),
+            enumerate_interfaces: *matches.get_one("enumerate_interfaces").unwrap_or(&false),
+            no_interface_label: *matches.get_one("no_interface_label").unwrap_or(&false),
             export_remote_ip_and_port: *matches
                 .get_one("export_remote_ip_and_port")
                 .unwrap_or(&false),
+            endpoint_label: *matches.get_one("endpoint_label").unwrap_or(&false),
             export_latest_handshake_delay: *matches
                 .get_one("export_latest_handshake_delay")
                 .unwrap_or(&false),
+            analyze_dump: matches.get_one("analyze_dump").cloned(),
+            skip_unsupported_json: *matches.get_one("skip_unsupported_json").unwrap_or(&true),
+            wg_concurrency: *matches.get_one("wg_concurrency").unwrap_or(&4),
+            export_scrape_mode: *matches.get_one("export_scrape_mode").unwrap_or(&false),
+            maintenance_window: matches.get_one("maintenance_window").copied(),
+            export_counter_resets: *matches.get_one("export_counter_resets").unwrap_or(&false),
+            export_bytes_per_second: *matches.get_one("export_bytes_per_second").unwrap_or(&false),
+            export_configured_allowed_ips: *matches
+                .get_one("export_configured_allowed_ips")
+                .unwrap_or(&false),
+            stdio: *matches.get_one("stdio").unwrap_or(&false),
+            stdin: *matches.get_one("stdin").unwrap_or(&false),
+            oneshot: *matches.get_one("oneshot").unwrap_or(&false),
+            stdin_dump: None,
+            site_from_allowed_ip: matches.get_one("site_from_allowed_ip").copied(),
+            export_unique_remote_ips: *matches
+                .get_one("export_unique_remote_ips")
+                .unwrap_or(&false),
+            export_interfaces_total: *matches.get_one("export_interfaces_total").unwrap_or(&false),
+            no_private_key_readout: *matches.get_one("no_private_key_readout").unwrap_or(&false),
+            export_handshake_label: *matches.get_one("export_handshake_label").unwrap_or(&false),
+            interface_options_file: matches.get_one("interface_options_file").cloned(),
+            interface_options: InterfaceOptionsMap::new(),
+            shutdown_grace_period: *matches.get_one("shutdown_grace_period").unwrap_or(&0),
+            draining: Arc::new(AtomicBool::new(false)),
+            collected: Arc::new(AtomicBool::new(false)),
+            handshake_timeout_seconds: matches
+                .get_many("handshake_timeout_seconds")
+                .into_iter()
+                .flatten()
+                .filter_map(|(interface, seconds): &(Option<String>, u64)| {
+                    interface.is_none().then_some(*seconds)
+                })
+                .next_back()
+                .unwrap_or(180),
+            interface_handshake_timeout_seconds: matches
+                .get_many("handshake_timeout_seconds")
+                .into_iter()
+                .flatten()
+                .filter_map(|(interface, seconds): &(Option<String>, u64)| {
+                    interface.clone().map(|interface| (interface, *seconds))
+                })
+                .collect(),
+            connected_only: *matches.get_one("connected_only").unwrap_or(&false),
+            skip_never_connected: *matches.get_one("skip_never_connected").unwrap_or(&false),
+            count_never_connected: *matches.get_one("count_never_connected").unwrap_or(&true),
+            max_peers_per_interface: *matches.get_one("max_peers_per_interface").unwrap_or(&0),
+            export_concurrent_scrapes: *matches
+                .get_one("export_concurrent_scrapes")
+                .unwrap_or(&false),
+            concurrent_scrapes: Arc::new(AtomicUsize::new(0)),
+            export_scrape_duration: *matches.get_one("export_scrape_duration").unwrap_or(&false),
+            dump_file: matches.get_one("dump_file").cloned(),
+            canonical_allowed_ips: *matches.get_one("canonical_allowed_ips").unwrap_or(&false),
+            export_first_seen: *matches.get_one("export_first_seen").unwrap_or(&false),
+            wg_path: matches.get_one("wg_path").cloned(),
+            wg_timeout_seconds: *matches.get_one("wg_timeout_seconds").unwrap_or(&0),
+            name_comma_replacement: matches.get_one("name_comma_replacement").cloned(),
+            skip_malformed_lines: *matches.get_one("skip_malformed_lines").unwrap_or(&false),
+            expected_allowed_ips_file: matches.get_one("expected_allowed_ips_file").cloned(),
+            expected_allowed_ips: ExpectedAllowedIpsMap::new(),
+            export_peers_by_connection_age: *matches
+                .get_one("export_peers_by_connection_age")
+                .unwrap_or(&false),
+            connection_age_buckets,
+            export_peers_by_family: *matches.get_one("export_peers_by_family").unwrap_or(&false),
+            validate_public_keys: *matches.get_one("validate_public_keys").unwrap_or(&false),
+            export_handshake_age_histogram: *matches
+                .get_one("export_handshake_age_histogram")
+                .unwrap_or(&false),
+            export_avg_bytes_per_peer: *matches
+                .get_one("export_avg_bytes_per_peer")
+                .unwrap_or(&false),
+            export_peer_info: *matches.get_one("export_peer_info").unwrap_or(&false),
+            export_server_public_key: *matches
+                .get_one("export_server_public_key")
+                .unwrap_or(&false),
+            remote_agent_urls: matches
+                .get_many("remote_agent_urls")
+                .map(|e: ValuesRef<'_, String>| e.into_iter().map(|a| a.to_owned()).collect()),
+            remote_agent_timeout_seconds: *matches
+                .get_one("remote_agent_timeout_seconds")
+                .unwrap_or(&10),
+            hash_public_keys: *matches.get_one("hash_public_keys").unwrap_or(&false),
+            names_file: matches.get_one("names_file").cloned(),
+            on_duplicate_name: match matches
+                .get_one::<String>("on_duplicate_name")
+                .map(|s| s.as_str())
+            {
+                Some("suffix") => DuplicateNameAction::Suffix,
+                Some("fail") => DuplicateNameAction::Fail,
+                _ => DuplicateNameAction::Warn,
+            },
+            export_configured_peers: *matches.get_one("export_configured_peers").unwrap_or(&false),
+            export_unnamed_peers: *matches.get_one("export_unnamed_peers").unwrap_or(&false),
+            export_listen_port_and_fwmark: *matches
+                .get_one("export_listen_port_and_fwmark")
+                .unwrap_or(&false),
+            export_preshared_key_presence: *matches
+                .get_one("export_preshared_key_presence")
+                .unwrap_or(&false),
+            export_allowed_ips_count: *matches
+                .get_one("export_allowed_ips_count")
+                .unwrap_or(&false),
+            export_peer_connected: *matches.get_one("export_peer_connected").unwrap_or(&false),
+            export_persistent_keepalive: *matches
+                .get_one("export_persistent_keepalive")
+                .unwrap_or(&false),
+            export_dump_line_counts: *matches.get_one("export_dump_line_counts").unwrap_or(&false),
+            export_name_changes: *matches.get_one("export_name_changes").unwrap_or(&false),
+            field_separator: match matches
+                .get_one::<String>("field_separator")
+                .map(|s| s.as_str())
+            {
+                Some("space") => Some(' '),
+                Some("comma") => Some(','),
+                _ => None,
+            },
+            scrape_state: Arc::new(Mutex::new(ScrapeState::default())),
+            cache_seconds: *matches.get_one("cache_seconds").unwrap_or(&0),
+            dump_cache: Arc::new(tokio::sync::Mutex::new(DumpCache::default())),
+            resolve_remote_ip: *matches.get_one("resolve_remote_ip").unwrap_or(&false),
+            resolve_remote_ip_ttl_seconds: *matches
+                .get_one("resolve_remote_ip_ttl_seconds")
+                .unwrap_or(&300),
+            remote_host_cache: Arc::new(Mutex::new(RemoteHostCache::default())),
+            geoip_db_files: matches
+                .get_many("geoip_db_files")
+                .map(|e: ValuesRef<'_, String>| e.into_iter().map(|a| a.to_owned()).collect()),
+            geoip: Arc::new(Mutex::new(GeoIp::default())),
+            include_public_keys_file: matches.get_one("include_public_keys_file").cloned(),
+            exclude_public_keys_file: matches.get_one("exclude_public_keys_file").cloned(),
+            peer_filter: Arc::new(RwLock::new(PeerFilter::default())),
+            tls_cert_file: matches.get_one("tls_cert_file").cloned(),
+            tls_key_file: matches.get_one("tls_key_file").cloned(),
+            metrics_path: matches.get_one("metrics_path").cloned(),
+            external_path_prefix: matches.get_one("external_path_prefix").cloned(),
+            static_labels: matches
+                .get_many("static_labels")
+                .map(|e: ValuesRef<'_, (String, String)>| e.cloned().collect())
+                .unwrap_or_default(),
+            openmetrics: *matches.get_one("openmetrics").unwrap_or(&false),
+            openmetrics_handshake_timestamps: *matches
+                .get_one("openmetrics_handshake_timestamps")
+                .unwrap_or(&false),
+            tls_config: Arc::new(RwLock::new(None)),
+            authorization: Authorization::new(
+                matches.get_one::<String>("auth_token").map(|s| s.as_str()),
+                matches.get_one::<String>("basic_auth").map(|s| s.as_str()),
+            ),
         };
 
         options
     }
+
+    pub fn load_interface_options(&mut self) -> Result<(), anyhow::Error> {
+        if let Some(interface_options_file) = &self.interface_options_file {
+            let txt = std::fs::read_to_string(interface_options_file)?;
+            self.interface_options = interface_options_try_from(&txt)?;
+        }
+        Ok(())
+    }
+
+    pub fn load_expected_allowed_ips(&mut self) -> Result<(), anyhow::Error> {
+        if let Some(expected_allowed_ips_file) = &self.expected_allowed_ips_file {
+            let txt = std::fs::read_to_string(expected_allowed_ips_file)?;
+            self.expected_allowed_ips = expected_allowed_ips_try_from(&txt)?;
+        }
+        Ok(())
+    }
+
+    // the timeout of the interface if specified, the global one otherwise
+    pub fn handshake_timeout_seconds(&self, interface: &str) -> u64 {
+        self.interface_handshake_timeout_seconds
+            .get(interface)
+            .copied()
+            .unwrap_or(self.handshake_timeout_seconds)
+    }
+
+    // the overrides of the interface, if any
+    pub fn interface_options(&self, interface: &str) -> Option<&InterfaceOptions> {
+        self.interface_options.get(interface)
+    }
+
+    pub fn load_peer_filter(&self) -> Result<(), std::io::Error> {
+        let peer_filter = PeerFilter::from_files(
+            self.include_public_keys_file.as_deref(),
+            self.exclude_public_keys_file.as_deref(),
+        )?;
+        *self.peer_filter.write().unwrap() = peer_filter;
+        Ok(())
+    }
+
+    // never fails: a database that cannot be opened is logged and skipped
+    pub fn load_geoip(&self) {
+        if let Some(geoip_db_files) = &self.geoip_db_files {
+            *self.geoip.lock().unwrap() = GeoIp::open(geoip_db_files);
+        }
+    }
+
+    pub fn load_tls_config(&self) -> Result<(), TlsConfigError> {
+        if let (Some(tls_cert_file), Some(tls_key_file)) = (&self.tls_cert_file, &self.tls_key_file)
+        {
+            let tls_config = TlsConfig::from_files(tls_cert_file, tls_key_file)?;
+            *self.tls_config.write().unwrap() = Some(tls_config);
+        }
+        Ok(())
+    }
 }
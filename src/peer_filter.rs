@@ -0,0 +1,88 @@
+use log::debug;
+use std::collections::HashSet;
+
+// Holds the public keys to include/exclude from the exported metrics.
+// If the include list is present only the peers in it will be exported.
+// The exclude list is always applied afterwards.
+#[derive(Debug, Default, Clone)]
+pub struct PeerFilter {
+    include: Option<HashSet<String>>,
+    exclude: HashSet<String>,
+}
+
+// one public key per line, empty lines and lines starting with # are ignored
+fn parse_public_keys(txt: &str) -> HashSet<String> {
+    txt.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_owned())
+        .collect()
+}
+
+impl PeerFilter {
+    pub fn from_files(
+        include_file: Option<&str>,
+        exclude_file: Option<&str>,
+    ) -> Result<PeerFilter, std::io::Error> {
+        let include = include_file
+            .map(std::fs::read_to_string)
+            .transpose()?
+            .map(|txt| parse_public_keys(&txt));
+        let exclude = exclude_file
+            .map(std::fs::read_to_string)
+            .transpose()?
+            .map(|txt| parse_public_keys(&txt))
+            .unwrap_or_default();
+
+        let peer_filter = PeerFilter { include, exclude };
+        debug!("PeerFilter::from_files returning {:?}", peer_filter);
+        Ok(peer_filter)
+    }
+
+    pub fn is_allowed(&self, public_key: &str) -> bool {
+        let included = self
+            .include
+            .as_ref()
+            .is_none_or(|include| include.contains(public_key));
+        included && !self.exclude.contains(public_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYS: &str = "
+# this is a comment
+2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+   qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=
+";
+
+    #[test]
+    fn test_parse_public_keys() {
+        let keys = parse_public_keys(KEYS);
+        assert_eq!(keys.len(), 2);
+        assert!(keys.contains("2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk="));
+        assert!(keys.contains("qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU="));
+    }
+
+    #[test]
+    fn test_is_allowed() {
+        let pf = PeerFilter::default();
+        assert!(pf.is_allowed("anything"));
+
+        let pf = PeerFilter {
+            include: Some(parse_public_keys(KEYS)),
+            exclude: HashSet::new(),
+        };
+        assert!(pf.is_allowed("2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk="));
+        assert!(!pf.is_allowed("L2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008="));
+
+        let pf = PeerFilter {
+            include: Some(parse_public_keys(KEYS)),
+            exclude: parse_public_keys("qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU="),
+        };
+        assert!(pf.is_allowed("2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk="));
+        assert!(!pf.is_allowed("qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU="));
+    }
+}
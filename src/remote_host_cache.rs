@@ -0,0 +1,131 @@
+use crate::options::Options;
+use crate::wireguard::{Endpoint, WireGuard};
+use futures::stream::{self, StreamExt};
+use log::{debug, trace};
+use std::collections::{BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// a slow resolver must not hold the scrape for too long
+const REVERSE_DNS_TIMEOUT: Duration = Duration::from_secs(2);
+
+// The host names of the remote ips, shared between requests so every ip is
+// resolved at most once every TTL. Failed lookups are cached too.
+#[derive(Debug, Default)]
+pub struct RemoteHostCache {
+    // remote ip => (host name, UNIX timestamp of the lookup)
+    hosts: HashMap<String, (Option<String>, u64)>,
+}
+
+impl RemoteHostCache {
+    pub fn get(&self, remote_ip: &str) -> Option<&str> {
+        self.hosts
+            .get(remote_ip)
+            .and_then(|(host, _)| host.as_deref())
+    }
+
+    pub fn is_stale(&self, remote_ip: &str, now: u64, ttl_seconds: u64) -> bool {
+        match self.hosts.get(remote_ip) {
+            Some((_, resolved_at)) => now.saturating_sub(*resolved_at) >= ttl_seconds,
+            None => true,
+        }
+    }
+
+    pub fn insert(&mut self, remote_ip: String, host: Option<String>, now: u64) {
+        self.hosts.insert(remote_ip, (host, now));
+    }
+
+    // forgets the stale entries, so the ips no longer in use do not pile up
+    pub fn remove_stale(&mut self, now: u64, ttl_seconds: u64) {
+        self.hosts
+            .retain(|_, (_, resolved_at)| now.saturating_sub(*resolved_at) < ttl_seconds);
+    }
+}
+
+// The PTR record of the ip, if any. getnameinfo falls back to the numeric
+// address when there is no record, that is considered a failure too.
+async fn resolve_remote_host(remote_ip: &str) -> Option<String> {
+    let addr: IpAddr = remote_ip.parse().ok()?;
+    let lookup = tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&addr));
+
+    match tokio::time::timeout(REVERSE_DNS_TIMEOUT, lookup).await {
+        Ok(Ok(Ok(host))) if host.parse::<IpAddr>().is_err() => Some(host),
+        result => {
+            debug!("reverse lookup of {} failed: {:?}", remote_ip, result);
+            None
+        }
+    }
+}
+
+// Resolves the remote ips of the peers that are not in the cache (or whose
+// entry is older than the TTL).
+pub async fn resolve_remote_hosts(wg: &WireGuard, options: &Options) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time went backwards")
+        .as_secs();
+    let ttl_seconds = options.resolve_remote_ip_ttl_seconds;
+
+    let stale_remote_ips = {
+        let remote_host_cache = options.remote_host_cache.lock().unwrap();
+        wg.interfaces
+            .values()
+            .flatten()
+            .filter_map(|endpoint| match endpoint {
+                Endpoint::Remote(ep) => ep.remote_ip.as_deref(),
+                Endpoint::Local(_) => None,
+            })
+            .filter(|remote_ip| remote_host_cache.is_stale(remote_ip, now, ttl_seconds))
+            .map(|remote_ip| remote_ip.to_owned())
+            .collect::<BTreeSet<String>>()
+    };
+    trace!("stale_remote_ips == {:?}", stale_remote_ips);
+
+    let hosts = stream::iter(stale_remote_ips)
+        .map(|remote_ip| async move {
+            let host = resolve_remote_host(&remote_ip).await;
+            (remote_ip, host)
+        })
+        .buffer_unordered(options.wg_concurrency as usize)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut remote_host_cache = options.remote_host_cache.lock().unwrap();
+    remote_host_cache.remove_stale(now, ttl_seconds);
+    for (remote_ip, host) in hosts {
+        remote_host_cache.insert(remote_ip, host, now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_host_cache() {
+        let mut cache = RemoteHostCache::default();
+
+        assert!(cache.is_stale("10.0.0.1", 100, 300));
+        cache.insert(
+            "10.0.0.1".to_owned(),
+            Some("alice.example.com".to_owned()),
+            100,
+        );
+        cache.insert("10.0.0.2".to_owned(), None, 200);
+
+        assert_eq!(cache.get("10.0.0.1"), Some("alice.example.com"));
+        assert_eq!(cache.get("10.0.0.2"), None);
+        assert!(!cache.is_stale("10.0.0.1", 399, 300));
+        assert!(cache.is_stale("10.0.0.1", 400, 300));
+        assert!(!cache.is_stale("10.0.0.2", 400, 300));
+
+        cache.remove_stale(400, 300);
+        assert_eq!(cache.get("10.0.0.1"), None);
+        assert!(!cache.is_stale("10.0.0.2", 400, 300));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_remote_host_invalid_ip() {
+        assert_eq!(resolve_remote_host("not an ip").await, None);
+    }
+}
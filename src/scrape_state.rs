@@ -0,0 +1,674 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// What we remember of a peer between two scrapes.
+#[derive(Debug, Default, Clone)]
+pub struct PeerState {
+    pub sent_bytes: u128,
+    pub received_bytes: u128,
+    pub counter_resets: u64,
+}
+
+// The byte counters of a peer as read by the previous scrape that ran wg,
+// along with the rates computed then.
+#[derive(Debug, Clone)]
+pub struct PeerRates {
+    pub sent_bytes: u128,
+    pub received_bytes: u128,
+    pub observed_at: Instant,
+    pub sent_bytes_per_second: f64,
+    pub received_bytes_per_second: f64,
+}
+
+// The friendly name of a peer as loaded from the config files.
+#[derive(Debug, Default, Clone)]
+pub struct PeerNameState {
+    pub name: Option<String>,
+    pub name_changes: u64,
+}
+
+// Interface, public key and source (None for the local wg) of a peer: the
+// same peer collected from two remote agents has two states.
+type PeerKey = (String, String, Option<String>);
+
+// State shared between scrapes. Everything here lives in memory only so
+// it starts from scratch every time the exporter is restarted. The state of
+// the peers is kept along with the last scrape that included them, the
+// peers missing from a scrape are forgotten by end_scrape.
+#[derive(Debug, Default)]
+pub struct ScrapeState {
+    peers: HashMap<PeerKey, (PeerState, u64)>,
+    peer_rates: HashMap<PeerKey, (PeerRates, u64)>,
+    // keyed by public key, the config files do not specify the interface
+    peer_names: HashMap<String, PeerNameState>,
+    // UNIX timestamp of the first scrape that included the peer
+    peers_first_seen: HashMap<PeerKey, (u64, u64)>,
+    // UNIX timestamp since when the peer has been in every scrape
+    peers_present_since: HashMap<PeerKey, (u64, u64)>,
+    scrapes: u64,
+    config_parse_errors: u64,
+    invalid_public_keys: u64,
+}
+
+impl ScrapeState {
+    // Records the current byte counters of a peer and returns its state. If
+    // either counter went backwards since the previous scrape the counters
+    // have been reset (for example because the peer was removed and added
+    // back) and the reset count is increased. A peer seen for the first time
+    // is not considered a reset.
+    pub fn observe_peer(
+        &mut self,
+        interface: &str,
+        public_key: &str,
+        source: Option<&str>,
+        sent_bytes: u128,
+        received_bytes: u128,
+    ) -> &PeerState {
+        let scrapes = self.scrapes;
+        let (peer_state, last_scrape) = self
+            .peers
+            .entry(peer_key(interface, public_key, source))
+            .or_insert_with(|| {
+                (
+                    PeerState {
+                        sent_bytes,
+                        received_bytes,
+                        counter_resets: 0,
+                    },
+                    scrapes,
+                )
+            });
+        *last_scrape = scrapes;
+
+        if sent_bytes < peer_state.sent_bytes || received_bytes < peer_state.received_bytes {
+            peer_state.counter_resets += 1;
+        }
+        peer_state.sent_bytes = sent_bytes;
+        peer_state.received_bytes = received_bytes;
+
+        peer_state
+    }
+
+    // The state of a peer whose dump was reused from the cache: its counters
+    // were compared when they were read, so nothing is recorded and the peer
+    // is only kept. None if the peer is unknown.
+    pub fn cached_peer(
+        &mut self,
+        interface: &str,
+        public_key: &str,
+        source: Option<&str>,
+    ) -> Option<&PeerState> {
+        let scrapes = self.scrapes;
+        let (peer_state, last_scrape) = self
+            .peers
+            .get_mut(&peer_key(interface, public_key, source))?;
+        *last_scrape = scrapes;
+        Some(peer_state)
+    }
+
+    // Records the current byte counters of a peer and returns the bytes sent
+    // and received per second since the previous scrape. A peer seen for the
+    // first time has no rate yet and a counter that went backwards has been
+    // reset, both are reported as 0.
+    pub fn observe_peer_rates(
+        &mut self,
+        interface: &str,
+        public_key: &str,
+        source: Option<&str>,
+        sent_bytes: u128,
+        received_bytes: u128,
+        now: Instant,
+    ) -> (f64, f64) {
+        let scrapes = self.scrapes;
+        let key = peer_key(interface, public_key, source);
+        let (sent_bytes_per_second, received_bytes_per_second) = match self.peer_rates.get(&key) {
+            Some((previous, _)) => {
+                let elapsed = now
+                    .saturating_duration_since(previous.observed_at)
+                    .as_secs_f64();
+                let bytes_per_second = |previous: u128, current: u128| {
+                    if elapsed > 0.0 {
+                        current.saturating_sub(previous) as f64 / elapsed
+                    } else {
+                        0.0
+                    }
+                };
+                (
+                    bytes_per_second(previous.sent_bytes, sent_bytes),
+                    bytes_per_second(previous.received_bytes, received_bytes),
+                )
+            }
+            None => (0.0, 0.0),
+        };
+
+        self.peer_rates.insert(
+            key,
+            (
+                PeerRates {
+                    sent_bytes,
+                    received_bytes,
+                    observed_at: now,
+                    sent_bytes_per_second,
+                    received_bytes_per_second,
+                },
+                scrapes,
+            ),
+        );
+        (sent_bytes_per_second, received_bytes_per_second)
+    }
+
+    // The rates of a peer whose dump was reused from the cache: its counters
+    // have not moved since they were read, so nothing is recorded and the
+    // rates computed from the dump of the cache are returned again.
+    pub fn cached_peer_rates(
+        &mut self,
+        interface: &str,
+        public_key: &str,
+        source: Option<&str>,
+    ) -> (f64, f64) {
+        let scrapes = self.scrapes;
+        match self
+            .peer_rates
+            .get_mut(&peer_key(interface, public_key, source))
+        {
+            Some((peer_rates, last_scrape)) => {
+                *last_scrape = scrapes;
+                (
+                    peer_rates.sent_bytes_per_second,
+                    peer_rates.received_bytes_per_second,
+                )
+            }
+            None => (0.0, 0.0),
+        }
+    }
+
+    // Returns when the peer was seen for the first time, recording now if
+    // this is the first time.
+    pub fn observe_peer_first_seen(
+        &mut self,
+        interface: &str,
+        public_key: &str,
+        source: Option<&str>,
+        now: u64,
+    ) -> u64 {
+        let scrapes = self.scrapes;
+        let first_seen = self
+            .peers_first_seen
+            .entry(peer_key(interface, public_key, source))
+            .or_insert((now, scrapes));
+        first_seen.1 = scrapes;
+        first_seen.0
+    }
+
+    // Returns since when the peer has been continuously present, recording
+    // now if the peer is new or was missing from the previous scrape.
+    pub fn observe_peer_present(
+        &mut self,
+        interface: &str,
+        public_key: &str,
+        source: Option<&str>,
+        now: u64,
+    ) -> u64 {
+        let scrapes = self.scrapes;
+        let present_since = self
+            .peers_present_since
+            .entry(peer_key(interface, public_key, source))
+            .or_insert((now, scrapes));
+        present_since.1 = scrapes;
+        present_since.0
+    }
+
+    // The first seen timestamp of a peer whose dump was reused from the
+    // cache: nothing is recorded, the peer is only kept. now if the peer is
+    // unknown.
+    pub fn cached_peer_first_seen(
+        &mut self,
+        interface: &str,
+        public_key: &str,
+        source: Option<&str>,
+        now: u64,
+    ) -> u64 {
+        cached_timestamp(
+            &mut self.peers_first_seen,
+            self.scrapes,
+            &peer_key(interface, public_key, source),
+            now,
+        )
+    }
+
+    // The same for the present since timestamp.
+    pub fn cached_peer_present(
+        &mut self,
+        interface: &str,
+        public_key: &str,
+        source: Option<&str>,
+        now: u64,
+    ) -> u64 {
+        cached_timestamp(
+            &mut self.peers_present_since,
+            self.scrapes,
+            &peer_key(interface, public_key, source),
+            now,
+        )
+    }
+
+    // To be called once all the peers of a scrape have been observed: the
+    // peers missing from the scrape are forgotten, so the state of the
+    // removed peers does not pile up and the state of a peer coming back
+    // starts over (its age, its rates, its first seen timestamp and its
+    // counter resets).
+    pub fn end_scrape(&mut self) {
+        let scrapes = self.scrapes;
+        self.peers.retain(|_, peer| peer.1 == scrapes);
+        self.peer_rates.retain(|_, peer| peer.1 == scrapes);
+        self.peers_first_seen.retain(|_, peer| peer.1 == scrapes);
+        self.peers_present_since.retain(|_, peer| peer.1 == scrapes);
+        self.scrapes += 1;
+    }
+
+    // Adds the friendly descriptions skipped in this scrape and returns the
+    // total since the exporter started.
+    pub fn observe_config_parse_errors(&mut self, config_parse_errors: u64) -> u64 {
+        self.config_parse_errors += config_parse_errors;
+        self.config_parse_errors
+    }
+
+    // Adds the invalid public keys found in this scrape and returns the total
+    // since the exporter started.
+    pub fn observe_invalid_public_keys(&mut self, invalid_public_keys: u64) -> u64 {
+        self.invalid_public_keys += invalid_public_keys;
+        self.invalid_public_keys
+    }
+
+    // Records the friendly name of a peer as resolved from the config files
+    // and returns its state. The change count is increased every time the
+    // name differs from the one of the previous scrape. A peer seen for the
+    // first time is not considered a change.
+    pub fn observe_peer_name(&mut self, public_key: &str, name: Option<&str>) -> &PeerNameState {
+        let peer_name_state = self
+            .peer_names
+            .entry(public_key.to_owned())
+            .or_insert_with(|| PeerNameState {
+                name: name.map(|name| name.to_owned()),
+                name_changes: 0,
+            });
+
+        if peer_name_state.name.as_deref() != name {
+            peer_name_state.name_changes += 1;
+            peer_name_state.name = name.map(|name| name.to_owned());
+        }
+
+        peer_name_state
+    }
+}
+
+fn peer_key(interface: &str, public_key: &str, source: Option<&str>) -> PeerKey {
+    (
+        interface.to_owned(),
+        public_key.to_owned(),
+        source.map(|source| source.to_owned()),
+    )
+}
+
+fn cached_timestamp(
+    timestamps: &mut HashMap<PeerKey, (u64, u64)>,
+    scrapes: u64,
+    key: &PeerKey,
+    now: u64,
+) -> u64 {
+    match timestamps.get_mut(key) {
+        Some((timestamp, last_scrape)) => {
+            *last_scrape = scrapes;
+            *timestamp
+        }
+        None => now,
+    }
+}
+
+// The wg dumps of the latest collection, along with the interface passed
+// to wg show, reused by the scrapes that come within the TTL. The errors are
+// kept as strings since they are reported by every scrape reusing them.
+#[derive(Debug, Default)]
+pub struct DumpCache {
+    collected_at: Option<Instant>,
+    dumps: Vec<(String, Result<String, String>)>,
+}
+
+impl DumpCache {
+    pub fn get(&self, now: Instant, ttl: Duration) -> Option<&[(String, Result<String, String>)]> {
+        match self.collected_at {
+            Some(collected_at) if now.saturating_duration_since(collected_at) < ttl => {
+                Some(&self.dumps)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn set(&mut self, collected_at: Instant, dumps: Vec<(String, Result<String, String>)>) {
+        self.collected_at = Some(collected_at);
+        self.dumps = dumps;
+    }
+}
+
+// Tracks a scrape in progress: the counter is incremented on creation and
+// decremented on drop, so it stays accurate even if the scrape fails.
+pub struct ScrapeGuard<'a> {
+    concurrent_scrapes: &'a AtomicUsize,
+}
+
+impl<'a> ScrapeGuard<'a> {
+    // Returns the guard and the number of scrapes in progress, this one included.
+    pub fn enter(concurrent_scrapes: &'a AtomicUsize) -> (ScrapeGuard<'a>, usize) {
+        let current = concurrent_scrapes.fetch_add(1, Ordering::SeqCst) + 1;
+        (ScrapeGuard { concurrent_scrapes }, current)
+    }
+}
+
+impl Drop for ScrapeGuard<'_> {
+    fn drop(&mut self) {
+        self.concurrent_scrapes.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_observe_peer_rates() {
+        let mut state = ScrapeState::default();
+        let start = Instant::now();
+
+        assert_eq!(
+            state.observe_peer_rates("wg0", "a", None, 100, 1000, start),
+            (0.0, 0.0)
+        );
+        assert_eq!(
+            state.observe_peer_rates("wg0", "a", None, 600, 1105, start + Duration::from_secs(10)),
+            (50.0, 10.5)
+        );
+        // the sent counter was reset
+        assert_eq!(
+            state.observe_peer_rates("wg0", "a", None, 0, 1300, start + Duration::from_secs(20)),
+            (0.0, 19.5)
+        );
+        // same peer on another interface
+        assert_eq!(
+            state.observe_peer_rates(
+                "wg1",
+                "a",
+                None,
+                5000,
+                5000,
+                start + Duration::from_secs(20)
+            ),
+            (0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_observe_peer_rates_sources() {
+        let mut state = ScrapeState::default();
+        let start = Instant::now();
+        let later = start + Duration::from_secs(10);
+        let remote = Some("http://10.0.0.1:9587/dump");
+
+        state.observe_peer_rates("wg0", "a", None, 100, 100, start);
+        state.observe_peer_rates("wg0", "a", remote, 5000, 5000, start);
+        // the same peer from another source has its own counters
+        assert_eq!(
+            state.observe_peer_rates("wg0", "a", None, 200, 300, later),
+            (10.0, 20.0)
+        );
+        assert_eq!(
+            state.observe_peer_rates("wg0", "a", remote, 5100, 5000, later),
+            (10.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_cached_peer_rates() {
+        let mut state = ScrapeState::default();
+        let start = Instant::now();
+
+        assert_eq!(state.cached_peer_rates("wg0", "a", None), (0.0, 0.0));
+        state.observe_peer_rates("wg0", "a", None, 100, 100, start);
+        state.end_scrape();
+        assert_eq!(
+            state.observe_peer_rates("wg0", "a", None, 200, 300, start + Duration::from_secs(10)),
+            (10.0, 20.0)
+        );
+        state.end_scrape();
+
+        // the dump of the previous scrape is reused: same rates, and the peer
+        // is not forgotten
+        assert_eq!(state.cached_peer_rates("wg0", "a", None), (10.0, 20.0));
+        state.end_scrape();
+        assert_eq!(state.peer_rates.len(), 1);
+
+        // the next dump is compared to the last one read from wg
+        assert_eq!(
+            state.observe_peer_rates("wg0", "a", None, 400, 300, start + Duration::from_secs(30)),
+            (10.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_end_scrape() {
+        let mut state = ScrapeState::default();
+        let start = Instant::now();
+
+        state.observe_peer("wg0", "a", None, 100, 100);
+        state.observe_peer("wg0", "a", None, 0, 0);
+        state.observe_peer_rates("wg0", "a", None, 100, 100, start);
+        state.observe_peer_first_seen("wg0", "a", None, 100);
+        state.observe_peer_first_seen("wg0", "b", None, 100);
+        state.end_scrape();
+
+        // a is missing from this scrape
+        state.observe_peer_first_seen("wg0", "b", None, 200);
+        state.end_scrape();
+        assert_eq!(state.peers.len(), 0);
+        assert_eq!(state.peer_rates.len(), 0);
+        assert_eq!(state.peers_first_seen.len(), 1);
+
+        // and starts over when it comes back
+        assert_eq!(state.observe_peer("wg0", "a", None, 0, 0).counter_resets, 0);
+        assert_eq!(
+            state.observe_peer_rates("wg0", "a", None, 200, 200, start + Duration::from_secs(20)),
+            (0.0, 0.0)
+        );
+        assert_eq!(state.observe_peer_first_seen("wg0", "a", None, 300), 300);
+        assert_eq!(state.observe_peer_first_seen("wg0", "b", None, 300), 100);
+    }
+
+    #[test]
+    fn test_observe_peer_counter_resets() {
+        let mut state = ScrapeState::default();
+
+        // new peers are not resets, even if they start from zero
+        assert_eq!(state.observe_peer("wg0", "a", None, 0, 0).counter_resets, 0);
+        assert_eq!(
+            state
+                .observe_peer("wg0", "b", None, 100, 100)
+                .counter_resets,
+            0
+        );
+
+        assert_eq!(
+            state.observe_peer("wg0", "a", None, 10, 20).counter_resets,
+            0
+        );
+        assert_eq!(
+            state.observe_peer("wg0", "a", None, 10, 20).counter_resets,
+            0
+        );
+        // received went backwards
+        assert_eq!(
+            state.observe_peer("wg0", "a", None, 15, 5).counter_resets,
+            1
+        );
+        assert_eq!(
+            state.observe_peer("wg0", "a", None, 30, 40).counter_resets,
+            1
+        );
+        // sent went backwards
+        assert_eq!(
+            state.observe_peer("wg0", "a", None, 0, 50).counter_resets,
+            2
+        );
+
+        // the same public key on another interface is a different peer
+        assert_eq!(state.observe_peer("wg1", "a", None, 0, 0).counter_resets, 0);
+        assert_eq!(
+            state
+                .observe_peer("wg0", "b", None, 200, 200)
+                .counter_resets,
+            0
+        );
+    }
+
+    #[test]
+    fn test_observe_peer_name_changes() {
+        let mut state = ScrapeState::default();
+
+        assert_eq!(state.observe_peer_name("a", Some("alice")).name_changes, 0);
+        assert_eq!(state.observe_peer_name("b", None).name_changes, 0);
+
+        assert_eq!(state.observe_peer_name("a", Some("alice")).name_changes, 0);
+        assert_eq!(state.observe_peer_name("a", Some("bob")).name_changes, 1);
+        assert_eq!(state.observe_peer_name("a", Some("bob")).name_changes, 1);
+        // removing the name is a change too
+        assert_eq!(state.observe_peer_name("a", None).name_changes, 2);
+        assert_eq!(state.observe_peer_name("b", Some("carol")).name_changes, 1);
+    }
+
+    #[test]
+    fn test_scrape_guard() {
+        let concurrent_scrapes = AtomicUsize::new(0);
+
+        let (first, current) = ScrapeGuard::enter(&concurrent_scrapes);
+        assert_eq!(current, 1);
+        {
+            let (_second, current) = ScrapeGuard::enter(&concurrent_scrapes);
+            assert_eq!(current, 2);
+        }
+        assert_eq!(concurrent_scrapes.load(Ordering::SeqCst), 1);
+        drop(first);
+        assert_eq!(concurrent_scrapes.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_observe_peer_first_seen() {
+        let mut state = ScrapeState::default();
+
+        assert_eq!(state.observe_peer_first_seen("wg0", "a", None, 100), 100);
+        assert_eq!(state.observe_peer_first_seen("wg0", "a", None, 200), 100);
+        assert_eq!(state.observe_peer_first_seen("wg0", "b", None, 200), 200);
+        assert_eq!(state.observe_peer_first_seen("wg1", "a", None, 300), 300);
+        assert_eq!(state.observe_peer_first_seen("wg0", "a", None, 400), 100);
+    }
+
+    #[test]
+    fn test_observe_peer_present() {
+        let mut state = ScrapeState::default();
+
+        assert_eq!(state.observe_peer_present("wg0", "a", None, 100), 100);
+        assert_eq!(state.observe_peer_present("wg0", "b", None, 100), 100);
+        state.end_scrape();
+
+        assert_eq!(state.observe_peer_present("wg0", "a", None, 200), 100);
+        state.end_scrape();
+
+        // b was missing from the previous scrape so it starts over
+        assert_eq!(state.observe_peer_present("wg0", "a", None, 300), 100);
+        assert_eq!(state.observe_peer_present("wg0", "b", None, 300), 300);
+        assert_eq!(state.observe_peer_present("wg1", "a", None, 300), 300);
+        state.end_scrape();
+
+        assert_eq!(state.observe_peer_present("wg0", "b", None, 400), 300);
+    }
+
+    #[test]
+    fn test_observe_peer_sources() {
+        let mut state = ScrapeState::default();
+        let remote = Some("http://10.0.0.1:9587/dump");
+
+        // the same peer from another source has its own state
+        state.observe_peer("wg0", "a", None, 100, 100);
+        state.observe_peer("wg0", "a", remote, 5000, 5000);
+        assert_eq!(
+            state
+                .observe_peer("wg0", "a", None, 200, 200)
+                .counter_resets,
+            0
+        );
+        assert_eq!(
+            state
+                .observe_peer("wg0", "a", remote, 5100, 5100)
+                .counter_resets,
+            0
+        );
+
+        assert_eq!(state.observe_peer_first_seen("wg0", "a", None, 100), 100);
+        assert_eq!(state.observe_peer_first_seen("wg0", "a", remote, 200), 200);
+        assert_eq!(state.observe_peer_present("wg0", "a", None, 100), 100);
+        assert_eq!(state.observe_peer_present("wg0", "a", remote, 200), 200);
+        assert_eq!(state.observe_peer_present("wg0", "a", None, 300), 100);
+    }
+
+    #[test]
+    fn test_cached_peer() {
+        let mut state = ScrapeState::default();
+
+        assert!(state.cached_peer("wg0", "a", None).is_none());
+        assert_eq!(state.cached_peer_first_seen("wg0", "a", None, 100), 100);
+        assert_eq!(state.cached_peer_present("wg0", "a", None, 100), 100);
+        // nothing was recorded
+        assert!(state.peers_first_seen.is_empty());
+        assert!(state.peers_present_since.is_empty());
+
+        state.observe_peer("wg0", "a", None, 100, 100);
+        state.observe_peer("wg0", "a", None, 0, 0);
+        state.observe_peer_first_seen("wg0", "a", None, 200);
+        state.observe_peer_present("wg0", "a", None, 200);
+        state.end_scrape();
+
+        // the dump of the previous scrape is reused: same state, and the
+        // peer is not forgotten
+        assert_eq!(
+            state.cached_peer("wg0", "a", None).unwrap().counter_resets,
+            1
+        );
+        assert_eq!(state.cached_peer_first_seen("wg0", "a", None, 300), 200);
+        assert_eq!(state.cached_peer_present("wg0", "a", None, 300), 200);
+        state.end_scrape();
+        assert_eq!(state.peers.len(), 1);
+        assert_eq!(state.peers_first_seen.len(), 1);
+        assert_eq!(state.peers_present_since.len(), 1);
+
+        // the next dump is compared to the last one read from wg
+        assert_eq!(state.observe_peer("wg0", "a", None, 0, 0).counter_resets, 1);
+    }
+
+    #[test]
+    fn test_dump_cache() {
+        let mut cache = DumpCache::default();
+        let now = Instant::now();
+        let ttl = Duration::from_secs(10);
+
+        assert_eq!(cache.get(now, ttl), None);
+
+        let dumps = vec![
+            ("wg0".to_owned(), Ok("dump".to_owned())),
+            ("wg1".to_owned(), Err("failed".to_owned())),
+        ];
+        cache.set(now, dumps.clone());
+        assert_eq!(
+            cache.get(now + Duration::from_secs(9), ttl),
+            Some(&dumps as &[_])
+        );
+        assert_eq!(cache.get(now + ttl, ttl), None);
+    }
+}
@@ -0,0 +1,69 @@
+use crate::exporter_error::SiteExtractorParseError;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+// Derives the site of a peer from one octet (1 based) of its primary (first)
+// allowed IPv4. For example with octet:2 the peer with allowed ips
+// 10.42.0.2/32 belongs to site 42.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SiteExtractor {
+    octet: usize,
+}
+
+impl FromStr for SiteExtractor {
+    type Err = SiteExtractorParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let octet = s
+            .strip_prefix("octet:")
+            .and_then(|octet| octet.parse::<usize>().ok())
+            .filter(|octet| (1..=4).contains(octet))
+            .ok_or_else(|| SiteExtractorParseError::InvalidFormat { s: s.to_owned() })?;
+
+        Ok(SiteExtractor { octet })
+    }
+}
+
+impl SiteExtractor {
+    // Returns None if the primary allowed ip is missing or is not an IPv4.
+    pub fn extract(&self, allowed_ips: &str) -> Option<String> {
+        let primary = allowed_ips.split(',').next()?.trim();
+        let ip = primary.split('/').next()?;
+        let ip: Ipv4Addr = ip.parse().ok()?;
+
+        Some(ip.octets()[self.octet - 1].to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "octet:2".parse::<SiteExtractor>().unwrap(),
+            SiteExtractor { octet: 2 }
+        );
+        assert!("octet:0".parse::<SiteExtractor>().is_err());
+        assert!("octet:5".parse::<SiteExtractor>().is_err());
+        assert!("byte:2".parse::<SiteExtractor>().is_err());
+    }
+
+    #[test]
+    fn test_extract() {
+        let se: SiteExtractor = "octet:2".parse().unwrap();
+        assert_eq!(se.extract("10.42.0.2/32"), Some("42".to_owned()));
+        assert_eq!(
+            se.extract("10.43.0.2/32,fd86:ea04:::4/128"),
+            Some("43".to_owned())
+        );
+        // only the primary allowed ip is considered
+        assert_eq!(se.extract("fd86:ea04:::4/128,10.43.0.2/32"), None);
+        assert_eq!(se.extract("(none)"), None);
+        assert_eq!(se.extract(""), None);
+
+        let se: SiteExtractor = "octet:4".parse().unwrap();
+        assert_eq!(se.extract("10.42.0.2/32"), Some("2".to_owned()));
+    }
+}
@@ -0,0 +1,119 @@
+use crate::exporter::perform_request;
+use crate::http_server::{metrics_content_type, metrics_path};
+use crate::options::Options;
+use hyper::{Body, Request};
+use log::{debug, warn};
+use std::io::{BufRead, IsTerminal, Write};
+use std::sync::Arc;
+
+// Reads the request head (request line and headers) from the reader. Returns None
+// if there is no request at all (plain invocation).
+fn read_request_head<R: BufRead>(reader: &mut R) -> Result<Option<String>, std::io::Error> {
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line)? == 0 || request_line.trim().is_empty() {
+        return Ok(None);
+    }
+
+    // discard the headers, we do not need them
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    Ok(Some(request_line.trim().to_owned()))
+}
+
+fn http_response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+// Collects the metrics once and writes them to stdout.
+pub(crate) async fn print_metrics(
+    options: Arc<Options>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let request = Request::builder()
+        .uri(metrics_path(&options))
+        .body(Body::empty())?;
+    let metrics = perform_request(request, options).await?;
+
+    let mut stdout = std::io::stdout();
+    stdout.write_all(metrics.as_bytes())?;
+    stdout.flush()?;
+
+    Ok(())
+}
+
+// Serves a single request over stdin/stdout, inetd style. If there is no request
+// (for example the exporter is invoked by an SSH ForceCommand) only the metrics are
+// written to stdout.
+pub(crate) async fn serve_stdio(
+    options: Arc<Options>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let stdin = std::io::stdin();
+    let request_line = if stdin.is_terminal() {
+        None
+    } else {
+        read_request_head(&mut stdin.lock())?
+    };
+    debug!("serve_stdio request_line == {:?}", request_line);
+
+    let request = Request::builder()
+        .uri(metrics_path(&options))
+        .body(Body::empty())?;
+    let content_type = metrics_content_type(&options);
+    let result = perform_request(request, options).await;
+
+    let mut stdout = std::io::stdout();
+    match (request_line, result) {
+        (None, Ok(metrics)) => stdout.write_all(metrics.as_bytes())?,
+        (None, Err(e)) => return Err(e),
+        (Some(_), Ok(metrics)) => {
+            stdout.write_all(http_response("200 OK", content_type, &metrics).as_bytes())?
+        }
+        (Some(_), Err(e)) => {
+            warn!("internal server error == {:?}", e);
+            stdout.write_all(
+                http_response("500 Internal Server Error", "text/plain", &e.to_string()).as_bytes(),
+            )?
+        }
+    }
+    stdout.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_request_head() {
+        let mut reader =
+            Cursor::new("GET /metrics HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\r\n");
+        assert_eq!(
+            read_request_head(&mut reader).unwrap(),
+            Some("GET /metrics HTTP/1.1".to_owned())
+        );
+
+        let mut reader = Cursor::new("");
+        assert_eq!(read_request_head(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_http_response() {
+        assert_eq!(
+            http_response("200 OK", "text/plain", "pippo"),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\nConnection: close\r\n\r\npippo"
+        );
+    }
+}
@@ -1,20 +1,28 @@
 use crate::exporter_error::ExporterError;
 use crate::options::Options;
-use crate::wireguard_config::PeerEntryHashMap;
+use crate::peer_filter::PeerFilter;
+use crate::wireguard_config::{PeerEntry, PeerEntryHashMap};
 use crate::FriendlyDescription;
-use log::{debug, trace};
-use prometheus_exporter_base::{MetricType, PrometheusInstance, PrometheusMetric};
-use regex::Regex;
-use std::collections::HashMap;
+use chrono::{DateTime, SecondsFormat};
+use log::{debug, trace, warn};
+use num_traits::Num;
+use prometheus_exporter_base::{
+    MetricType, MissingValue, PrometheusInstance, PrometheusMetric, RenderToPrometheus,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::TryFrom;
-use std::fmt::Debug;
-use std::net::SocketAddr;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::fmt::{Debug, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const EMPTY: &str = "(none)";
+const UNSUPPORTED_JSON_VALUE: &str = "unsupported_json_value";
 
 #[derive(Default, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub(crate) struct SecureString(String);
+pub struct SecureString(String);
 
 #[cfg(feature = "leaky_log")]
 impl Debug for SecureString {
@@ -36,549 +44,4434 @@ impl From<&str> for SecureString {
     }
 }
 
+impl SecureString {
+    // the secret itself, never to be logged
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+// Converts a dump with non-standard field separators (as produced by some wg
+// wrappers) to the standard tab separated one. Consecutive separators are
+// treated as one. If the separator is a comma the allowed ips get split too,
+// so the extra columns of the remote lines are merged back.
+pub fn normalize_field_separator(
+    dump: &str,
+    separator: char,
+    has_interface_column: bool,
+) -> String {
+    let remote_columns = if has_interface_column { 9 } else { 8 };
+    let allowed_ips_column = remote_columns - 5;
+
+    let mut result = String::with_capacity(dump.len());
+    for line in dump.lines() {
+        let mut v: Vec<String> = line
+            .split(separator)
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_owned())
+            .collect();
+        if v.len() > remote_columns {
+            let extra = v.len() - remote_columns;
+            let allowed_ips = v
+                .drain(allowed_ips_column..=allowed_ips_column + extra)
+                .collect::<Vec<_>>()
+                .join(",");
+            v.insert(allowed_ips_column, allowed_ips);
+        }
+        result.push_str(&v.join("\t"));
+        result.push('\n');
+    }
+    result
+}
+
+const REDACTED: &str = "(redacted)";
+
+// Replaces the private key of the local interface lines with a placeholder
+// so it never reaches the parser or the logs. The private key is the
+// second column in the wg show all dump and the first one if the dump is
+// of a specific interface (the interface name column is missing).
+pub fn redact_private_keys(dump: &str, has_interface_column: bool) -> String {
+    let (local_columns, private_key_column) = if has_interface_column { (5, 1) } else { (4, 0) };
+
+    let mut result = String::with_capacity(dump.len());
+    for line in dump.lines() {
+        let mut v: Vec<&str> = line.split('\t').collect();
+        if v.len() == local_columns {
+            v[private_key_column] = REDACTED;
+        }
+        result.push_str(&v.join("\t"));
+        result.push('\n');
+    }
+    result
+}
+
 #[allow(dead_code)]
-#[derive(Default, Debug, Clone)]
-pub(crate) struct LocalEndpoint {
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct LocalEndpoint {
     pub public_key: String,
+    #[serde(skip)]
     pub private_key: SecureString,
     pub local_port: u16,
-    pub persistent_keepalive: bool,
+    // None if off
+    pub fwmark: Option<u32>,
 }
 
-#[derive(Debug, Clone)]
-pub(crate) struct RemoteEndpoint {
+#[derive(Debug, Clone, Serialize)]
+pub struct RemoteEndpoint {
     pub public_key: String,
     pub remote_ip: Option<String>,
     pub remote_port: Option<u16>,
     pub allowed_ips: String,
+    // only whether the peer has a preshared key, the key itself is never kept
+    pub has_preshared_key: bool,
     pub latest_handshake: u64,
     pub sent_bytes: u128,
     pub received_bytes: u128,
-    #[allow(dead_code)]
-    pub persistent_keepalive: bool,
+    // the keepalive interval in seconds, None if off
+    pub persistent_keepalive: Option<u16>,
+    // the remote agent the peer was collected from, None if collected locally
+    pub source: Option<String>,
+    // whether the dump was reused from the cache instead of running wg
+    #[serde(skip)]
+    pub from_cache: bool,
 }
 
-#[derive(Debug, Clone)]
-pub(crate) enum Endpoint {
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Endpoint {
     Local(LocalEndpoint),
     Remote(RemoteEndpoint),
 }
 
-fn to_option_string(s: &str) -> Option<String> {
-    if s == EMPTY {
-        None
+// what identifies an endpoint of an interface when merging: the peers
+// collected from different remote agents are different series
+fn merge_key(endpoint: &Endpoint) -> (bool, String, Option<String>) {
+    match endpoint {
+        Endpoint::Local(lep) => (false, lep.public_key.clone(), None),
+        Endpoint::Remote(ep) => (true, ep.public_key.clone(), ep.source.clone()),
+    }
+}
+
+// wg prints the fwmark in hex (for example 0xca6c) or off if not set.
+fn parse_fwmark(s: &str) -> Option<u32> {
+    if s == "off" || s == EMPTY {
+        return None;
+    }
+
+    let fwmark = match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse::<u32>(),
+    };
+    match fwmark {
+        Ok(0) => None,
+        Ok(fwmark) => Some(fwmark),
+        Err(_) => {
+            warn!("cannot parse the fwmark {}, ignoring it", s);
+            None
+        }
+    }
+}
+
+// RFC3339 (UTC) representation of the latest handshake, "never" if the
+// peer never connected
+fn format_handshake_time(latest_handshake: u64) -> String {
+    if latest_handshake == 0 {
+        return "never".to_owned();
+    }
+
+    i64::try_from(latest_handshake)
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .map(|dt| dt.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_else(|| "never".to_owned())
+}
+
+// An endpoint in the JSON output, along with its friendly description
+#[derive(Debug, Serialize)]
+struct JsonEndpoint<'a> {
+    #[serde(flatten)]
+    endpoint: Cow<'a, Endpoint>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    friendly_name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    friendly_json: Option<BTreeMap<&'a str, &'a serde_json::Value>>,
+}
+
+// what to do when more than one exported peer has the same friendly_name
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateNameAction {
+    // log a warning and export the names as they are
+    #[default]
+    Warn,
+    // append the first characters of the public key to the names
+    Suffix,
+    // fail the scrape
+    Fail,
+}
+
+// how the dump of an interface was obtained: either from
+// wg show all dump or from wg show <interface> dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScrapeMode {
+    All,
+    Explicit,
+}
+
+impl ScrapeMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ScrapeMode::All => "all",
+            ScrapeMode::Explicit => "explicit",
+        }
+    }
+}
+
+pub fn render_scrape_modes(
+    scrape_modes: &BTreeMap<String, ScrapeMode>,
+    options: &Options,
+) -> String {
+    let mut pc_interface_scrape_mode = PrometheusMetric::build()
+        .with_name("wireguard_interface_scrape_mode")
+        .with_metric_type(MetricType::Gauge)
+        .with_help("How the interface data was obtained (wg show all or wg show <interface>)")
+        .build();
+
+    for (interface, scrape_mode) in scrape_modes {
+        pc_interface_scrape_mode.render_and_append_instance(
+            &interface_instance(interface as &str, options)
+                .with_label("mode", scrape_mode.as_str())
+                .with_value(1),
+        );
+    }
+
+    pc_interface_scrape_mode.render()
+}
+
+// Every series starts with the --label labels, before its own labels.
+pub(crate) fn new_instance<N>(options: &Options) -> PrometheusInstance<'_, N, MissingValue>
+where
+    N: Num + std::fmt::Display + Debug,
+{
+    options
+        .static_labels
+        .iter()
+        .fold(PrometheusInstance::new(), |instance, (name, value)| {
+            instance.with_label(name.as_str(), value.as_str())
+        })
+}
+
+// The interface label comes first, unless --no_interface_label drops it.
+pub(crate) fn interface_instance<'a, N>(
+    interface: &'a str,
+    options: &'a Options,
+) -> PrometheusInstance<'a, N, MissingValue>
+where
+    N: Num + std::fmt::Display + Debug,
+{
+    let instance = new_instance(options);
+    if options.no_interface_label {
+        instance
     } else {
-        Some(s.to_owned())
+        instance.with_label("interface", interface)
     }
 }
 
-fn to_bool(s: &str) -> bool {
-    s != "off"
+// The labels of the series of a peer, rendered once and shared by all its
+// series: the --label labels, the interface label (see interface_instance)
+// and then the labels, in this order.
+fn peer_labels(interface: &str, labels: &[(&str, &str)], options: &Options) -> String {
+    let mut rendered = String::new();
+    // the static labels are escaped once, when --label is parsed
+    for (name, value) in &options.static_labels {
+        rendered.push(if rendered.is_empty() { '{' } else { ',' });
+        let _ = write!(rendered, "{}=\"{}\"", name, value);
+    }
+    // every other value is escaped here and nowhere else
+    let interface_label = Some(("interface", interface)).filter(|_| !options.no_interface_label);
+    for (name, value) in interface_label.into_iter().chain(labels.iter().copied()) {
+        rendered.push(if rendered.is_empty() { '{' } else { ',' });
+        let _ = write!(rendered, "{}=\"{}\"", name, escape_label_value(value));
+    }
+    if !rendered.is_empty() {
+        rendered.push('}');
+    }
+    rendered
 }
 
-#[derive(Debug, Clone)]
-pub(crate) struct WireGuard {
-    pub interfaces: HashMap<String, Vec<Endpoint>>,
+// Seconds since the latest handshake. Peers that never completed a handshake
+// report 0, as do handshakes in the future (clock skew).
+fn handshake_delay(latest_handshake: u64, now: u64) -> u64 {
+    if latest_handshake == 0 {
+        0
+    } else {
+        now.saturating_sub(latest_handshake)
+    }
 }
 
-impl TryFrom<&str> for WireGuard {
-    type Error = ExporterError;
+pub fn render_concurrent_scrapes(concurrent_scrapes: usize, options: &Options) -> String {
+    let mut pc_concurrent_scrapes = PrometheusMetric::build()
+        .with_name("wireguard_exporter_concurrent_scrapes")
+        .with_metric_type(MetricType::Gauge)
+        .with_help(
+            "Number of scrapes being served when this scrape was collected, this one included",
+        )
+        .build();
+    pc_concurrent_scrapes
+        .render_and_append_instance(&new_instance(options).with_value(concurrent_scrapes));
+    pc_concurrent_scrapes.render()
+}
 
-    fn try_from(input: &str) -> Result<Self, Self::Error> {
-        debug!("WireGuard::try_from({}) called", input);
-        let mut wg = WireGuard {
-            interfaces: HashMap::new(),
-        };
+pub fn render_scrape_duration(scrape_duration: Duration, options: &Options) -> String {
+    let mut pc_scrape_duration = PrometheusMetric::build()
+        .with_name("wireguard_scrape_duration_seconds")
+        .with_metric_type(MetricType::Gauge)
+        .with_help("Seconds taken to collect this scrape, running wg and rendering included")
+        .build();
+    pc_scrape_duration.render_and_append_instance(
+        &new_instance(options).with_value(scrape_duration.as_secs_f64()),
+    );
+    pc_scrape_duration.render()
+}
 
-        for line in input.lines() {
-            let v: Vec<&str> = line.split('\t').filter(|s| !s.is_empty()).collect();
-            debug!("WireGuard::try_from v == {:?}", v);
-
-            let endpoint = if v.len() == 5 {
-                // this is the local interface
-                Endpoint::Local(LocalEndpoint {
-                    public_key: v[1].to_owned(),
-                    private_key: v[2].into(),
-                    local_port: v[3].parse::<u16>().unwrap(),
-                    persistent_keepalive: to_bool(v[4]),
-                })
-            } else {
-                // remote endpoint
-                let public_key = v[1].to_owned();
-
-                let (remote_ip, remote_port) = if let Some(ip_and_port) = to_option_string(v[3]) {
-                    // this workaround fixes issue #10 (see
-                    // https://github.com/MindFlavor/prometheus_wireguard_exporter/issues/10).
-                    // Whenever it will be fixed upstream this code will be replaced with a
-                    // simple
-                    // let addr: SocketAddr = ip_and_port.parse::<SocketAddr>().unwrap();
-                    let re =
-                        Regex::new(r"^\[(?P<ip>[A-Fa-f0-9:]+)%(.*)\]:(?P<port>[0-9]+)$").unwrap();
-                    let addr: SocketAddr = re
-                        .replace_all(&ip_and_port, "[$ip]:$port")
-                        .parse::<SocketAddr>()
-                        .unwrap();
-
-                    (Some(addr.ip().to_string()), Some(addr.port()))
-                } else {
-                    (None, None)
-                };
+// The first 12 hex digits of the SHA-256 of the public key. Used in place of
+// the public key in the labels so the keys do not end up in Prometheus.
+fn hash_public_key(public_key: &str) -> String {
+    let mut hash = format!("{:x}", Sha256::digest(public_key.as_bytes()));
+    hash.truncate(12);
+    hash
+}
 
-                let allowed_ips = v[4].to_owned();
-
-                Endpoint::Remote(RemoteEndpoint {
-                    public_key,
-                    remote_ip,
-                    remote_port,
-                    allowed_ips,
-                    latest_handshake: v[5].parse::<u64>()?,
-                    received_bytes: v[6].parse::<u128>().unwrap(),
-                    sent_bytes: v[7].parse::<u128>().unwrap(),
-                    persistent_keepalive: to_bool(v[8]),
-                })
-            };
+// The value of the public_key label
+fn public_key_label<'a>(public_key: &'a str, options: &Options) -> Cow<'a, str> {
+    if options.hash_public_keys {
+        Cow::Owned(hash_public_key(public_key))
+    } else {
+        Cow::Borrowed(public_key)
+    }
+}
+
+// (none) and the empty string are no allowed ips
+pub fn allowed_ips_count(allowed_ips: &str) -> usize {
+    allowed_ips
+        .split(',')
+        .map(|allowed_ip| allowed_ip.trim())
+        .filter(|allowed_ip| !allowed_ip.is_empty() && *allowed_ip != EMPTY)
+        .count()
+}
 
-            trace!("WireGuard::try_from endpoint == {:?}", endpoint);
+// Sorts (by address and then prefix length) and deduplicates the allowed
+// ips so the same set always results in the same string.
+pub fn canonical_allowed_ips(allowed_ips: &str) -> String {
+    let mut v: Vec<(Option<(IpAddr, u8)>, &str)> = allowed_ips
+        .split(',')
+        .map(|ip_and_subnet| ip_and_subnet.trim())
+        .map(|ip_and_subnet| {
+            let parsed = ip_and_subnet.split_once('/').and_then(|(ip, subnet)| {
+                Some((ip.parse::<IpAddr>().ok()?, subnet.parse::<u8>().ok()?))
+            });
+            (parsed, ip_and_subnet)
+        })
+        .collect();
+    v.sort();
+    v.dedup();
 
-            if let Some(endpoints) = wg.interfaces.get_mut(v[0]) {
-                endpoints.push(endpoint);
-            } else {
-                let new_vec = vec![endpoint];
-                wg.interfaces.insert(v[0].to_owned(), new_vec);
+    v.into_iter()
+        .map(|(_, ip_and_subnet)| ip_and_subnet)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// A peer is considered connected if the latest handshake happened within
+// the handshake timeout. Peers that never completed a handshake are never
+// connected.
+fn is_connected(latest_handshake: u64, now: u64, handshake_timeout_seconds: u64) -> bool {
+    latest_handshake != 0 && now.saturating_sub(latest_handshake) <= handshake_timeout_seconds
+}
+
+// up is keyed by the interface passed to wg show (all if -i is not specified)
+pub fn render_up(
+    up: &BTreeMap<String, bool>,
+    source_up: &BTreeMap<String, bool>,
+    options: &Options,
+) -> String {
+    let mut pc_up = PrometheusMetric::build()
+        .with_name("wireguard_up")
+        .with_metric_type(MetricType::Gauge)
+        .with_help(
+            "1 if the WireGuard data of the interface was collected successfully, 0 otherwise",
+        )
+        .build();
+    for (interface, up) in up {
+        pc_up.render_and_append_instance(
+            &interface_instance(interface.as_str(), options).with_value(*up as u8),
+        );
+    }
+    // the remote agents
+    for (source, up) in source_up {
+        pc_up.render_and_append_instance(
+            &new_instance(options)
+                .with_label("source", source.as_str())
+                .with_value(*up as u8),
+        );
+    }
+    pc_up.render()
+}
+
+// Always 1, the labels tell which build produced the series. The git
+// revision is there only if PROMETHEUS_WIREGUARD_EXPORTER_GIT_SHA was set
+// at build time.
+pub fn render_build_info(options: &Options) -> String {
+    let mut pc_build_info = PrometheusMetric::build()
+        .with_name("wireguard_exporter_build_info")
+        .with_metric_type(MetricType::Gauge)
+        .with_help("Version of the exporter and of the rustc that built it, always 1")
+        .build();
+    let mut instance = new_instance(options)
+        .with_label("version", env!("CARGO_PKG_VERSION"))
+        .with_label("rustc", env!("PROMETHEUS_WIREGUARD_EXPORTER_RUSTC_VERSION"));
+    if let Some(revision) = option_env!("PROMETHEUS_WIREGUARD_EXPORTER_GIT_SHA") {
+        instance = instance.with_label("revision", revision);
+    }
+    pc_build_info.render_and_append_instance(&instance.with_value(1));
+    pc_build_info.render()
+}
+
+// Flattens a friendly_json value in (label, value) pairs: numbers, strings
+// and booleans are the value of the label, the entries of a nested object
+// are prefixed by the label and an underscore ({"site":{"id":3}} is
+// site_id="3") and so are the elements of an array with their index
+// ({"tags":["prod","eu"]} is tags_0="prod" and tags_1="eu"). Empty arrays
+// and objects have no label and null is the only unsupported value.
+fn flatten_json_value(
+    label: String,
+    value: &serde_json::Value,
+    skip_unsupported_json: bool,
+    flattened: &mut Vec<(String, String)>,
+) {
+    match value {
+        serde_json::Value::Number(number) => flattened.push((label, number.to_string())),
+        serde_json::Value::String(s) => flattened.push((label, s.to_owned())),
+        serde_json::Value::Bool(b) => flattened.push((label, b.to_string())),
+        serde_json::Value::Object(object) => {
+            for (key, value) in object {
+                flatten_json_value(
+                    format!("{}_{}", label, key),
+                    value,
+                    skip_unsupported_json,
+                    flattened,
+                );
             }
         }
+        serde_json::Value::Array(array) => {
+            for (idx, value) in array.iter().enumerate() {
+                flatten_json_value(
+                    format!("{}_{}", label, idx),
+                    value,
+                    skip_unsupported_json,
+                    flattened,
+                );
+            }
+        }
+        serde_json::Value::Null if skip_unsupported_json => {}
+        serde_json::Value::Null => flattened.push((label, UNSUPPORTED_JSON_VALUE.to_owned())),
+    }
+}
 
-        trace!("{:?}", wg);
-        Ok(wg)
+// rough size of the sent, received and handshake series of a peer, used to
+// size the rendered string once instead of growing it at every family
+const RENDERED_BYTES_PER_PEER: usize = 512;
+
+// Writes the HELP and TYPE lines of a metric family, separated from the
+// previous family by an empty line. The samples follow.
+fn write_header(rendered: &mut String, name: &str, metric_type: MetricType, help: &str) {
+    if !rendered.is_empty() {
+        rendered.push('\n');
     }
+    let _ = write!(
+        rendered,
+        "# HELP {} {}\n# TYPE {} {}\n",
+        name, help, name, metric_type
+    );
 }
 
-impl WireGuard {
-    pub fn merge(&mut self, merge_from: &WireGuard) {
-        for (interface_name, endpoints_to_merge) in merge_from.interfaces.iter() {
-            if let Some(endpoints) = self.interfaces.get_mut(interface_name as &str) {
-                endpoints.extend_from_slice(endpoints_to_merge);
-            } else {
-                let mut new_vec = Vec::new();
-                new_vec.extend_from_slice(endpoints_to_merge);
-                self.interfaces.insert(interface_name.to_owned(), new_vec);
-            }
+// Writes a sample, the instance renders its labels and value. The name of
+// a histogram series has its suffix (_bucket, _sum or _count) already.
+fn write_instance(rendered: &mut String, name: &str, instance: &dyn RenderToPrometheus) {
+    rendered.push_str(name);
+    rendered.push_str(&instance.render());
+    rendered.push('\n');
+}
+
+// Writes a sample with the labels rendered by peer_labels.
+fn write_sample(rendered: &mut String, name: &str, labels: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(rendered, "{}{} {}", name, labels, value);
+}
+
+// An exported peer. The families are written one after the other, so the
+// single pass over the peers keeps here what their series need.
+struct PeerSeries<'a> {
+    ep: &'a RemoteEndpoint,
+    labels: String,
+    // the descriptive labels too, with the info metric
+    info_labels: Option<String>,
+    // the labels plus handshake_time, with the handshake label
+    handshake_labels: Option<String>,
+    connected: bool,
+    // the values kept in the scrape state, if exported
+    counter_resets: Option<u64>,
+    bytes_per_second: Option<(f64, f64)>,
+    first_seen: Option<u64>,
+}
+
+// Writes a metric family with a sample for each peer that has a value.
+fn write_peer_family<N: std::fmt::Display>(
+    rendered: &mut String,
+    name: &str,
+    metric_type: MetricType,
+    help: &str,
+    peer_series: &[PeerSeries],
+    value: impl Fn(&PeerSeries) -> Option<N>,
+) {
+    write_header(rendered, name, metric_type, help);
+    for peer in peer_series {
+        if let Some(value) = value(peer) {
+            write_sample(rendered, name, &peer.labels, value);
         }
     }
+}
 
-    pub(crate) fn render_with_names(
-        &self,
-        pehm: Option<&PeerEntryHashMap>,
-        options: &Options,
-    ) -> String {
-        debug!(
-            "WireGuard::render_with_names(self == {:?}, pehm == {:?}, options == {:?} called",
-            self, pehm, options
-        );
+// upper bounds of the buckets of wireguard_handshake_age_seconds, +Inf excluded
+const HANDSHAKE_AGE_BUCKETS: [u64; 8] = [0, 30, 60, 120, 300, 600, 1800, 3600];
 
-        // these are the exported counters
-        let mut pc_sent_bytes_total = PrometheusMetric::build()
-            .with_name("wireguard_sent_bytes_total")
-            .with_metric_type(MetricType::Counter)
-            .with_help("Bytes sent to the peer")
-            .build();
-        let mut pc_received_bytes_total = PrometheusMetric::build()
-            .with_name("wireguard_received_bytes_total")
-            .with_metric_type(MetricType::Counter)
-            .with_help("Bytes received from the peer")
-            .build();
-        let mut pc_latest_handshake = PrometheusMetric::build()
-            .with_name("wireguard_latest_handshake_seconds")
-            .with_metric_type(MetricType::Gauge)
-            .with_help("UNIX timestamp seconds of the last handshake")
-            .build();
-        let mut pc_latest_handshake_delay = if options.export_latest_handshake_delay {
-            Some(
-                PrometheusMetric::build()
-                    .with_name("wireguard_latest_handshake_delay_seconds")
-                    .with_metric_type(MetricType::Gauge)
-                    .with_help("Seconds from the last handshake")
-                    .build(),
+// Whether the allowed ips have an IPv4 and an IPv6 address, in this order.
+fn allowed_ips_families(allowed_ips: &str) -> (bool, bool) {
+    allowed_ips
+        .split(',')
+        .fold((false, false), |(ipv4, ipv6), allowed_ip| {
+            (
+                ipv4 || allowed_ip.contains('.'),
+                ipv6 || allowed_ip.contains(':'),
             )
-        } else {
-            None
-        };
+        })
+}
 
-        // Here we make sure we process the interfaces in the
-        // lexicographical order.
-        // This is not stricly necessary but it ensures
-        // a consistent output between executions (the iter() function
-        // of HashMap does not guarantee any ordering).
-        // Prometheus does not care about ordering but humans do so
-        // we'll sort it beforehand. Being references the cost
-        // should be negligible anyway.
-        let mut interfaces_sorted: Vec<(&String, &Vec<Endpoint>)> = self
-            .interfaces
-            .iter()
-            .collect::<Vec<(&String, &Vec<Endpoint>)>>();
-        interfaces_sorted.sort_by(|a, b| a.0.partial_cmp(b.0).unwrap());
+fn write_peers_by_family(
+    rendered: &mut String,
+    peers_by_family: &BTreeMap<&str, (u64, u64)>,
+    options: &Options,
+) {
+    const NAME: &str = "wireguard_peers_by_family";
+    write_header(
+        rendered,
+        NAME,
+        MetricType::Gauge,
+        "Number of peers of the interface with IPv4 or IPv6 allowed ips",
+    );
 
-        for (interface, endpoints) in interfaces_sorted.into_iter() {
-            for endpoint in endpoints {
-                // only show remote endpoints
-                if let Endpoint::Remote(ep) = endpoint {
-                    debug!("WireGuard::render_with_names ep == {:?}", ep);
+    for (interface, (ipv4, ipv6)) in peers_by_family {
+        for (family, count) in [("ipv4", ipv4), ("ipv6", ipv6)] {
+            write_instance(
+                rendered,
+                NAME,
+                &interface_instance(interface, options)
+                    .with_label("family", family)
+                    .with_value(*count),
+            );
+        }
+    }
+}
 
-                    // we store in attributes_owned the ownership of the values in order to
-                    // store in attibutes their references. attributes_owned is onyl
-                    // needed for separate ip+subnet
-                    let mut attributes_owned: Vec<(String, String)> = Vec::new();
-                    let mut attributes: Vec<(&str, &str)> =
-                        vec![("interface", interface), ("public_key", &ep.public_key)];
+// The peers of every interface classified by how long they have been
+// continuously present. Every boundary of connection_age_buckets is the
+// upper (exclusive) bound of a bucket, the last bucket has the peers older
+// than the last boundary: with [3600, 86400] the buckets are <3600, <86400
+// and >=86400.
+fn write_peers_by_connection_age(
+    rendered: &mut String,
+    connection_ages: &BTreeMap<&str, Vec<u64>>,
+    options: &Options,
+) {
+    const NAME: &str = "wireguard_peers_by_connection_age";
+    write_header(
+        rendered,
+        NAME,
+        MetricType::Gauge,
+        "Number of peers of the interface by seconds of continuous presence",
+    );
 
-                    if options.separate_allowed_ips {
-                        let v_ip_and_subnet: Vec<(&str, &str)> = ep
-                            .allowed_ips
-                            .split(',')
-                            .map(|ip_and_subnet| {
-                                debug!(
-                                    "WireGuard::render_with_names ip_and_subnet == {:?}",
-                                    ip_and_subnet
-                                );
-                                let tokens: Vec<&str> = ip_and_subnet.split('/').collect();
-                                debug!("WireGuard::render_with_names tokens == {:?}", tokens);
-                                let addr = tokens[0];
-                                let subnet = tokens[1];
-                                (addr, subnet)
-                            })
-                            .collect();
+    for (interface, ages) in connection_ages {
+        let mut lower_bound = 0;
+        for upper_bound in &options.connection_age_buckets {
+            let bucket = format!("<{}", upper_bound);
+            write_instance(
+                rendered,
+                NAME,
+                &interface_instance(interface, options)
+                    .with_label("bucket", bucket.as_str())
+                    .with_value(
+                        ages.iter()
+                            .filter(|age| (lower_bound..*upper_bound).contains(*age))
+                            .count(),
+                    ),
+            );
+            lower_bound = *upper_bound;
+        }
+        let bucket = format!(">={}", lower_bound);
+        write_instance(
+            rendered,
+            NAME,
+            &interface_instance(interface, options)
+                .with_label("bucket", bucket.as_str())
+                .with_value(ages.iter().filter(|age| **age >= lower_bound).count()),
+        );
+    }
+}
 
-                        for (idx, (ip, subnet)) in v_ip_and_subnet.iter().enumerate() {
-                            attributes_owned
-                                .push((format!("allowed_ip_{}", idx), (*ip).to_string()));
-                            attributes_owned
-                                .push((format!("allowed_subnet_{}", idx), (*subnet).to_string()));
-                        }
-                        debug!(
-                            "WireGuard::render_with_names attributes == {:?}",
-                            attributes
-                        );
-                    } else {
-                        attributes.push(("allowed_ips", &ep.allowed_ips));
-                    }
+fn write_handshake_age_histogram(
+    rendered: &mut String,
+    handshake_ages: &BTreeMap<&str, Vec<u64>>,
+    options: &Options,
+) {
+    write_header(
+        rendered,
+        "wireguard_handshake_age_seconds",
+        MetricType::Histogram,
+        "Seconds since the latest handshake of the peers of the interface (peers that never had a handshake are excluded)",
+    );
 
-                    // let's add the friendly_name attribute if present
-                    // and has meaniningful value
-                    if let Some(pehm) = pehm {
-                        if let Some(ep_friendly_description) = pehm.get(&ep.public_key as &str) {
-                            if let Some(friendly_description) =
-                                &ep_friendly_description.friendly_description
-                            {
-                                match friendly_description {
-                                    FriendlyDescription::Name(name) => {
-                                        attributes.push(("friendly_name", name));
-                                    }
-                                    FriendlyDescription::Json(json) => {
-                                        // let's put them in a intermediate vector and then sort it
-                                        let mut v_temp = Vec::new();
+    for (interface, ages) in handshake_ages {
+        for bucket in HANDSHAKE_AGE_BUCKETS {
+            let le = bucket.to_string();
+            write_instance(
+                rendered,
+                "wireguard_handshake_age_seconds_bucket",
+                &interface_instance(interface, options)
+                    .with_label("le", le.as_str())
+                    .with_value(ages.iter().filter(|age| **age <= bucket).count()),
+            );
+        }
+        write_instance(
+            rendered,
+            "wireguard_handshake_age_seconds_bucket",
+            &interface_instance(interface, options)
+                .with_label("le", "+Inf")
+                .with_value(ages.len()),
+        );
+        write_instance(
+            rendered,
+            "wireguard_handshake_age_seconds_sum",
+            &interface_instance(interface, options).with_value(ages.iter().sum::<u64>()),
+        );
+        write_instance(
+            rendered,
+            "wireguard_handshake_age_seconds_count",
+            &interface_instance(interface, options).with_value(ages.len()),
+        );
+    }
+}
 
-                                        json.iter().for_each(|(header, value)| {
-                                            //attributes_owned
-                                            v_temp.push((
-                                                header.to_string(),
-                                                match value {
-                                                    serde_json::Value::Number(number) => {
-                                                        number.to_string()
-                                                    }
-                                                    serde_json::Value::String(s) => s.to_owned(),
-                                                    serde_json::Value::Bool(b) => b.to_string(),
-                                                    _ => panic!("unsupported json value"),
-                                                },
-                                            ));
-                                        });
+pub fn render_dump_line_counts(dump_line_counts: &DumpLineCounts, options: &Options) -> String {
+    let mut pc_dump_lines_parsed = PrometheusMetric::build()
+        .with_name("wireguard_dump_lines_parsed")
+        .with_metric_type(MetricType::Gauge)
+        .with_help("Number of lines of the wg dump of the interface parsed in this scrape")
+        .build();
+    let mut pc_dump_lines_skipped = PrometheusMetric::build()
+        .with_name("wireguard_dump_lines_skipped")
+        .with_metric_type(MetricType::Gauge)
+        .with_help(
+            "Number of malformed lines of the wg dump of the interface skipped in this scrape",
+        )
+        .build();
+    for (interface, dump_line_count) in dump_line_counts {
+        let instance = interface_instance(interface.as_str(), options);
+        pc_dump_lines_parsed
+            .render_and_append_instance(&instance.clone().with_value(dump_line_count.parsed));
+        pc_dump_lines_skipped
+            .render_and_append_instance(&instance.with_value(dump_line_count.skipped));
+    }
+    format!(
+        "{}\n{}",
+        pc_dump_lines_parsed.render(),
+        pc_dump_lines_skipped.render()
+    )
+}
 
-                                        v_temp.sort_by(|(k0, _), (k1, _)| k0.cmp(k1));
+pub fn render_draining(draining: bool, options: &Options) -> String {
+    let mut pc_exporter_draining = PrometheusMetric::build()
+        .with_name("wireguard_exporter_draining")
+        .with_metric_type(MetricType::Gauge)
+        .with_help("1 if the exporter is shutting down and draining the scrapes, 0 otherwise")
+        .build();
+    pc_exporter_draining
+        .render_and_append_instance(&new_instance(options).with_value(draining as u8));
+    pc_exporter_draining.render()
+}
 
-                                        v_temp
-                                            .into_iter()
-                                            .for_each(|item| attributes_owned.push(item));
-                                    }
-                                }
-                            }
-                        }
-                    }
+pub fn render_config_parse_errors(config_parse_errors: u64, options: &Options) -> String {
+    let mut pc_config_parse_errors = PrometheusMetric::build()
+        .with_name("wireguard_config_parse_errors_total")
+        .with_metric_type(MetricType::Counter)
+        .with_help(
+            "Friendly descriptions of the config files skipped because they cannot be parsed",
+        )
+        .build();
+    pc_config_parse_errors
+        .render_and_append_instance(&new_instance(options).with_value(config_parse_errors));
+    pc_config_parse_errors.render()
+}
 
-                    if options.export_remote_ip_and_port {
-                        if let Some(r_ip) = &ep.remote_ip {
-                            attributes.push(("remote_ip", r_ip));
-                        }
-                        if let Some(r_port) = &ep.remote_port {
-                            attributes_owned.push(("remote_port".to_string(), r_port.to_string()));
-                        }
-                    }
+pub fn render_invalid_public_keys(invalid_public_keys: u64, options: &Options) -> String {
+    let mut pc_invalid_public_keys = PrometheusMetric::build()
+        .with_name("wireguard_invalid_public_keys_total")
+        .with_metric_type(MetricType::Counter)
+        .with_help("Public keys that do not decode as 32 bytes of base64")
+        .build();
+    pc_invalid_public_keys
+        .render_and_append_instance(&new_instance(options).with_value(invalid_public_keys));
+    pc_invalid_public_keys.render()
+}
 
-                    for (label, val) in &attributes_owned {
-                        attributes.push((label, val));
-                    }
+// A WireGuard public key is 32 bytes, base64 encoded.
+pub fn is_valid_public_key(public_key: &str) -> bool {
+    base64::decode(public_key).is_ok_and(|key| key.len() == 32)
+}
 
-                    let mut instance = PrometheusInstance::new();
-                    for (h, v) in attributes {
-                        instance = instance.with_label(h, v);
-                    }
+// Escapes a label value as the exposition format requires. Most values
+// have nothing to escape and are returned as they are.
+pub fn escape_label_value(value: &str) -> Cow<'_, str> {
+    if value.contains(['\\', '"', '\n']) {
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+            .into()
+    } else {
+        Cow::Borrowed(value)
+    }
+}
 
-                    pc_latest_handshake_delay
-                        .as_mut()
-                        .map(|pc_latest_handshake_delay| {
-                            let earlier = UNIX_EPOCH + Duration::from_secs(ep.latest_handshake);
-                            let delta = SystemTime::now()
-                                .duration_since(earlier)
-                                .expect("time went backwards");
-                            pc_latest_handshake_delay.render_and_append_instance(
-                                &instance.clone().with_value(delta.as_secs() as u128),
-                            )
-                        });
+// [a-zA-Z_][a-zA-Z0-9_]*, without the __ prefix reserved to Prometheus.
+pub fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    !name.starts_with("__") && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// The labels of the series of the exporter, a --label with one of these
+// names would be rendered twice in the same series.
+const RESERVED_LABEL_NAMES: [&str; 25] = [
+    "allowed_ips",
+    "bucket",
+    "configured_endpoint",
+    "direction",
+    "endpoint",
+    "family",
+    "friendly_name",
+    "handshake_time",
+    "interface",
+    "le",
+    "mode",
+    "public_key",
+    "qos_class",
+    "remote_asn",
+    "remote_country",
+    "remote_host",
+    "remote_ip",
+    "remote_port",
+    "revision",
+    "rustc",
+    "server_public_key",
+    "site",
+    "source",
+    "state",
+    "version",
+];
 
-                    pc_sent_bytes_total
-                        .render_and_append_instance(&instance.clone().with_value(ep.sent_bytes))
-                        .render();
+// Whether --label cannot use the name. The separate allowed ips are
+// numbered too: allowed_ip_0, allowed_subnet_0 and so on.
+pub fn is_reserved_label_name(name: &str) -> bool {
+    RESERVED_LABEL_NAMES.contains(&name)
+        || name.starts_with("allowed_ip_")
+        || name.starts_with("allowed_subnet_")
+}
 
-                    pc_received_bytes_total
-                        .render_and_append_instance(&instance.clone().with_value(ep.received_bytes))
-                        .render();
+// Converts the rendered metrics to the OpenMetrics text format: the counter
+// families lose the _total suffix (their samples keep it), the blank lines
+// between the families are dropped and the output ends with # EOF. With
+// handshake_timestamps the latest handshake samples carry the handshake as
+// their timestamp, unless the peer never had one.
+pub fn to_openmetrics(rendered: &str, handshake_timestamps: bool) -> String {
+    const HANDSHAKE_METRIC: &str = "wireguard_latest_handshake_seconds";
 
-                    pc_latest_handshake.render_and_append_instance(
-                        &instance.with_value(ep.latest_handshake.into()),
-                    );
-                }
+    let counters: HashSet<&str> = rendered
+        .lines()
+        .filter_map(|line| line.strip_prefix("# TYPE "))
+        .filter_map(|metadata| metadata.strip_suffix(" counter"))
+        .collect();
+
+    let mut result = String::with_capacity(rendered.len() + 6);
+    for line in rendered.lines().filter(|line| !line.is_empty()) {
+        if let Some(metadata) = line
+            .strip_prefix("# HELP ")
+            .or_else(|| line.strip_prefix("# TYPE "))
+        {
+            let (name, rest) = metadata.split_once(' ').unwrap_or((metadata, ""));
+            if let Some(family) = name
+                .strip_suffix("_total")
+                .filter(|_| counters.contains(name))
+            {
+                result.push_str(&line[..7]);
+                result.push_str(family);
+                result.push(' ');
+                result.push_str(rest);
+                result.push('\n');
+                continue;
             }
         }
 
-        format!(
-            "{}\n{}\n{}{}",
-            pc_sent_bytes_total.render(),
-            pc_received_bytes_total.render(),
-            pc_latest_handshake.render(),
-            pc_latest_handshake_delay.map_or_else(
-                // this row adds pc_latest_handshake_delay only if configured
-                || "".to_owned(),
-                |pc_latest_handshake_delay| format!("\n{}", pc_latest_handshake_delay.render())
-            )
-        )
+        result.push_str(line);
+        if handshake_timestamps
+            && line
+                .strip_prefix(HANDSHAKE_METRIC)
+                .is_some_and(|rest| rest.starts_with(['{', ' ']))
+        {
+            if let Some(handshake) = line.rsplit(' ').next().filter(|value| *value != "0") {
+                result.push(' ');
+                result.push_str(handshake);
+            }
+        }
+        result.push('\n');
     }
+    result.push_str("# EOF\n");
+    result
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[derive(Debug, Default, Clone)]
+pub struct WireGuard {
+    pub interfaces: HashMap<String, Vec<Endpoint>>,
+}
 
-    const TEXT : &'static str = "wg0\t000q4qAC0ExW/BuGSmVR1nxH9JAXT6g9Wd3oEGy5lA=\t0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8=\t51820\toff
-wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t37.159.76.245:29159\t10.70.0.2/32,10.70.0.66/32\t1555771458\t10288508\t139524160\toff
-wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t0\t0\toff
-wg0\tL2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\t(none)\t(none)\t10.70.0.4/32\t0\t0\t0\toff
-wg0\tMdVOIPKt9K2MPj/sO2NlWQbOnFJ6L/qX80mmhQwsUlA=\t(none)\t(none)\t10.70.0.50/32\t0\t0\t0\toff
-wg2\tMdVOIPKt9K2MPj/sO2NlWQbOnFJcL/qX80mmhQwsUlA=\t(none)\t(none)\t10.70.5.50/32\t0\t0\t0\toff
-pollo\tYdVOIPKt9K2MPsO2NlWQbOnFJcL/qX80mmhQwsUlA=\t(none)\t(none)\t10.70.70.50/32\t0\t0\t0\toff
-wg0\t928vO9Lf4+Mo84cWu4k1oRyzf0AR7FTGoPKHGoTMSHk=\t(none)\t5.90.62.106:21741\t10.70.0.80/32\t1555344925\t283012\t6604620\toff
+// The endpoint the way wg show prints it, with the ipv6 addresses in brackets.
+pub fn format_endpoint(remote_ip: &str, remote_port: u16) -> String {
+    if remote_ip.contains(':') {
+        format!("[{}]:{}", remote_ip, remote_port)
+    } else {
+        format!("{}:{}", remote_ip, remote_port)
+    }
+}
+
+// Splits the endpoint of a peer in host and port. The host can be an IPv4
+// address, an IPv6 address in brackets, with or without the scope id (for
+// example [fe80::1%wg0]:51820, see issue #10) or a host name. The scope id
+// is dropped. Returns None if there is no endpoint or it cannot be parsed.
+fn parse_remote_endpoint(endpoint: &str) -> Option<(String, u16)> {
+    if endpoint == EMPTY {
+        return None;
+    }
+
+    let parsed = endpoint.rsplit_once(':').and_then(|(host, port)| {
+        let port = port.parse::<u16>().ok()?;
+
+        let host = match host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+            Some(ipv6) => {
+                let ipv6 = ipv6.split_once('%').map_or(ipv6, |(ipv6, _scope)| ipv6);
+                ipv6.parse::<Ipv6Addr>().ok()?.to_string()
+            }
+            None => match host.parse::<Ipv4Addr>() {
+                Ok(ipv4) => ipv4.to_string(),
+                Err(_) if is_host_name(host) => host.to_owned(),
+                Err(_) => return None,
+            },
+        };
+
+        Some((host, port))
+    });
+
+    if parsed.is_none() {
+        warn!("cannot parse the endpoint {}, ignoring it", endpoint);
+    }
+    parsed
+}
+
+fn is_host_name(host: &str) -> bool {
+    !host.is_empty()
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+// Parses a single (non empty) line of the dump, v holds its columns.
+fn parse_endpoint(line_no: usize, v: &[&str]) -> Result<Endpoint, ExporterError> {
+    // the columns are never echoed, any of them might be a private or a
+    // preshared key
+    let malformed = || ExporterError::MalformedDumpLine {
+        line_no,
+        columns: v.len(),
+    };
+
+    Ok(if v.len() == 5 {
+        // this is the local interface
+        // private-key, public-key, listen-port, fwmark
+        Endpoint::Local(LocalEndpoint {
+            public_key: v[2].to_owned(),
+            private_key: v[1].into(),
+            local_port: v[3].parse::<u16>().map_err(|_| malformed())?,
+            fwmark: parse_fwmark(v[4]),
+        })
+    } else if v.len() == 9 {
+        // remote endpoint
+        let public_key = v[1].to_owned();
+
+        let (remote_ip, remote_port) = match parse_remote_endpoint(v[3]) {
+            Some((remote_ip, remote_port)) => (Some(remote_ip), Some(remote_port)),
+            None => (None, None),
+        };
+
+        // a peer without allowed ips has no allowed_ips label at all
+        let allowed_ips = if v[4] == EMPTY {
+            String::new()
+        } else {
+            v[4].to_owned()
+        };
+
+        Endpoint::Remote(RemoteEndpoint {
+            public_key,
+            remote_ip,
+            remote_port,
+            allowed_ips,
+            has_preshared_key: v[2] != EMPTY,
+            latest_handshake: v[5].parse::<u64>().map_err(|_| malformed())?,
+            received_bytes: v[6].parse::<u128>().map_err(|_| malformed())?,
+            sent_bytes: v[7].parse::<u128>().map_err(|_| malformed())?,
+            persistent_keepalive: if v[8] == "off" {
+                None
+            } else {
+                Some(v[8].parse::<u16>().map_err(|_| malformed())?)
+            },
+            source: None,
+            from_cache: false,
+        })
+    } else {
+        return Err(malformed());
+    })
+}
+
+// Number of lines of the dump, per interface.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DumpLineCount {
+    pub parsed: u64,
+    pub skipped: u64,
+}
+
+pub type DumpLineCounts = BTreeMap<String, DumpLineCount>;
+
+impl TryFrom<&str> for WireGuard {
+    type Error = ExporterError;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        WireGuard::parse_dump(input, false).map(|(wg, _)| wg)
+    }
+}
+
+impl WireGuard {
+    // Parses the wg show all dump. If skip_malformed_lines is true the malformed
+    // lines are skipped (and counted) instead of failing the whole dump.
+    pub fn parse_dump(
+        input: &str,
+        skip_malformed_lines: bool,
+    ) -> Result<(WireGuard, DumpLineCounts), ExporterError> {
+        debug!("WireGuard::parse_dump({}) called", input);
+        let mut wg = WireGuard {
+            interfaces: HashMap::new(),
+        };
+        let mut dump_line_counts = DumpLineCounts::new();
+
+        for (idx, line) in input.lines().enumerate() {
+            let v: Vec<&str> = line.split('\t').filter(|s| !s.is_empty()).collect();
+            debug!("WireGuard::parse_dump v == {:?}", v);
+
+            if v.is_empty() {
+                continue;
+            }
+
+            let dump_line_count = dump_line_counts.entry(v[0].to_owned()).or_default();
+            let endpoint = match parse_endpoint(idx + 1, &v) {
+                Ok(endpoint) => {
+                    dump_line_count.parsed += 1;
+                    endpoint
+                }
+                Err(e) if skip_malformed_lines => {
+                    warn!("WireGuard::parse_dump skipping line: {}", e);
+                    dump_line_count.skipped += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            trace!("WireGuard::parse_dump endpoint == {:?}", endpoint);
+
+            if let Some(endpoints) = wg.interfaces.get_mut(v[0]) {
+                endpoints.push(endpoint);
+            } else {
+                let new_vec = vec![endpoint];
+                wg.interfaces.insert(v[0].to_owned(), new_vec);
+            }
+        }
+
+        trace!("{:?}", wg);
+        Ok((wg, dump_line_counts))
+    }
+
+    // marks every peer as collected from the remote agent
+    pub fn set_source(&mut self, source: &str) {
+        for endpoint in self.interfaces.values_mut().flatten() {
+            if let Endpoint::Remote(ep) = endpoint {
+                ep.source = Some(source.to_owned());
+            }
+        }
+    }
+
+    // marks every peer as read from a cached dump
+    pub fn set_from_cache(&mut self) {
+        for endpoint in self.interfaces.values_mut().flatten() {
+            if let Endpoint::Remote(ep) = endpoint {
+                ep.from_cache = true;
+            }
+        }
+    }
+
+    // A peer found in both, on the same interface and from the same source
+    // (for example with -i wg0 along with all), is kept once: the one with
+    // the most recent handshake. Otherwise it would be exported twice.
+    pub fn merge(&mut self, merge_from: &WireGuard) {
+        for (interface_name, endpoints_to_merge) in merge_from.interfaces.iter() {
+            let endpoints = self
+                .interfaces
+                .entry(interface_name.to_owned())
+                .or_default();
+            let mut positions: HashMap<_, usize> = endpoints
+                .iter()
+                .enumerate()
+                .map(|(position, endpoint)| (merge_key(endpoint), position))
+                .collect();
+
+            for endpoint in endpoints_to_merge {
+                match positions.get(&merge_key(endpoint)) {
+                    Some(&position) => {
+                        if let (Endpoint::Remote(existing), Endpoint::Remote(ep)) =
+                            (&endpoints[position], endpoint)
+                        {
+                            if ep.latest_handshake > existing.latest_handshake {
+                                endpoints[position] = endpoint.clone();
+                            }
+                        }
+                    }
+                    None => {
+                        positions.insert(merge_key(endpoint), endpoints.len());
+                        endpoints.push(endpoint.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    // logs the public keys, of the interfaces and of the peers, that are not
+    // valid and returns how many they are
+    pub fn invalid_public_keys(&self) -> u64 {
+        let mut invalid_public_keys = 0;
+
+        for (interface, endpoints) in self.interfaces.iter() {
+            for endpoint in endpoints {
+                let public_key = match endpoint {
+                    Endpoint::Local(lep) => &lep.public_key,
+                    Endpoint::Remote(ep) => &ep.public_key,
+                };
+                if !is_valid_public_key(public_key) {
+                    warn!(
+                        "invalid public key {:?} on interface {}",
+                        public_key, interface
+                    );
+                    invalid_public_keys += 1;
+                }
+            }
+        }
+
+        invalid_public_keys
+    }
+
+    // drops the specified interfaces along with their peers
+    pub fn exclude_interfaces(&mut self, interfaces: &[String]) {
+        self.interfaces
+            .retain(|interface, _| !interfaces.contains(interface));
+    }
+
+    // friendly names shared by more than one of the peers to export, along
+    // with the public keys of the peers sharing them
+    pub fn duplicate_friendly_names<'a>(
+        &self,
+        pehm: &'a PeerEntryHashMap,
+        peer_filter: &PeerFilter,
+    ) -> BTreeMap<&'a str, BTreeSet<&'a str>> {
+        let mut names: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+
+        for endpoint in self.interfaces.values().flatten() {
+            if let Endpoint::Remote(ep) = endpoint {
+                if !peer_filter.is_allowed(&ep.public_key) {
+                    continue;
+                }
+
+                // the same peer on more than one interface is not a duplicate
+                // since it's the same public key
+                if let Some(peer_entry) = pehm.get(&ep.public_key as &str) {
+                    if let Some(FriendlyDescription::Name(name)) = &peer_entry.friendly_description
+                    {
+                        names
+                            .entry(name)
+                            .or_default()
+                            .insert(&peer_entry.public_key);
+                    }
+                }
+            }
+        }
+
+        names.retain(|_, public_keys| public_keys.len() > 1);
+        names
+    }
+
+    // fails if there are duplicate friendly names and the scrape should fail
+    // because of them
+    pub fn check_duplicate_friendly_names(
+        &self,
+        pehm: &PeerEntryHashMap,
+        options: &Options,
+    ) -> Result<(), ExporterError> {
+        if options.on_duplicate_name != DuplicateNameAction::Fail {
+            return Ok(());
+        }
+
+        let peer_filter = options.peer_filter.read().unwrap();
+        match self
+            .duplicate_friendly_names(pehm, &peer_filter)
+            .into_iter()
+            .next()
+        {
+            Some((name, public_keys)) => Err(ExporterError::DuplicateFriendlyName {
+                name: name.to_owned(),
+                public_keys: public_keys.into_iter().map(str::to_owned).collect(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // The interfaces and their endpoints as JSON, keyed by interface. The
+    // private keys are never included.
+    pub fn render_json(
+        &self,
+        pehm: Option<&PeerEntryHashMap>,
+        options: &Options,
+    ) -> Result<String, serde_json::Error> {
+        let peer_filter = options.peer_filter.read().unwrap();
+        let mut interfaces: BTreeMap<&str, Vec<JsonEndpoint>> = BTreeMap::new();
+
+        for (interface, endpoints) in self.interfaces.iter() {
+            let json_endpoints = interfaces.entry(interface).or_default();
+            for endpoint in endpoints {
+                let mut json_endpoint = JsonEndpoint {
+                    endpoint: Cow::Borrowed(endpoint),
+                    friendly_name: None,
+                    friendly_json: None,
+                };
+
+                if let Endpoint::Remote(ep) = endpoint {
+                    if !peer_filter.is_allowed(&ep.public_key) {
+                        continue;
+                    }
+
+                    if options.hash_public_keys {
+                        json_endpoint.endpoint = Cow::Owned(Endpoint::Remote(RemoteEndpoint {
+                            public_key: hash_public_key(&ep.public_key),
+                            ..ep.clone()
+                        }));
+                    }
+
+                    match pehm
+                        .and_then(|pehm| pehm.get(&ep.public_key as &str))
+                        .and_then(|peer_entry| peer_entry.friendly_description.as_ref())
+                    {
+                        Some(FriendlyDescription::Name(name)) => {
+                            json_endpoint.friendly_name = Some(name)
+                        }
+                        Some(FriendlyDescription::Json(json)) => {
+                            json_endpoint.friendly_json =
+                                Some(json.iter().map(|(k, v)| (*k, v)).collect())
+                        }
+                        None => {}
+                    }
+                }
+
+                json_endpoints.push(json_endpoint);
+            }
+        }
+
+        serde_json::to_string(&interfaces)
+    }
+
+    // The number of peers in the config files and, for every one of them
+    // missing from wg on all the interfaces, wireguard_peer_down. Nothing in
+    // a config file ties it to an interface so a peer is down only if no
+    // interface has it, and wireguard_peer_down has no interface label.
+    fn write_configured_peers(
+        &self,
+        rendered: &mut String,
+        pehm: &PeerEntryHashMap,
+        peer_filter: &PeerFilter,
+        options: &Options,
+    ) {
+        let observed: HashSet<&str> = self
+            .interfaces
+            .values()
+            .flatten()
+            .filter_map(|endpoint| match endpoint {
+                Endpoint::Remote(ep) => Some(ep.public_key.as_str()),
+                Endpoint::Local(_) => None,
+            })
+            .collect();
+
+        let mut configured: Vec<&PeerEntry> = pehm
+            .values()
+            .filter(|peer_entry| peer_filter.is_allowed(&peer_entry.public_key))
+            .collect();
+        configured.sort_by(|a, b| a.public_key.cmp(&b.public_key));
+
+        write_header(
+            rendered,
+            "wireguard_peers_configured_total",
+            MetricType::Gauge,
+            "Number of peers in the config files",
+        );
+        write_instance(
+            rendered,
+            "wireguard_peers_configured_total",
+            &new_instance(options).with_value(configured.len()),
+        );
+
+        write_header(
+            rendered,
+            "wireguard_peer_down",
+            MetricType::Gauge,
+            "1 for each peer of the config files missing from every interface",
+        );
+        for peer_entry in configured {
+            if observed.contains(&peer_entry.public_key as &str) {
+                continue;
+            }
+
+            let public_key = public_key_label(&peer_entry.public_key, options);
+            let mut instance = new_instance(options).with_label("public_key", &public_key as &str);
+            let name = match &peer_entry.friendly_description {
+                Some(FriendlyDescription::Name(name)) => Some(escape_label_value(name)),
+                _ => None,
+            };
+            if let Some(name) = &name {
+                instance = instance.with_label("friendly_name", name as &str);
+            }
+            write_instance(rendered, "wireguard_peer_down", &instance.with_value(1));
+        }
+    }
+
+    pub fn render_with_names(&self, pehm: Option<&PeerEntryHashMap>, options: &Options) -> String {
+        debug!(
+            "WireGuard::render_with_names(self == {:?}, pehm == {:?}, options == {:?} called",
+            self, pehm, options
+        );
+
+        // Here we make sure we process the interfaces in the
+        // lexicographical order.
+        // This is not stricly necessary but it ensures
+        // a consistent output between executions (the iter() function
+        // of HashMap does not guarantee any ordering).
+        // Prometheus does not care about ordering but humans do so
+        // we'll sort it beforehand. Being references the cost
+        // should be negligible anyway.
+        let mut interfaces_sorted: Vec<(&String, &Vec<Endpoint>)> = self
+            .interfaces
+            .iter()
+            .collect::<Vec<(&String, &Vec<Endpoint>)>>();
+        interfaces_sorted.sort_by(|a, b| a.0.partial_cmp(b.0).unwrap());
+
+        let peer_filter = options.peer_filter.read().unwrap();
+        let mut scrape_state = options.scrape_state.lock().unwrap();
+        let remote_host_cache = options.remote_host_cache.lock().unwrap();
+        let mut geoip = options.geoip.lock().unwrap();
+
+        let duplicate_names = pehm
+            .map(|pehm| self.duplicate_friendly_names(pehm, &peer_filter))
+            .unwrap_or_default();
+        if options.on_duplicate_name == DuplicateNameAction::Warn {
+            for (name, public_keys) in duplicate_names.iter() {
+                warn!(
+                    "friendly_name {} is shared by the peers {:?}",
+                    name, public_keys
+                );
+            }
+        }
+
+        // grand totals across every peer of every interface, the filtered
+        // out peers included
+        let mut total_sent_bytes: u128 = 0;
+        let mut total_received_bytes: u128 = 0;
+
+        // distinct remote ips of every interface, only populated
+        // if the remote ips are exported
+        let mut unique_remote_ips: BTreeMap<&str, HashSet<&str>> = BTreeMap::new();
+
+        // (sent, received) bytes of every qos class of every interface
+        let mut qos_class_bytes: BTreeMap<(&str, &str), (u128, u128)> = BTreeMap::new();
+
+        // (sent, received, peers) of every interface, the filtered out
+        // peers included
+        let mut interface_bytes: BTreeMap<&str, (u128, u128, u64)> = BTreeMap::new();
+
+        // seconds since the latest handshake of the peers of every interface
+        let mut handshake_ages: BTreeMap<&str, Vec<u64>> = BTreeMap::new();
+
+        // seconds of continuous presence of the peers of every interface
+        let mut connection_ages: BTreeMap<&str, Vec<u64>> = BTreeMap::new();
+        // peers with IPv4 and with IPv6 allowed ips
+        let mut peers_by_family: BTreeMap<&str, (u64, u64)> = BTreeMap::new();
+
+        // the exported peers, in the order of their series
+        let mut peer_series: Vec<PeerSeries> = Vec::new();
+        // (interface, listen port, fwmark) of every interface, only populated
+        // if they are exported
+        let mut interface_ports: Vec<(&str, u16, Option<u32>)> = Vec::new();
+
+        // (connected, disconnected) peers of every interface
+        let mut peers_total: BTreeMap<&str, (u64, u64)> = BTreeMap::new();
+        // the interfaces with more peers than max_peers_per_interface
+        let mut peers_truncated: BTreeSet<&str> = BTreeSet::new();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_secs();
+        // monotonic, the rates are not affected by changes of the clock
+        let scrape_instant = Instant::now();
+
+        for (interface, endpoints) in interfaces_sorted.into_iter() {
+            // the per-interface overrides take precedence over the global options
+            let interface_options = options.interface_options(interface);
+            let separate_allowed_ips = interface_options
+                .and_then(|io| io.separate_allowed_ips)
+                .unwrap_or(options.separate_allowed_ips);
+            let export_remote_ip_and_port = !options.endpoint_label
+                && interface_options
+                    .and_then(|io| io.export_remote_ip_and_port)
+                    .unwrap_or(options.export_remote_ip_and_port);
+            let interface_label: &str = interface_options
+                .and_then(|io| io.alias.as_deref())
+                .unwrap_or(interface);
+
+            if options.export_unique_remote_ips && export_remote_ip_and_port {
+                unique_remote_ips.entry(interface_label).or_default();
+            }
+
+            if options.export_peers_by_family {
+                peers_by_family.entry(interface_label).or_default();
+            }
+
+            if options.export_listen_port_and_fwmark {
+                for endpoint in endpoints {
+                    if let Endpoint::Local(lep) = endpoint {
+                        interface_ports.push((interface_label, lep.local_port, lep.fwmark));
+                    }
+                }
+            }
+
+            // the public key of the interface itself, unique across hosts
+            let server_public_key = if options.export_server_public_key {
+                endpoints.iter().find_map(|endpoint| match endpoint {
+                    Endpoint::Local(lep) => Some(public_key_label(&lep.public_key, options)),
+                    Endpoint::Remote(_) => None,
+                })
+            } else {
+                None
+            };
+
+            // the peers are sorted by public key so the output does not
+            // change when the kernel reorders them
+            let mut endpoints_sorted: Vec<&Endpoint> = endpoints.iter().collect();
+            endpoints_sorted.sort_by_key(|endpoint| match endpoint {
+                Endpoint::Local(_) => None,
+                Endpoint::Remote(ep) => Some(&ep.public_key),
+            });
+
+            let mut exported_peers = 0;
+            for endpoint in endpoints_sorted {
+                // only show remote endpoints
+                if let Endpoint::Remote(ep) = endpoint {
+                    debug!("WireGuard::render_with_names ep == {:?}", ep);
+
+                    // the interface and grand totals are counters, so they sum
+                    // every peer: a peer skipped below must not make them go down
+                    total_sent_bytes += ep.sent_bytes;
+                    total_received_bytes += ep.received_bytes;
+                    let interface_bytes = interface_bytes.entry(interface_label).or_default();
+                    interface_bytes.0 += ep.sent_bytes;
+                    interface_bytes.1 += ep.received_bytes;
+                    interface_bytes.2 += 1;
+
+                    if !peer_filter.is_allowed(&ep.public_key) {
+                        debug!(
+                            "WireGuard::render_with_names skipping filtered out peer {}",
+                            ep.public_key
+                        );
+                        continue;
+                    }
+
+                    if options.export_peers_by_connection_age {
+                        let source = ep.source.as_deref();
+                        let present_since = if ep.from_cache {
+                            scrape_state.cached_peer_present(interface, &ep.public_key, source, now)
+                        } else {
+                            scrape_state.observe_peer_present(
+                                interface,
+                                &ep.public_key,
+                                source,
+                                now,
+                            )
+                        };
+                        connection_ages
+                            .entry(interface_label)
+                            .or_default()
+                            .push(now.saturating_sub(present_since));
+                    }
+
+                    if let Some(peers_by_family) = peers_by_family.get_mut(interface_label) {
+                        let (ipv4, ipv6) = allowed_ips_families(&ep.allowed_ips);
+                        peers_by_family.0 += ipv4 as u64;
+                        peers_by_family.1 += ipv6 as u64;
+                    }
+
+                    let connected = is_connected(
+                        ep.latest_handshake,
+                        now,
+                        options.handshake_timeout_seconds(interface),
+                    );
+                    let skip_never_connected =
+                        options.skip_never_connected && ep.latest_handshake == 0;
+                    if !skip_never_connected || options.count_never_connected {
+                        let peers_total = peers_total.entry(interface_label).or_default();
+                        if connected {
+                            peers_total.0 += 1;
+                        } else {
+                            peers_total.1 += 1;
+                        }
+                    }
+
+                    if options.connected_only && !connected {
+                        debug!(
+                            "WireGuard::render_with_names skipping disconnected peer {}",
+                            ep.public_key
+                        );
+                        continue;
+                    }
+
+                    if skip_never_connected {
+                        debug!(
+                            "WireGuard::render_with_names skipping never connected peer {}",
+                            ep.public_key
+                        );
+                        continue;
+                    }
+
+                    if options.max_peers_per_interface > 0
+                        && exported_peers >= options.max_peers_per_interface
+                    {
+                        if peers_truncated.insert(interface_label) {
+                            warn!(
+                                "interface {} has more than {} peers, the others are not exported",
+                                interface_label, options.max_peers_per_interface
+                            );
+                        }
+                        continue;
+                    }
+                    exported_peers += 1;
+
+                    // we store in attributes_owned the ownership of the values in order to
+                    // store in attibutes their references. attributes_owned is onyl
+                    // needed for separate ip+subnet
+                    let mut attributes_owned: Vec<(String, String)> = Vec::new();
+                    let allowed_ips;
+                    // the lookups in pehm keep using the real key
+                    let public_key = public_key_label(&ep.public_key, options);
+                    let mut attributes: Vec<(&str, &str)> = Vec::new();
+                    if let Some(source) = &ep.source {
+                        attributes.push(("source", source));
+                    }
+                    if let Some(server_public_key) = &server_public_key {
+                        attributes.push(("server_public_key", server_public_key));
+                    }
+                    attributes.push(("public_key", &public_key));
+                    // the labels above identify the peer, the ones below describe it
+                    let identifying_labels = attributes.len();
+
+                    if ep.allowed_ips.is_empty() {
+                        // no allowed ips, no labels
+                    } else if separate_allowed_ips {
+                        let v_ip_and_subnet: Vec<(&str, &str)> = ep
+                            .allowed_ips
+                            .split(',')
+                            .map(|ip_and_subnet| {
+                                debug!(
+                                    "WireGuard::render_with_names ip_and_subnet == {:?}",
+                                    ip_and_subnet
+                                );
+                                let tokens: Vec<&str> = ip_and_subnet.split('/').collect();
+                                debug!("WireGuard::render_with_names tokens == {:?}", tokens);
+                                let addr = tokens[0];
+                                let subnet = tokens[1];
+                                (addr, subnet)
+                            })
+                            .collect();
+
+                        for (idx, (ip, subnet)) in v_ip_and_subnet.iter().enumerate() {
+                            attributes_owned
+                                .push((format!("allowed_ip_{}", idx), (*ip).to_string()));
+                            attributes_owned
+                                .push((format!("allowed_subnet_{}", idx), (*subnet).to_string()));
+                        }
+                        debug!(
+                            "WireGuard::render_with_names attributes == {:?}",
+                            attributes
+                        );
+                    } else if options.canonical_allowed_ips {
+                        allowed_ips = canonical_allowed_ips(&ep.allowed_ips);
+                        attributes.push(("allowed_ips", &allowed_ips));
+                    } else {
+                        attributes.push(("allowed_ips", &ep.allowed_ips));
+                    }
+
+                    if let Some(site) = options
+                        .site_from_allowed_ip
+                        .and_then(|site_extractor| site_extractor.extract(&ep.allowed_ips))
+                    {
+                        attributes_owned.push(("site".to_owned(), site));
+                    }
+
+                    let qos_class = pehm
+                        .and_then(|pehm| pehm.get(&ep.public_key as &str))
+                        .and_then(|peer_entry| peer_entry.qos_class);
+
+                    // let's add the friendly_name attribute if present
+                    // and has meaniningful value
+                    if let Some(pehm) = pehm {
+                        if let Some(ep_friendly_description) = pehm.get(&ep.public_key as &str) {
+                            if let Some(friendly_description) =
+                                &ep_friendly_description.friendly_description
+                            {
+                                match friendly_description {
+                                    FriendlyDescription::Name(name) => {
+                                        let mut name: Cow<str> = Cow::Borrowed(name);
+                                        match &options.name_comma_replacement {
+                                            Some(replacement) if name.contains(',') => {
+                                                name = name.replace(',', replacement).into();
+                                            }
+                                            _ => {}
+                                        }
+                                        if options.on_duplicate_name == DuplicateNameAction::Suffix
+                                            && duplicate_names.contains_key(&name as &str)
+                                        {
+                                            name = format!(
+                                                "{} ({})",
+                                                name,
+                                                ep.public_key.get(..8).unwrap_or(&ep.public_key)
+                                            )
+                                            .into();
+                                        }
+                                        match name {
+                                            Cow::Borrowed(name) => {
+                                                attributes.push(("friendly_name", name))
+                                            }
+                                            Cow::Owned(name) => attributes_owned
+                                                .push(("friendly_name".to_owned(), name)),
+                                        }
+                                    }
+                                    FriendlyDescription::Json(json) => {
+                                        // let's put them in a intermediate vector and then sort it
+                                        let mut v_temp = Vec::new();
+
+                                        json.iter().for_each(|(header, value)| {
+                                            flatten_json_value(
+                                                header.to_string(),
+                                                value,
+                                                options.skip_unsupported_json,
+                                                &mut v_temp,
+                                            )
+                                        });
+
+                                        // the names are keys of the document, only the
+                                        // ones that make a label of their own are kept
+                                        v_temp.retain(|(name, _)| {
+                                            let usable = is_valid_label_name(name)
+                                                && !is_reserved_label_name(name)
+                                                && !options
+                                                    .static_labels
+                                                    .iter()
+                                                    .any(|(static_name, _)| static_name == name);
+                                            if !usable {
+                                                warn!(
+                                                    "friendly_json of peer {}: {} is not a valid label name or is used by the exporter, skipping it",
+                                                    ep.public_key, name
+                                                );
+                                            }
+                                            usable
+                                        });
+                                        v_temp.sort_by(|(k0, _), (k1, _)| k0.cmp(k1));
+
+                                        v_temp
+                                            .into_iter()
+                                            .for_each(|item| attributes_owned.push(item));
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    if export_remote_ip_and_port {
+                        if let Some(r_ip) = &ep.remote_ip {
+                            attributes.push(("remote_ip", r_ip));
+                            if let Some(remote_ips) = unique_remote_ips.get_mut(interface_label) {
+                                remote_ips.insert(r_ip);
+                            }
+                        }
+                        if let Some(r_port) = &ep.remote_port {
+                            attributes_owned.push(("remote_port".to_string(), r_port.to_string()));
+                        }
+                        // only present if resolve_remote_hosts found a name
+                        if let Some(remote_host) = ep
+                            .remote_ip
+                            .as_ref()
+                            .and_then(|r_ip| remote_host_cache.get(r_ip))
+                        {
+                            attributes_owned
+                                .push(("remote_host".to_string(), remote_host.to_owned()));
+                        }
+                        // only present if the databases of --geoip_db know the ip
+                        if let Some(r_ip) = ep.remote_ip.as_ref().filter(|_| geoip.is_enabled()) {
+                            let geoip_info = geoip.lookup(r_ip);
+                            if let Some(country) = &geoip_info.country {
+                                attributes_owned
+                                    .push(("remote_country".to_string(), country.to_owned()));
+                            }
+                            if let Some(asn) = &geoip_info.asn {
+                                attributes_owned.push(("remote_asn".to_string(), asn.to_owned()));
+                            }
+                        }
+                    }
+
+                    if options.endpoint_label {
+                        if let (Some(r_ip), Some(r_port)) = (&ep.remote_ip, ep.remote_port) {
+                            attributes_owned
+                                .push(("endpoint".to_string(), format_endpoint(r_ip, r_port)));
+                        }
+                    }
+
+                    // a peer that never connected has no remote endpoint:
+                    // export where it is supposed to connect, if configured
+                    if (export_remote_ip_and_port || options.endpoint_label)
+                        && ep.remote_ip.is_none()
+                    {
+                        if let Some(configured_endpoint) = pehm
+                            .and_then(|pehm| pehm.get(&ep.public_key as &str))
+                            .and_then(|peer_entry| peer_entry.endpoint)
+                        {
+                            attributes.push(("configured_endpoint", configured_endpoint));
+                        }
+                    }
+
+                    if let Some(qos_class) = qos_class {
+                        attributes.push(("qos_class", qos_class));
+
+                        let qos_class_bytes = qos_class_bytes
+                            .entry((interface_label, qos_class))
+                            .or_default();
+                        qos_class_bytes.0 += ep.sent_bytes;
+                        qos_class_bytes.1 += ep.received_bytes;
+                    }
+
+                    for (label, val) in &attributes_owned {
+                        attributes.push((label, val));
+                    }
+
+                    // with the info metric the descriptive labels are exported once,
+                    // the other series keep only the labels identifying the peer
+                    let info_labels = if options.export_peer_info {
+                        let info_labels = peer_labels(interface_label, &attributes, options);
+                        attributes.truncate(identifying_labels);
+                        Some(info_labels)
+                    } else {
+                        None
+                    };
+                    let labels = peer_labels(interface_label, &attributes, options);
+
+                    // the handshake_time label is added only to the latest
+                    // handshake metric as it changes at every handshake
+                    let handshake_time;
+                    let handshake_labels = if options.export_handshake_label {
+                        handshake_time = format_handshake_time(ep.latest_handshake);
+                        attributes.push(("handshake_time", &handshake_time));
+                        Some(peer_labels(interface_label, &attributes, options))
+                    } else {
+                        None
+                    };
+
+                    let counter_resets = if !options.export_counter_resets {
+                        None
+                    } else if ep.from_cache {
+                        Some(
+                            scrape_state
+                                .cached_peer(interface, &ep.public_key, ep.source.as_deref())
+                                .map_or(0, |peer_state| peer_state.counter_resets),
+                        )
+                    } else {
+                        let peer_state = scrape_state.observe_peer(
+                            interface,
+                            &ep.public_key,
+                            ep.source.as_deref(),
+                            ep.sent_bytes,
+                            ep.received_bytes,
+                        );
+                        Some(peer_state.counter_resets)
+                    };
+
+                    let bytes_per_second = if !options.export_bytes_per_second {
+                        None
+                    } else if ep.from_cache {
+                        Some(scrape_state.cached_peer_rates(
+                            interface,
+                            &ep.public_key,
+                            ep.source.as_deref(),
+                        ))
+                    } else {
+                        Some(scrape_state.observe_peer_rates(
+                            interface,
+                            &ep.public_key,
+                            ep.source.as_deref(),
+                            ep.sent_bytes,
+                            ep.received_bytes,
+                            scrape_instant,
+                        ))
+                    };
+
+                    let first_seen = if !options.export_first_seen {
+                        None
+                    } else if ep.from_cache {
+                        Some(scrape_state.cached_peer_first_seen(
+                            interface,
+                            &ep.public_key,
+                            ep.source.as_deref(),
+                            now,
+                        ))
+                    } else {
+                        Some(scrape_state.observe_peer_first_seen(
+                            interface,
+                            &ep.public_key,
+                            ep.source.as_deref(),
+                            now,
+                        ))
+                    };
+
+                    if options.export_handshake_age_histogram {
+                        let ages = handshake_ages.entry(interface_label).or_default();
+                        if ep.latest_handshake != 0 {
+                            ages.push(now.saturating_sub(ep.latest_handshake));
+                        }
+                    }
+
+                    peer_series.push(PeerSeries {
+                        ep,
+                        labels,
+                        info_labels,
+                        handshake_labels,
+                        connected,
+                        counter_resets,
+                        bytes_per_second,
+                        first_seen,
+                    });
+                }
+            }
+        }
+
+        // all the peers of this scrape have been observed
+        scrape_state.end_scrape();
+
+        // every metric family is written in the same string, sized for the
+        // three families every peer has
+        let peers: usize = self.interfaces.values().map(Vec::len).sum();
+        let mut rendered = String::with_capacity(peers * RENDERED_BYTES_PER_PEER);
+
+        // these are the exported counters
+        write_peer_family(
+            &mut rendered,
+            "wireguard_sent_bytes_total",
+            MetricType::Counter,
+            "Bytes sent to the peer",
+            &peer_series,
+            |peer| Some(peer.ep.sent_bytes),
+        );
+        write_peer_family(
+            &mut rendered,
+            "wireguard_received_bytes_total",
+            MetricType::Counter,
+            "Bytes received from the peer",
+            &peer_series,
+            |peer| Some(peer.ep.received_bytes),
+        );
+
+        write_header(
+            &mut rendered,
+            "wireguard_latest_handshake_seconds",
+            MetricType::Gauge,
+            "UNIX timestamp seconds of the last handshake",
+        );
+        for peer in &peer_series {
+            write_sample(
+                &mut rendered,
+                "wireguard_latest_handshake_seconds",
+                peer.handshake_labels.as_ref().unwrap_or(&peer.labels),
+                peer.ep.latest_handshake,
+            );
+        }
+
+        // the sums of the peer counters of every interface, the interfaces
+        // without peers are not in interface_bytes
+        write_header(
+            &mut rendered,
+            "wireguard_interface_sent_bytes_total",
+            MetricType::Counter,
+            "Bytes sent to all the peers of the interface",
+        );
+        for (interface, (sent_bytes, _, _)) in &interface_bytes {
+            write_instance(
+                &mut rendered,
+                "wireguard_interface_sent_bytes_total",
+                &interface_instance(interface, options).with_value(*sent_bytes),
+            );
+        }
+        write_header(
+            &mut rendered,
+            "wireguard_interface_received_bytes_total",
+            MetricType::Counter,
+            "Bytes received from all the peers of the interface",
+        );
+        for (interface, (_, received_bytes, _)) in &interface_bytes {
+            write_instance(
+                &mut rendered,
+                "wireguard_interface_received_bytes_total",
+                &interface_instance(interface, options).with_value(*received_bytes),
+            );
+        }
+
+        // the same sums across all the interfaces, rendered once
+        write_header(
+            &mut rendered,
+            "wireguard_total_sent_bytes_total",
+            MetricType::Counter,
+            "Bytes sent to all the peers of all the interfaces",
+        );
+        write_instance(
+            &mut rendered,
+            "wireguard_total_sent_bytes_total",
+            &new_instance(options).with_value(total_sent_bytes),
+        );
+        write_header(
+            &mut rendered,
+            "wireguard_total_received_bytes_total",
+            MetricType::Counter,
+            "Bytes received from all the peers of all the interfaces",
+        );
+        write_instance(
+            &mut rendered,
+            "wireguard_total_received_bytes_total",
+            &new_instance(options).with_value(total_received_bytes),
+        );
+
+        // the optional metrics are added only if configured
+        if options.export_peer_info {
+            write_header(
+                &mut rendered,
+                "wireguard_peer_info",
+                MetricType::Gauge,
+                "Descriptive labels of the peer, always 1",
+            );
+            for info_labels in peer_series
+                .iter()
+                .filter_map(|peer| peer.info_labels.as_ref())
+            {
+                write_sample(&mut rendered, "wireguard_peer_info", info_labels, 1);
+            }
+        }
+
+        if options.export_latest_handshake_delay {
+            write_peer_family(
+                &mut rendered,
+                "wireguard_latest_handshake_delay_seconds",
+                MetricType::Gauge,
+                "Seconds from the last handshake",
+                &peer_series,
+                |peer| Some(handshake_delay(peer.ep.latest_handshake, now)),
+            );
+        }
+
+        if options.export_counter_resets {
+            write_peer_family(
+                &mut rendered,
+                "wireguard_peer_counter_resets_total",
+                MetricType::Counter,
+                "Number of times the peer byte counters were reset since the exporter started",
+                &peer_series,
+                |peer| peer.counter_resets,
+            );
+        }
+
+        if options.export_bytes_per_second {
+            write_peer_family(
+                &mut rendered,
+                "wireguard_sent_bytes_per_second",
+                MetricType::Gauge,
+                "Bytes per second sent to the peer since the previous scrape",
+                &peer_series,
+                |peer| peer.bytes_per_second.map(|(sent, _)| sent),
+            );
+            write_peer_family(
+                &mut rendered,
+                "wireguard_received_bytes_per_second",
+                MetricType::Gauge,
+                "Bytes per second received from the peer since the previous scrape",
+                &peer_series,
+                |peer| peer.bytes_per_second.map(|(_, received)| received),
+            );
+        }
+
+        if options.export_first_seen {
+            write_peer_family(
+                &mut rendered,
+                "wireguard_peer_first_seen_timestamp_seconds",
+                MetricType::Gauge,
+                "UNIX timestamp seconds of the first scrape that included the peer since the exporter started",
+                &peer_series,
+                |peer| peer.first_seen,
+            );
+        }
+
+        if options.export_persistent_keepalive {
+            write_peer_family(
+                &mut rendered,
+                "wireguard_persistent_keepalive_seconds",
+                MetricType::Gauge,
+                "Persistent keepalive interval of the peer, 0 if off",
+                &peer_series,
+                |peer| Some(peer.ep.persistent_keepalive.unwrap_or(0)),
+            );
+        }
+
+        if options.export_preshared_key_presence {
+            write_peer_family(
+                &mut rendered,
+                "wireguard_peer_has_preshared_key",
+                MetricType::Gauge,
+                "1 if the peer has a preshared key, 0 otherwise",
+                &peer_series,
+                |peer| Some(peer.ep.has_preshared_key as u8),
+            );
+        }
+
+        if options.export_allowed_ips_count {
+            write_peer_family(
+                &mut rendered,
+                "wireguard_peer_allowed_ips_count",
+                MetricType::Gauge,
+                "Number of allowed ips of the peer",
+                &peer_series,
+                |peer| Some(allowed_ips_count(&peer.ep.allowed_ips)),
+            );
+        }
+
+        if options.export_peer_connected {
+            write_peer_family(
+                &mut rendered,
+                "wireguard_peer_connected",
+                MetricType::Gauge,
+                "1 if the latest handshake of the peer is within the handshake timeout, 0 otherwise",
+                &peer_series,
+                |peer| Some(peer.connected as u8),
+            );
+        }
+
+        if options.export_listen_port_and_fwmark {
+            write_header(
+                &mut rendered,
+                "wireguard_interface_listen_port",
+                MetricType::Gauge,
+                "UDP port the interface listens on",
+            );
+            for (interface, listen_port, _) in &interface_ports {
+                write_instance(
+                    &mut rendered,
+                    "wireguard_interface_listen_port",
+                    &interface_instance(interface, options).with_value(*listen_port),
+                );
+            }
+            write_header(
+                &mut rendered,
+                "wireguard_interface_fwmark",
+                MetricType::Gauge,
+                "fwmark of the packets sent by the interface, missing if off",
+            );
+            for (interface, _, fwmark) in &interface_ports {
+                if let Some(fwmark) = fwmark {
+                    write_instance(
+                        &mut rendered,
+                        "wireguard_interface_fwmark",
+                        &interface_instance(interface, options).with_value(*fwmark),
+                    );
+                }
+            }
+        }
+
+        if !options.expected_allowed_ips.is_empty() {
+            // peers missing from the baseline are not checked
+            write_peer_family(
+                &mut rendered,
+                "wireguard_peer_allowed_ips_unexpected",
+                MetricType::Gauge,
+                "1 if the allowed ips of the peer differ from the expected ones, 0 otherwise",
+                &peer_series,
+                |peer| {
+                    options.expected_allowed_ips.get(&peer.ep.public_key).map(
+                        |expected_allowed_ips| {
+                            (canonical_allowed_ips(&peer.ep.allowed_ips) != *expected_allowed_ips)
+                                as u8
+                        },
+                    )
+                },
+            );
+        }
+
+        // only meaningful if we have the config files
+        if let (true, Some(pehm)) = (options.export_unnamed_peers, pehm) {
+            write_peer_family(
+                &mut rendered,
+                "wireguard_peer_unnamed",
+                MetricType::Gauge,
+                "1 for each peer missing from the config files",
+                &peer_series,
+                |peer| Some(1).filter(|_| !pehm.contains_key(&peer.ep.public_key as &str)),
+            );
+        }
+
+        // only meaningful if we have the config files
+        if let (true, Some(pehm)) = (options.export_configured_peers, pehm) {
+            self.write_configured_peers(&mut rendered, pehm, &peer_filter, options);
+        }
+
+        if options.connected_only || options.skip_never_connected {
+            write_header(
+                &mut rendered,
+                "wireguard_peers_total",
+                MetricType::Gauge,
+                "Number of peers of the interface by connection state",
+            );
+            for (interface, (connected, disconnected)) in &peers_total {
+                for (state, count) in [("connected", connected), ("disconnected", disconnected)] {
+                    write_instance(
+                        &mut rendered,
+                        "wireguard_peers_total",
+                        &interface_instance(interface, options)
+                            .with_label("state", state)
+                            .with_value(*count),
+                    );
+                }
+            }
+        }
+
+        if !peers_truncated.is_empty() {
+            write_header(
+                &mut rendered,
+                "wireguard_peers_truncated",
+                MetricType::Gauge,
+                "1 if the interface has more peers than --max_peers_per_interface",
+            );
+            for interface in &peers_truncated {
+                write_instance(
+                    &mut rendered,
+                    "wireguard_peers_truncated",
+                    &interface_instance(interface, options).with_value(1),
+                );
+            }
+        }
+
+        if !qos_class_bytes.is_empty() {
+            write_header(
+                &mut rendered,
+                "wireguard_qos_class_bytes_total",
+                MetricType::Counter,
+                "Bytes sent to and received from the peers of the qos class",
+            );
+            for ((interface, qos_class), (sent_bytes, received_bytes)) in &qos_class_bytes {
+                let qos_class = escape_label_value(qos_class);
+                for (direction, bytes) in [("sent", sent_bytes), ("received", received_bytes)] {
+                    write_instance(
+                        &mut rendered,
+                        "wireguard_qos_class_bytes_total",
+                        &interface_instance(interface, options)
+                            .with_label("qos_class", &qos_class as &str)
+                            .with_label("direction", direction)
+                            .with_value(*bytes),
+                    );
+                }
+            }
+        }
+
+        if options.export_interfaces_total {
+            write_header(
+                &mut rendered,
+                "wireguard_interfaces_total",
+                MetricType::Gauge,
+                "Number of interfaces",
+            );
+            write_instance(
+                &mut rendered,
+                "wireguard_interfaces_total",
+                &new_instance(options).with_value(self.interfaces.len()),
+            );
+        }
+
+        if !unique_remote_ips.is_empty() {
+            write_header(
+                &mut rendered,
+                "wireguard_interface_unique_remote_ips",
+                MetricType::Gauge,
+                "Number of distinct remote ips of the peers of the interface",
+            );
+            for (interface, remote_ips) in &unique_remote_ips {
+                write_instance(
+                    &mut rendered,
+                    "wireguard_interface_unique_remote_ips",
+                    &interface_instance(interface, options).with_value(remote_ips.len()),
+                );
+            }
+        }
+
+        if options.export_handshake_age_histogram {
+            write_handshake_age_histogram(&mut rendered, &handshake_ages, options);
+        }
+
+        if options.export_peers_by_family {
+            write_peers_by_family(&mut rendered, &peers_by_family, options);
+        }
+
+        if options.export_peers_by_connection_age {
+            write_peers_by_connection_age(&mut rendered, &connection_ages, options);
+        }
+
+        if options.export_avg_bytes_per_peer {
+            // interfaces without peers are not in the map so there
+            // is no division by zero
+            write_header(
+                &mut rendered,
+                "wireguard_interface_avg_sent_bytes_per_peer",
+                MetricType::Gauge,
+                "Bytes sent to the peers of the interface divided by the number of peers",
+            );
+            for (interface, (sent_bytes, _, peers)) in &interface_bytes {
+                write_instance(
+                    &mut rendered,
+                    "wireguard_interface_avg_sent_bytes_per_peer",
+                    &interface_instance(interface, options)
+                        .with_value(*sent_bytes as f64 / *peers as f64),
+                );
+            }
+            write_header(
+                &mut rendered,
+                "wireguard_interface_avg_received_bytes_per_peer",
+                MetricType::Gauge,
+                "Bytes received from the peers of the interface divided by the number of peers",
+            );
+            for (interface, (_, received_bytes, peers)) in &interface_bytes {
+                write_instance(
+                    &mut rendered,
+                    "wireguard_interface_avg_received_bytes_per_peer",
+                    &interface_instance(interface, options)
+                        .with_value(*received_bytes as f64 / *peers as f64),
+                );
+            }
+        }
+
+        if options.export_name_changes {
+            if let Some(pehm) = pehm {
+                write_header(
+                    &mut rendered,
+                    "wireguard_peer_name_changed_total",
+                    MetricType::Counter,
+                    "Number of times the friendly name of the peer changed in the config files since the exporter started",
+                );
+
+                // sorted for the same reason of the interfaces
+                let mut peer_entries = pehm.values().collect::<Vec<_>>();
+                peer_entries.sort_by(|a, b| a.public_key.cmp(&b.public_key));
+
+                for peer_entry in peer_entries
+                    .into_iter()
+                    .filter(|peer_entry| peer_filter.is_allowed(&peer_entry.public_key))
+                {
+                    // the friendly_name or, for friendly_json, the whole json
+                    let name =
+                        peer_entry
+                            .friendly_description
+                            .as_ref()
+                            .map(|friendly_description| match friendly_description {
+                                FriendlyDescription::Name(name) => name.to_string(),
+                                FriendlyDescription::Json(json) => {
+                                    serde_json::to_string(&json.iter().collect::<BTreeMap<_, _>>())
+                                        .unwrap_or_default()
+                                }
+                            });
+                    let peer_name_state =
+                        scrape_state.observe_peer_name(&peer_entry.public_key, name.as_deref());
+
+                    write_instance(
+                        &mut rendered,
+                        "wireguard_peer_name_changed_total",
+                        &new_instance(options)
+                            .with_label(
+                                "public_key",
+                                &public_key_label(&peer_entry.public_key, options) as &str,
+                            )
+                            .with_value(peer_name_state.name_changes),
+                    );
+                }
+            }
+        }
+
+        if options.export_configured_allowed_ips {
+            if let Some(pehm) = pehm {
+                write_header(
+                    &mut rendered,
+                    "wireguard_peer_configured_allowed_ips",
+                    MetricType::Gauge,
+                    "AllowedIPs of the peer as specified in the config file",
+                );
+
+                // sorted for the same reason of the interfaces
+                let mut peer_entries = pehm.values().collect::<Vec<_>>();
+                peer_entries.sort_by(|a, b| a.public_key.cmp(&b.public_key));
+
+                for peer_entry in peer_entries
+                    .into_iter()
+                    .filter(|peer_entry| peer_filter.is_allowed(&peer_entry.public_key))
+                    // the names file entries have no AllowedIPs
+                    .filter(|peer_entry| !peer_entry.allowed_ips.is_empty())
+                {
+                    write_instance(
+                        &mut rendered,
+                        "wireguard_peer_configured_allowed_ips",
+                        &new_instance(options)
+                            .with_label(
+                                "public_key",
+                                &public_key_label(&peer_entry.public_key, options) as &str,
+                            )
+                            .with_label("allowed_ips", peer_entry.allowed_ips)
+                            .with_value(1),
+                    );
+                }
+            }
+        }
+
+        if let Some(maintenance_window) = &options.maintenance_window {
+            write_header(
+                &mut rendered,
+                "wireguard_maintenance_active",
+                MetricType::Gauge,
+                "1 if the configured maintenance window is active, 0 otherwise",
+            );
+            write_instance(
+                &mut rendered,
+                "wireguard_maintenance_active",
+                &new_instance(options).with_value(maintenance_window.is_active() as u8),
+            );
+        }
+
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT : &str = "wg0\t0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8=\t000q4qAC0ExW/BuGSmVR1nxH9JAXT6g9Wd3oEGy5lA=\t51820\toff
+wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t37.159.76.245:29159\t10.70.0.2/32,10.70.0.66/32\t1555771458\t10288508\t139524160\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t0\t0\toff
+wg0\tL2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\t(none)\t(none)\t10.70.0.4/32\t0\t0\t0\toff
+wg0\tMdVOIPKt9K2MPj/sO2NlWQbOnFJ6L/qX80mmhQwsUlA=\t(none)\t(none)\t10.70.0.50/32\t0\t0\t0\toff
+wg2\tMdVOIPKt9K2MPj/sO2NlWQbOnFJcL/qX80mmhQwsUlA=\t(none)\t(none)\t10.70.5.50/32\t0\t0\t0\toff
+pollo\tYdVOIPKt9K2MPsO2NlWQbOnFJcL/qX80mmhQwsUlA=\t(none)\t(none)\t10.70.70.50/32\t0\t0\t0\toff
+wg0\t928vO9Lf4+Mo84cWu4k1oRyzf0AR7FTGoPKHGoTMSHk=\t(none)\t5.90.62.106:21741\t10.70.0.80/32\t1555344925\t283012\t6604620\toff
+";
+
+    const TEXT_ISSUE_19 : &str = "wg0\twJyy0Xcqk76dNQI8bnzaQvrtle5Od+wft1RBK3fC8kc=\tVfjHGauX8OxotDMm2vi3JdwOUTDsFbxCnyInJ/wAXlk=\t51820\toff
+wg0\t923V/iAdcz8BcqB0Xo6pDJzARGBJCQ6fWe+peixQyB4=\t(none)\t10.211.123.112:51820\t10.90.0.10/32,10.0.1.0/24\t0\t0\t0\toff
+wg0\t9M1fhLa9sIlT39z+SI/0a5H3mNSHYmM+NGA6sirD2nU=\t(none)\t10.211.123.113:51820\t10.90.0.3/32,10.198.171.0/24\t0\t0\t0\toff
+wg0\tgnRKXngxSppcYegsg38kEFn5Lmk4NcnRXLcZTtg2A2E=\t(none)\t10.211.123.114:51820\t10.90.0.11/32,10.189.143.0/24\t0\t0\t0\toff
+wg0\tYW7NBDEPXuW9GQlFWFzpgrivMxzdR55M8VOTX+E0thw=\t(none)\t10.211.123.115:51820\t10.90.0.12/32,10.0.2.0/24\t0\t0\t0\toff
+wg0\teVfg1BH1hcteASE16+TjShxAJNyFLQ9QIcnCaylD/AA=\t(none)\t10.211.123.116:51820\t10.90.0.13/32,10.0.3.0/24\t0\t0\t0\toff
+wg0\tlh1h+tWPahB+PAWW62ExHVVrOp9IwdjYwaGnPIXgNwY=\t(none)\t10.211.123.117:51820\t10.90.0.9/32,10.0.4.0/24\t0\t0\t0\toff
+wg0\tVQIrk1BiBfbOkkKGPiarEvhA4iPuszIL1lddvvFDvE0=\t(none)\t10.211.123.118:51820\t10.90.0.8/32,10.0.5.0/24\t0\t0\t0\toff
+wg0\tSMp58OwCNnwlzu+OdpA8xiNJzOwbl2gdMaD9CSZCC24=\t(none)\t10.211.123.119:51820\t10.90.0.14/32,10.0.6.0/24\t0\t0\t0\toff
+wg0\t+0+yMIHVCqyIf4by1gxAjqQ92iKv3bQ/JctNVUEpSlU=\t(none)\t10.211.123.120:51820\t10.90.0.7/32,10.0.7.0/24\t0\t0\t0\toff
+wg0\t2StYqQY9tyVkGcO4ykKTiTu6AQp/yIYx8I4hwBLO1jA=\t(none)\t10.211.123.121:51820\t10.90.0.15/32,10.0.8.0/24\t0\t0\t0\toff
+wg0\tqa0AMD2puDBBrs8NYQ+skIrIi/Q5NgQRZLEh5p80Mnc=\t(none)\t10.211.123.122:51820\t10.90.0.1/32,10.0.10.0/24\t0\t0\t0\toff
+wg0\tYwObmKDK4lfr5F6FHqJhDy9nkUQwbuK8wh4ac2VNSEU=\t(none)\t10.211.123.123:51820\t10.90.0.2/32,10.0.11.0/24\t0\t0\t0\toff
+wg0\tq07dm9n1UMLFbG6Dh+BNztCt7jVb9VtpVshQEf580kA=\t(none)\t10.211.123.124:51820\t10.90.0.6/32,10.0.13.0/24\t0\t0\t0\toff
+wg0\tyZOoC2t6pBcXvoczuiJqrQ+8CYvJCzcq8aqyp+APaAE=\t(none)\t10.211.123.125:51820\t10.90.0.16/32,10.0.14.0/24\t1574770531\t1232856\t12306832\toff
+wg0\tyjeBkrZqUThSSHySFzWCjxAH8cxtiWSI2I8JFD6t1UM=\t(none)\t10.211.123.126:51820\t10.90.0.5/32\t1574770705\t18576788764\t10642564136\toff
+wg0\tHtOSi37ALMnSkeAFqeWYZqlBnZqAJERhb5o/i3ZPEFI=\t(none)\t10.211.123.127:51820\t10.90.0.17/32\t1574770783\t62592693520\t1439257868\toff
+wg0\tsUsR6xufQQ8Tf0FuyY9tfEeYdhVMeFelr4ZMUrj+B0E=\t(none)\t10.211.123.128:51820\t10.90.0.18/32\t1574770693\t75066288152\t1624251784\toff";
+
+    #[test]
+    fn test_parse_issue_19() {
+        println!("starting debug");
+        let a = WireGuard::try_from(TEXT_ISSUE_19).unwrap();
+        assert!(a.interfaces.len() == 1);
+        assert!(a.interfaces["wg0"].len() == 18);
+
+        let e1 = match &a.interfaces["wg0"][1] {
+            Endpoint::Local(_) => panic!(),
+            Endpoint::Remote(re) => re,
+        };
+        assert_eq!(
+            e1.public_key,
+            "923V/iAdcz8BcqB0Xo6pDJzARGBJCQ6fWe+peixQyB4="
+        );
+
+        assert_eq!(e1.remote_ip, Some("10.211.123.112".to_owned()));
+        assert_eq!(e1.allowed_ips, "10.90.0.10/32,10.0.1.0/24".to_owned());
+
+        let e17 = match &a.interfaces["wg0"][17] {
+            Endpoint::Local(_) => panic!(),
+            Endpoint::Remote(re) => re,
+        };
+        assert_eq!(
+            e17.public_key,
+            "sUsR6xufQQ8Tf0FuyY9tfEeYdhVMeFelr4ZMUrj+B0E="
+        );
+
+        assert_eq!(e17.remote_ip, Some("10.211.123.128".to_owned()));
+        assert_eq!(e17.allowed_ips, "10.90.0.18/32".to_owned());
+        assert_eq!(e17.latest_handshake, 1574770693);
+        assert_eq!(e17.sent_bytes, 1624251784);
+        assert_eq!(e17.received_bytes, 75066288152);
+
+        let pe = PeerEntryHashMap::new();
+
+        let options = Options {
+            verbose: true,
+            prepend_sudo: true,
+            separate_allowed_ips: true,
+            extract_names_config_files: None,
+            interfaces: None,
+            export_remote_ip_and_port: true,
+            export_latest_handshake_delay: false,
+            ..Default::default()
+        };
+
+        let s = a.render_with_names(Some(&pe), &options);
+        println!("{}", s);
+
+        let s_ok = "# HELP wireguard_sent_bytes_total Bytes sent to the peer
+# TYPE wireguard_sent_bytes_total counter
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"+0+yMIHVCqyIf4by1gxAjqQ92iKv3bQ/JctNVUEpSlU=\",remote_ip=\"10.211.123.120\",allowed_ip_0=\"10.90.0.7\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.7.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2StYqQY9tyVkGcO4ykKTiTu6AQp/yIYx8I4hwBLO1jA=\",remote_ip=\"10.211.123.121\",allowed_ip_0=\"10.90.0.15\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.8.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"923V/iAdcz8BcqB0Xo6pDJzARGBJCQ6fWe+peixQyB4=\",remote_ip=\"10.211.123.112\",allowed_ip_0=\"10.90.0.10\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.1.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"9M1fhLa9sIlT39z+SI/0a5H3mNSHYmM+NGA6sirD2nU=\",remote_ip=\"10.211.123.113\",allowed_ip_0=\"10.90.0.3\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.198.171.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"HtOSi37ALMnSkeAFqeWYZqlBnZqAJERhb5o/i3ZPEFI=\",remote_ip=\"10.211.123.127\",allowed_ip_0=\"10.90.0.17\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 1439257868
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"SMp58OwCNnwlzu+OdpA8xiNJzOwbl2gdMaD9CSZCC24=\",remote_ip=\"10.211.123.119\",allowed_ip_0=\"10.90.0.14\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.6.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"VQIrk1BiBfbOkkKGPiarEvhA4iPuszIL1lddvvFDvE0=\",remote_ip=\"10.211.123.118\",allowed_ip_0=\"10.90.0.8\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.5.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"YW7NBDEPXuW9GQlFWFzpgrivMxzdR55M8VOTX+E0thw=\",remote_ip=\"10.211.123.115\",allowed_ip_0=\"10.90.0.12\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.2.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"YwObmKDK4lfr5F6FHqJhDy9nkUQwbuK8wh4ac2VNSEU=\",remote_ip=\"10.211.123.123\",allowed_ip_0=\"10.90.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.11.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"eVfg1BH1hcteASE16+TjShxAJNyFLQ9QIcnCaylD/AA=\",remote_ip=\"10.211.123.116\",allowed_ip_0=\"10.90.0.13\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.3.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"gnRKXngxSppcYegsg38kEFn5Lmk4NcnRXLcZTtg2A2E=\",remote_ip=\"10.211.123.114\",allowed_ip_0=\"10.90.0.11\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.189.143.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"lh1h+tWPahB+PAWW62ExHVVrOp9IwdjYwaGnPIXgNwY=\",remote_ip=\"10.211.123.117\",allowed_ip_0=\"10.90.0.9\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.4.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"q07dm9n1UMLFbG6Dh+BNztCt7jVb9VtpVshQEf580kA=\",remote_ip=\"10.211.123.124\",allowed_ip_0=\"10.90.0.6\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.13.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"qa0AMD2puDBBrs8NYQ+skIrIi/Q5NgQRZLEh5p80Mnc=\",remote_ip=\"10.211.123.122\",allowed_ip_0=\"10.90.0.1\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.10.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"sUsR6xufQQ8Tf0FuyY9tfEeYdhVMeFelr4ZMUrj+B0E=\",remote_ip=\"10.211.123.128\",allowed_ip_0=\"10.90.0.18\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 1624251784
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"yZOoC2t6pBcXvoczuiJqrQ+8CYvJCzcq8aqyp+APaAE=\",remote_ip=\"10.211.123.125\",allowed_ip_0=\"10.90.0.16\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.14.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 12306832
+wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"yjeBkrZqUThSSHySFzWCjxAH8cxtiWSI2I8JFD6t1UM=\",remote_ip=\"10.211.123.126\",allowed_ip_0=\"10.90.0.5\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 10642564136
+
+# HELP wireguard_received_bytes_total Bytes received from the peer
+# TYPE wireguard_received_bytes_total counter
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"+0+yMIHVCqyIf4by1gxAjqQ92iKv3bQ/JctNVUEpSlU=\",remote_ip=\"10.211.123.120\",allowed_ip_0=\"10.90.0.7\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.7.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"2StYqQY9tyVkGcO4ykKTiTu6AQp/yIYx8I4hwBLO1jA=\",remote_ip=\"10.211.123.121\",allowed_ip_0=\"10.90.0.15\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.8.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"923V/iAdcz8BcqB0Xo6pDJzARGBJCQ6fWe+peixQyB4=\",remote_ip=\"10.211.123.112\",allowed_ip_0=\"10.90.0.10\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.1.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"9M1fhLa9sIlT39z+SI/0a5H3mNSHYmM+NGA6sirD2nU=\",remote_ip=\"10.211.123.113\",allowed_ip_0=\"10.90.0.3\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.198.171.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"HtOSi37ALMnSkeAFqeWYZqlBnZqAJERhb5o/i3ZPEFI=\",remote_ip=\"10.211.123.127\",allowed_ip_0=\"10.90.0.17\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 62592693520
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"SMp58OwCNnwlzu+OdpA8xiNJzOwbl2gdMaD9CSZCC24=\",remote_ip=\"10.211.123.119\",allowed_ip_0=\"10.90.0.14\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.6.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"VQIrk1BiBfbOkkKGPiarEvhA4iPuszIL1lddvvFDvE0=\",remote_ip=\"10.211.123.118\",allowed_ip_0=\"10.90.0.8\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.5.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"YW7NBDEPXuW9GQlFWFzpgrivMxzdR55M8VOTX+E0thw=\",remote_ip=\"10.211.123.115\",allowed_ip_0=\"10.90.0.12\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.2.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"YwObmKDK4lfr5F6FHqJhDy9nkUQwbuK8wh4ac2VNSEU=\",remote_ip=\"10.211.123.123\",allowed_ip_0=\"10.90.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.11.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"eVfg1BH1hcteASE16+TjShxAJNyFLQ9QIcnCaylD/AA=\",remote_ip=\"10.211.123.116\",allowed_ip_0=\"10.90.0.13\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.3.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"gnRKXngxSppcYegsg38kEFn5Lmk4NcnRXLcZTtg2A2E=\",remote_ip=\"10.211.123.114\",allowed_ip_0=\"10.90.0.11\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.189.143.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"lh1h+tWPahB+PAWW62ExHVVrOp9IwdjYwaGnPIXgNwY=\",remote_ip=\"10.211.123.117\",allowed_ip_0=\"10.90.0.9\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.4.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"q07dm9n1UMLFbG6Dh+BNztCt7jVb9VtpVshQEf580kA=\",remote_ip=\"10.211.123.124\",allowed_ip_0=\"10.90.0.6\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.13.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"qa0AMD2puDBBrs8NYQ+skIrIi/Q5NgQRZLEh5p80Mnc=\",remote_ip=\"10.211.123.122\",allowed_ip_0=\"10.90.0.1\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.10.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"sUsR6xufQQ8Tf0FuyY9tfEeYdhVMeFelr4ZMUrj+B0E=\",remote_ip=\"10.211.123.128\",allowed_ip_0=\"10.90.0.18\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 75066288152
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"yZOoC2t6pBcXvoczuiJqrQ+8CYvJCzcq8aqyp+APaAE=\",remote_ip=\"10.211.123.125\",allowed_ip_0=\"10.90.0.16\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.14.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 1232856
+wireguard_received_bytes_total{interface=\"wg0\",public_key=\"yjeBkrZqUThSSHySFzWCjxAH8cxtiWSI2I8JFD6t1UM=\",remote_ip=\"10.211.123.126\",allowed_ip_0=\"10.90.0.5\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 18576788764
+
+# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake
+# TYPE wireguard_latest_handshake_seconds gauge
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"+0+yMIHVCqyIf4by1gxAjqQ92iKv3bQ/JctNVUEpSlU=\",remote_ip=\"10.211.123.120\",allowed_ip_0=\"10.90.0.7\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.7.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"2StYqQY9tyVkGcO4ykKTiTu6AQp/yIYx8I4hwBLO1jA=\",remote_ip=\"10.211.123.121\",allowed_ip_0=\"10.90.0.15\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.8.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"923V/iAdcz8BcqB0Xo6pDJzARGBJCQ6fWe+peixQyB4=\",remote_ip=\"10.211.123.112\",allowed_ip_0=\"10.90.0.10\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.1.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"9M1fhLa9sIlT39z+SI/0a5H3mNSHYmM+NGA6sirD2nU=\",remote_ip=\"10.211.123.113\",allowed_ip_0=\"10.90.0.3\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.198.171.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"HtOSi37ALMnSkeAFqeWYZqlBnZqAJERhb5o/i3ZPEFI=\",remote_ip=\"10.211.123.127\",allowed_ip_0=\"10.90.0.17\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 1574770783
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"SMp58OwCNnwlzu+OdpA8xiNJzOwbl2gdMaD9CSZCC24=\",remote_ip=\"10.211.123.119\",allowed_ip_0=\"10.90.0.14\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.6.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"VQIrk1BiBfbOkkKGPiarEvhA4iPuszIL1lddvvFDvE0=\",remote_ip=\"10.211.123.118\",allowed_ip_0=\"10.90.0.8\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.5.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"YW7NBDEPXuW9GQlFWFzpgrivMxzdR55M8VOTX+E0thw=\",remote_ip=\"10.211.123.115\",allowed_ip_0=\"10.90.0.12\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.2.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"YwObmKDK4lfr5F6FHqJhDy9nkUQwbuK8wh4ac2VNSEU=\",remote_ip=\"10.211.123.123\",allowed_ip_0=\"10.90.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.11.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"eVfg1BH1hcteASE16+TjShxAJNyFLQ9QIcnCaylD/AA=\",remote_ip=\"10.211.123.116\",allowed_ip_0=\"10.90.0.13\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.3.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"gnRKXngxSppcYegsg38kEFn5Lmk4NcnRXLcZTtg2A2E=\",remote_ip=\"10.211.123.114\",allowed_ip_0=\"10.90.0.11\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.189.143.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"lh1h+tWPahB+PAWW62ExHVVrOp9IwdjYwaGnPIXgNwY=\",remote_ip=\"10.211.123.117\",allowed_ip_0=\"10.90.0.9\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.4.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"q07dm9n1UMLFbG6Dh+BNztCt7jVb9VtpVshQEf580kA=\",remote_ip=\"10.211.123.124\",allowed_ip_0=\"10.90.0.6\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.13.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"qa0AMD2puDBBrs8NYQ+skIrIi/Q5NgQRZLEh5p80Mnc=\",remote_ip=\"10.211.123.122\",allowed_ip_0=\"10.90.0.1\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.10.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"sUsR6xufQQ8Tf0FuyY9tfEeYdhVMeFelr4ZMUrj+B0E=\",remote_ip=\"10.211.123.128\",allowed_ip_0=\"10.90.0.18\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 1574770693
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"yZOoC2t6pBcXvoczuiJqrQ+8CYvJCzcq8aqyp+APaAE=\",remote_ip=\"10.211.123.125\",allowed_ip_0=\"10.90.0.16\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.14.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 1574770531
+wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"yjeBkrZqUThSSHySFzWCjxAH8cxtiWSI2I8JFD6t1UM=\",remote_ip=\"10.211.123.126\",allowed_ip_0=\"10.90.0.5\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 1574770705
+
+# HELP wireguard_interface_sent_bytes_total Bytes sent to all the peers of the interface
+# TYPE wireguard_interface_sent_bytes_total counter
+wireguard_interface_sent_bytes_total{interface=\"wg0\"} 13718380620
+
+# HELP wireguard_interface_received_bytes_total Bytes received from all the peers of the interface
+# TYPE wireguard_interface_received_bytes_total counter
+wireguard_interface_received_bytes_total{interface=\"wg0\"} 156237003292
+
+# HELP wireguard_total_sent_bytes_total Bytes sent to all the peers of all the interfaces
+# TYPE wireguard_total_sent_bytes_total counter
+wireguard_total_sent_bytes_total 13718380620
+
+# HELP wireguard_total_received_bytes_total Bytes received from all the peers of all the interfaces
+# TYPE wireguard_total_received_bytes_total counter
+wireguard_total_received_bytes_total 156237003292
+";
+        assert_eq!(s, s_ok);
+    }
+
+    #[test]
+    fn test_parse() {
+        let a = WireGuard::try_from(TEXT).unwrap();
+        println!("{:?}", a);
+        assert!(a.interfaces.len() == 3);
+        assert!(a.interfaces["wg0"].len() == 6);
+
+        let e1 = match &a.interfaces["wg0"][1] {
+            Endpoint::Local(_) => panic!(),
+            Endpoint::Remote(re) => re,
+        };
+
+        assert_eq!(
+            e1.public_key,
+            "2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk="
+        );
+
+        assert_eq!(e1.remote_ip, Some("37.159.76.245".to_owned()));
+        assert_eq!(e1.allowed_ips, "10.70.0.2/32,10.70.0.66/32".to_owned());
+    }
+
+    #[test]
+    fn test_parse_and_serialize() {
+        let a = WireGuard::try_from(TEXT).unwrap();
+
+        let options = Options {
+            verbose: true,
+            prepend_sudo: true,
+            separate_allowed_ips: false,
+            extract_names_config_files: None,
+            interfaces: None,
+            export_remote_ip_and_port: true,
+            export_latest_handshake_delay: true,
+            ..Default::default()
+        };
+
+        let s = a.render_with_names(None, &options);
+        println!("{}", s);
+    }
+
+    #[test]
+    fn test_render_to_prometheus_simple() {
+        const REF : &str = "# HELP wireguard_sent_bytes_total Bytes sent to the peer\n# TYPE wireguard_sent_bytes_total counter\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"to_change\",remote_ip=\"remote_ip\",remote_port=\"100\"} 1000\n\n# HELP wireguard_received_bytes_total Bytes received from the peer\n# TYPE wireguard_received_bytes_total counter\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"to_change\",remote_ip=\"remote_ip\",remote_port=\"100\"} 5000\n\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"to_change\",remote_ip=\"remote_ip\",remote_port=\"100\"} 500\n\n# HELP wireguard_interface_sent_bytes_total Bytes sent to all the peers of the interface\n# TYPE wireguard_interface_sent_bytes_total counter\nwireguard_interface_sent_bytes_total{interface=\"Pippo\"} 1000\n\n# HELP wireguard_interface_received_bytes_total Bytes received from all the peers of the interface\n# TYPE wireguard_interface_received_bytes_total counter\nwireguard_interface_received_bytes_total{interface=\"Pippo\"} 5000\n\n# HELP wireguard_total_sent_bytes_total Bytes sent to all the peers of all the interfaces\n# TYPE wireguard_total_sent_bytes_total counter\nwireguard_total_sent_bytes_total 1000\n\n# HELP wireguard_total_received_bytes_total Bytes received from all the peers of all the interfaces\n# TYPE wireguard_total_received_bytes_total counter\nwireguard_total_received_bytes_total 5000\n";
+
+        let re = Endpoint::Remote(RemoteEndpoint {
+            public_key: "test".to_owned(),
+            remote_ip: Some("remote_ip".to_owned()),
+            remote_port: Some(100),
+            allowed_ips: "to_change".to_owned(),
+            has_preshared_key: false,
+            latest_handshake: 500,
+            sent_bytes: 1000,
+            received_bytes: 5000,
+            persistent_keepalive: None,
+            source: None,
+            from_cache: false,
+        });
+        let mut wg = WireGuard {
+            interfaces: HashMap::new(),
+        };
+
+        let v = vec![re];
+        wg.interfaces.insert("Pippo".to_owned(), v);
+
+        let options = Options {
+            verbose: true,
+            prepend_sudo: true,
+            separate_allowed_ips: false,
+            extract_names_config_files: None,
+            interfaces: None,
+            export_remote_ip_and_port: true,
+            export_latest_handshake_delay: false,
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(None, &options);
+
+        assert_eq!(prometheus, REF);
+    }
+
+    #[test]
+    fn test_render_bytes_per_second() {
+        const REF: &str = "# HELP wireguard_sent_bytes_per_second Bytes per second sent to the peer since the previous scrape\n# TYPE wireguard_sent_bytes_per_second gauge\nwireguard_sent_bytes_per_second{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 0\n\n# HELP wireguard_received_bytes_per_second Bytes per second received from the peer since the previous scrape\n# TYPE wireguard_received_bytes_per_second gauge\nwireguard_received_bytes_per_second{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 0\n";
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t2000\t3000\toff\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let options = Options {
+            export_bytes_per_second: true,
+            ..Default::default()
+        };
+
+        // the first scrape has nothing to compare with
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.ends_with(REF));
+
+        // with the info metric the rates keep only the labels identifying the peer
+        let options = Options {
+            export_bytes_per_second: true,
+            export_peer_info: true,
+            ..Default::default()
+        };
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.contains("\nwireguard_sent_bytes_per_second{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\"} 0\n"));
+    }
+
+    #[test]
+    fn test_render_counter_resets() {
+        const REF: &str = "# HELP wireguard_peer_counter_resets_total Number of times the peer byte counters were reset since the exporter started\n# TYPE wireguard_peer_counter_resets_total counter\nwireguard_peer_counter_resets_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32\"} 1\n";
+
+        let endpoint = |sent_bytes| RemoteEndpoint {
+            public_key: "test".to_owned(),
+            remote_ip: None,
+            remote_port: None,
+            allowed_ips: "10.0.0.2/32".to_owned(),
+            has_preshared_key: false,
+            latest_handshake: 500,
+            sent_bytes,
+            received_bytes: 5000,
+            persistent_keepalive: None,
+            source: None,
+            from_cache: false,
+        };
+
+        let options = Options {
+            export_counter_resets: true,
+            ..Default::default()
+        };
+
+        let mut wg = WireGuard {
+            interfaces: HashMap::new(),
+        };
+        wg.interfaces
+            .insert("Pippo".to_owned(), vec![Endpoint::Remote(endpoint(1000))]);
+        wg.render_with_names(None, &options);
+
+        wg.interfaces
+            .insert("Pippo".to_owned(), vec![Endpoint::Remote(endpoint(10))]);
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.ends_with(REF));
+    }
+
+    #[test]
+    fn test_render_configured_allowed_ips() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG: &str = "
+[Peer]
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32, 10.70.0.66/32
+
+[Peer]
+PublicKey = 000-not-live
+AllowedIPs = 10.70.0.9/32
+";
+
+        const REF: &str = "# HELP wireguard_peer_configured_allowed_ips AllowedIPs of the peer as specified in the config file\n# TYPE wireguard_peer_configured_allowed_ips gauge\nwireguard_peer_configured_allowed_ips{public_key=\"000-not-live\",allowed_ips=\"10.70.0.9/32\"} 1\nwireguard_peer_configured_allowed_ips{public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32, 10.70.0.66/32\"} 1\n";
+
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+        let wg = WireGuard::try_from(TEXT).unwrap();
+
+        let options = Options {
+            export_configured_allowed_ips: true,
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
+        assert!(prometheus.ends_with(REF));
+    }
+
+    #[test]
+    fn test_render_site_from_allowed_ip() {
+        let wg = WireGuard::try_from(TEXT).unwrap();
+
+        let options = Options {
+            site_from_allowed_ip: Some("octet:3".parse().unwrap()),
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg2\",public_key=\"MdVOIPKt9K2MPj/sO2NlWQbOnFJcL/qX80mmhQwsUlA=\",allowed_ips=\"10.70.5.50/32\",site=\"5\"} 0\n"));
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"pollo\",public_key=\"YdVOIPKt9K2MPsO2NlWQbOnFJcL/qX80mmhQwsUlA=\",allowed_ips=\"10.70.70.50/32\",site=\"70\"} 0\n"));
+    }
+
+    #[test]
+    fn test_format_handshake_time() {
+        assert_eq!(format_handshake_time(0), "never");
+        assert_eq!(format_handshake_time(1555771458), "2019-04-20T14:44:18Z");
+    }
+
+    #[test]
+    fn test_render_handshake_label() {
+        let wg = WireGuard::try_from(TEXT).unwrap();
+
+        let options = Options {
+            export_handshake_label: true,
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.contains("wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32,10.70.0.66/32\",handshake_time=\"2019-04-20T14:44:18Z\"} 1555771458\n"));
+        assert!(prometheus.contains("wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\",handshake_time=\"never\"} 0\n"));
+        // the other metrics are not affected
+        assert!(!prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\",handshake_time"));
+    }
+
+    #[test]
+    fn test_render_unique_remote_ips() {
+        const TEXT_REMOTE_IPS: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t37.159.76.245:29159\t10.70.0.2/32\t1555771458\t10288508\t139524160\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t37.159.76.245:29160\t10.70.0.3/32\t1555771458\t0\t0\toff
+wg0\tL2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\t(none)\t5.90.62.106:21741\t10.70.0.4/32\t1555771458\t0\t0\toff
+wg0\tMdVOIPKt9K2MPj/sO2NlWQbOnFJ6L/qX80mmhQwsUlA=\t(none)\t(none)\t10.70.0.50/32\t0\t0\t0\toff
+wg1\tMdVOIPKt9K2MPj/sO2NlWQbOnFJcL/qX80mmhQwsUlA=\t(none)\t(none)\t10.70.5.50/32\t0\t0\t0\toff
+";
+        const REF: &str = "# HELP wireguard_interface_unique_remote_ips Number of distinct remote ips of the peers of the interface\n# TYPE wireguard_interface_unique_remote_ips gauge\nwireguard_interface_unique_remote_ips{interface=\"wg0\"} 2\nwireguard_interface_unique_remote_ips{interface=\"wg1\"} 0\n";
+
+        let wg = WireGuard::try_from(TEXT_REMOTE_IPS).unwrap();
+
+        let options = Options {
+            export_remote_ip_and_port: true,
+            export_unique_remote_ips: true,
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.ends_with(REF));
+
+        // not exported without the remote ips
+        let options = Options {
+            export_unique_remote_ips: true,
+            ..Default::default()
+        };
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(!prometheus.contains("wireguard_interface_unique_remote_ips"));
+    }
+
+    #[test]
+    fn test_normalize_field_separator() {
+        let text_spaces = TEXT.replace('\t', "  ");
+        let normalized = normalize_field_separator(&text_spaces, ' ', true);
+        assert_eq!(normalized, TEXT);
+
+        let a = WireGuard::try_from(TEXT).unwrap();
+        let b = WireGuard::try_from(&normalized as &str).unwrap();
+        assert_eq!(
+            a.render_with_names(None, &Options::default()),
+            b.render_with_names(None, &Options::default())
+        );
+
+        // the commas of the allowed ips are preserved
+        let text_commas = TEXT.replace('\t', ",");
+        assert_eq!(normalize_field_separator(&text_commas, ',', true), TEXT);
+
+        // dump of a specific interface (no interface column)
+        assert_eq!(
+            normalize_field_separator(
+                "2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=,(none),37.159.76.245:29159,10.70.0.2/32,10.70.0.66/32,1555771458,10288508,139524160,off\n",
+                ',',
+                false
+            ),
+            "2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t37.159.76.245:29159\t10.70.0.2/32,10.70.0.66/32\t1555771458\t10288508\t139524160\toff\n"
+        );
+    }
+
+    #[test]
+    fn test_redact_private_keys() {
+        // the private key is the first key of the local line, the public key the second
+        let redacted = redact_private_keys(TEXT, true);
+        assert!(!redacted.contains("0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8="));
+        assert!(redacted.contains("000q4qAC0ExW/BuGSmVR1nxH9JAXT6g9Wd3oEGy5lA="));
+        assert!(redacted.starts_with(
+            "wg0\t(redacted)\t000q4qAC0ExW/BuGSmVR1nxH9JAXT6g9Wd3oEGy5lA=\t51820\toff\n"
+        ));
+        // the remote lines are untouched
+        assert_eq!(
+            &redacted[redacted.find('\n').unwrap()..],
+            &TEXT[TEXT.find('\n').unwrap()..]
+        );
+
+        let redacted = redact_private_keys(
+            "0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8=\t000q4qAC0ExW/BuGSmVR1nxH9JAXT6g9Wd3oEGy5lA=\t51820\toff\n",
+            false,
+        );
+        assert!(!redacted.contains("0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8="));
+        assert_eq!(
+            redacted,
+            "(redacted)\t000q4qAC0ExW/BuGSmVR1nxH9JAXT6g9Wd3oEGy5lA=\t51820\toff\n"
+        );
+
+        // the parser accepts the placeholder and keeps the public key
+        let wg = WireGuard::try_from(&redact_private_keys(TEXT, true) as &str).unwrap();
+        assert_eq!(wg.interfaces["wg0"].len(), 6);
+        match &wg.interfaces["wg0"][0] {
+            Endpoint::Local(lep) => {
+                assert_eq!(lep.private_key.expose(), REDACTED);
+                assert_eq!(
+                    lep.public_key,
+                    "000q4qAC0ExW/BuGSmVR1nxH9JAXT6g9Wd3oEGy5lA="
+                );
+            }
+            Endpoint::Remote(_) => panic!("expected the local endpoint"),
+        }
+    }
+
+    #[test]
+    fn test_render_endpoint_label() {
+        let wg = WireGuard::try_from(TEXT).unwrap();
+
+        let options = Options {
+            endpoint_label: true,
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32,10.70.0.66/32\",endpoint=\"37.159.76.245:29159\"} 139524160\n"));
+        // no endpoint, no label
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"pollo\",public_key=\"YdVOIPKt9K2MPsO2NlWQbOnFJcL/qX80mmhQwsUlA=\",allowed_ips=\"10.70.70.50/32\"} 0\n"));
+        assert!(!prometheus.contains("remote_ip"));
+
+        assert_eq!(format_endpoint("2001:db8::1", 51820), "[2001:db8::1]:51820");
+        assert_eq!(
+            format_endpoint("vpn.example.com", 51820),
+            "vpn.example.com:51820"
+        );
+    }
+
+    #[test]
+    fn test_parse_local_line() {
+        // wg show all dump of a wg-quick interface routing all the traffic
+        const TEXT_LOCAL: &str = "wg0\tyAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=\tHIgo9xNzJMWLKASShiTqIybxZ0U3wGLiUeJ1PKf8ykw=\t51820\t0xca6c
+wg0\txTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\t(none)\t192.95.5.67:1234\t0.0.0.0/0,::/0\t1555771458\t10288508\t139524160\toff
+";
+
+        let wg = WireGuard::try_from(TEXT_LOCAL).unwrap();
+        match &wg.interfaces["wg0"][0] {
+            Endpoint::Local(lep) => {
+                assert_eq!(
+                    lep.private_key.expose(),
+                    "yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk="
+                );
+                assert_eq!(
+                    lep.public_key,
+                    "HIgo9xNzJMWLKASShiTqIybxZ0U3wGLiUeJ1PKf8ykw="
+                );
+                assert_eq!(lep.local_port, 51820);
+                assert_eq!(lep.fwmark, Some(0xca6c));
+            }
+            Endpoint::Remote(_) => panic!("expected the local endpoint"),
+        }
+
+        // the redaction drops the private key and keeps the public one
+        let redacted = redact_private_keys(TEXT_LOCAL, true);
+        assert!(redacted.starts_with(
+            "wg0\t(redacted)\tHIgo9xNzJMWLKASShiTqIybxZ0U3wGLiUeJ1PKf8ykw=\t51820\t0xca6c\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_interface_options() {
+        let wg = WireGuard::try_from(TEXT).unwrap();
+
+        let mut options = Options {
+            interface_options: crate::interface_options::interface_options_try_from(
+                r#"{
+                    "wg0": { "export_remote_ip_and_port": true },
+                    "wg2": { "separate_allowed_ips": true, "alias": "office" }
+                }"#,
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(None, &options);
+        // wg0 exports the remote ip
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32,10.70.0.66/32\",remote_ip=\"37.159.76.245\",remote_port=\"29159\"} 139524160\n"));
+        // wg2 is aliased and splits the allowed ips
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"office\",public_key=\"MdVOIPKt9K2MPj/sO2NlWQbOnFJcL/qX80mmhQwsUlA=\",allowed_ip_0=\"10.70.5.50\",allowed_subnet_0=\"32\"} 0\n"));
+        // pollo uses the global options
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"pollo\",public_key=\"YdVOIPKt9K2MPsO2NlWQbOnFJcL/qX80mmhQwsUlA=\",allowed_ips=\"10.70.70.50/32\"} 0\n"));
+
+        // the overrides win over the global options
+        options.export_remote_ip_and_port = true;
+        options
+            .interface_options
+            .get_mut("wg0")
+            .unwrap()
+            .export_remote_ip_and_port = Some(false);
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(!prometheus.contains("interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32,10.70.0.66/32\",remote_ip"));
+        assert!(prometheus.contains("interface=\"office\",public_key=\"MdVOIPKt9K2MPj/sO2NlWQbOnFJcL/qX80mmhQwsUlA=\",allowed_ip_0=\"10.70.5.50\",allowed_subnet_0=\"32\"} 0\n"));
+    }
+
+    #[test]
+    fn test_render_scrape_duration() {
+        assert_eq!(render_scrape_duration(Duration::from_millis(1500), &Options::default()), "# HELP wireguard_scrape_duration_seconds Seconds taken to collect this scrape, running wg and rendering included\n# TYPE wireguard_scrape_duration_seconds gauge\nwireguard_scrape_duration_seconds 1.5\n");
+    }
+
+    #[test]
+    fn test_render_concurrent_scrapes() {
+        assert_eq!(render_concurrent_scrapes(3, &Options::default()), "# HELP wireguard_exporter_concurrent_scrapes Number of scrapes being served when this scrape was collected, this one included\n# TYPE wireguard_exporter_concurrent_scrapes gauge\nwireguard_exporter_concurrent_scrapes 3\n");
+    }
+
+    #[test]
+    fn test_render_up() {
+        let mut up = BTreeMap::new();
+        up.insert("wg1".to_owned(), false);
+        up.insert("wg0".to_owned(), true);
+        assert_eq!(render_up(&up, &BTreeMap::new(), &Options::default()), "# HELP wireguard_up 1 if the WireGuard data of the interface was collected successfully, 0 otherwise\n# TYPE wireguard_up gauge\nwireguard_up{interface=\"wg0\"} 1\nwireguard_up{interface=\"wg1\"} 0\n");
+
+        let mut source_up = BTreeMap::new();
+        source_up.insert("http://10.0.0.1:9587/dump".to_owned(), false);
+        assert_eq!(render_up(&up, &source_up, &Options::default()), "# HELP wireguard_up 1 if the WireGuard data of the interface was collected successfully, 0 otherwise\n# TYPE wireguard_up gauge\nwireguard_up{interface=\"wg0\"} 1\nwireguard_up{interface=\"wg1\"} 0\nwireguard_up{source=\"http://10.0.0.1:9587/dump\"} 0\n");
+    }
+
+    #[test]
+    fn test_parse_dump_skip_malformed_lines() {
+        const TEXT_MALFORMED: &str = "wg0\t0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8=\t000q4qAC0ExW/BuGSmVR1nxH9JAXT6g9Wd3oEGy5lA=\t51820\toff
+wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t37.159.76.245:29159\t10.70.0.2/32\t1555771458\t10288508\t139524160\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)
+wg1\tL2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\t(none)\t(none)\t10.70.0.4/32\t0\t0\t0\toff
+";
+        assert!(WireGuard::parse_dump(TEXT_MALFORMED, false).is_err());
+
+        let (wg, dump_line_counts) = WireGuard::parse_dump(TEXT_MALFORMED, true).unwrap();
+        assert_eq!(wg.interfaces["wg0"].len(), 2);
+        assert_eq!(wg.interfaces["wg1"].len(), 1);
+        assert_eq!(
+            dump_line_counts["wg0"],
+            DumpLineCount {
+                parsed: 2,
+                skipped: 1
+            }
+        );
+        assert_eq!(
+            dump_line_counts["wg1"],
+            DumpLineCount {
+                parsed: 1,
+                skipped: 0
+            }
+        );
+
+        assert_eq!(render_dump_line_counts(&dump_line_counts, &Options::default()), "# HELP wireguard_dump_lines_parsed Number of lines of the wg dump of the interface parsed in this scrape\n# TYPE wireguard_dump_lines_parsed gauge\nwireguard_dump_lines_parsed{interface=\"wg0\"} 2\nwireguard_dump_lines_parsed{interface=\"wg1\"} 1\n\n# HELP wireguard_dump_lines_skipped Number of malformed lines of the wg dump of the interface skipped in this scrape\n# TYPE wireguard_dump_lines_skipped gauge\nwireguard_dump_lines_skipped{interface=\"wg0\"} 1\nwireguard_dump_lines_skipped{interface=\"wg1\"} 0\n");
+    }
+
+    #[test]
+    fn test_render_draining() {
+        assert_eq!(render_draining(true, &Options::default()), "# HELP wireguard_exporter_draining 1 if the exporter is shutting down and draining the scrapes, 0 otherwise\n# TYPE wireguard_exporter_draining gauge\nwireguard_exporter_draining 1\n");
+        assert!(render_draining(false, &Options::default())
+            .ends_with("\nwireguard_exporter_draining 0\n"));
+    }
+
+    #[test]
+    fn test_render_name_changes() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG_BEFORE: &str = "[Peer]
+# friendly_name = alice
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+
+[Peer]
+# friendly_name = bob
+PublicKey = qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=
+AllowedIPs = 10.70.0.3/32
+";
+        const CONFIG_AFTER: &str = "[Peer]
+# friendly_name = alice
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+
+[Peer]
+# friendly_name = robert
+PublicKey = qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=
+AllowedIPs = 10.70.0.3/32
+";
+        const REF_AFTER: &str = "# HELP wireguard_peer_name_changed_total Number of times the friendly name of the peer changed in the config files since the exporter started\n# TYPE wireguard_peer_name_changed_total counter\nwireguard_peer_name_changed_total{public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\"} 0\nwireguard_peer_name_changed_total{public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\"} 1\n";
+
+        let wg = WireGuard::try_from(TEXT).unwrap();
+
+        let options = Options {
+            export_name_changes: true,
+            ..Default::default()
+        };
+
+        let pehm = peer_entry_hashmap_try_from(CONFIG_BEFORE).unwrap();
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
+        assert!(prometheus.contains("wireguard_peer_name_changed_total{public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\"} 0\n"));
+
+        let pehm = peer_entry_hashmap_try_from(CONFIG_AFTER).unwrap();
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
+        assert!(prometheus.ends_with(REF_AFTER));
+    }
+
+    #[test]
+    fn test_handshake_delay() {
+        assert_eq!(handshake_delay(0, 1555771458), 0);
+        assert_eq!(handshake_delay(1555771400, 1555771458), 58);
+        assert_eq!(handshake_delay(1555771500, 1555771458), 0);
+    }
+
+    #[test]
+    fn test_render_handshake_delay() {
+        const TEXT_NEVER: &str = "wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t0\t0\toff
+wg0\tL2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\t(none)\t(none)\t10.70.0.4/32\t0\t0\t0\toff
+";
+        const REF: &str = "# HELP wireguard_latest_handshake_delay_seconds Seconds from the last handshake\n# TYPE wireguard_latest_handshake_delay_seconds gauge\nwireguard_latest_handshake_delay_seconds{interface=\"wg0\",public_key=\"L2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\",allowed_ips=\"10.70.0.4/32\"} 0\nwireguard_latest_handshake_delay_seconds{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\"} 0\n";
+
+        let wg = WireGuard::try_from(TEXT_NEVER).unwrap();
+
+        let options = Options {
+            export_latest_handshake_delay: true,
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.ends_with(REF));
+    }
+
+    #[test]
+    fn test_canonical_allowed_ips() {
+        assert_eq!(
+            canonical_allowed_ips("10.70.0.66/32,10.70.0.2/32"),
+            canonical_allowed_ips("10.70.0.2/32,10.70.0.66/32")
+        );
+        assert_eq!(
+            canonical_allowed_ips(
+                "fd86:ea04::4/128,10.70.0.10/32,10.70.0.2/32,10.70.0.10/32,10.70.0.0/24"
+            ),
+            "10.70.0.0/24,10.70.0.2/32,10.70.0.10/32,fd86:ea04::4/128"
+        );
+        assert_eq!(canonical_allowed_ips("(none)"), "(none)");
+    }
+
+    #[test]
+    fn test_render_canonical_allowed_ips() {
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.66/32,10.70.0.2/32\t0\t0\t0\toff\n";
+        const TEXT_B: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32,10.70.0.66/32,10.70.0.2/32\t0\t0\t0\toff\n";
+
+        let options = Options {
+            canonical_allowed_ips: true,
+            ..Default::default()
+        };
+
+        let a = WireGuard::try_from(TEXT_A)
+            .unwrap()
+            .render_with_names(None, &options);
+        let b = WireGuard::try_from(TEXT_B)
+            .unwrap()
+            .render_with_names(None, &options);
+        assert_eq!(a, b);
+        assert!(a.contains("allowed_ips=\"10.70.0.2/32,10.70.0.66/32\""));
+    }
+
+    #[test]
+    fn test_render_peers_by_family() {
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32,fd00::3/128\t0\t0\t0\toff
+wg0\t928vO9Lf4+Mo84cWu4k1oRyzf0AR7FTGoPKHGoTMSHk=\t(none)\t(none)\tfd00::80/128\t0\t0\t0\toff
+wg1\tMdVOIPKt9K2MPj/sO2NlWQbOnFJcL/qX80mmhQwsUlA=\t(none)\t(none)\t(none)\t0\t0\t0\toff
+";
+        const REF: &str = "# HELP wireguard_peers_by_family Number of peers of the interface with IPv4 or IPv6 allowed ips\n# TYPE wireguard_peers_by_family gauge\nwireguard_peers_by_family{interface=\"wg0\",family=\"ipv4\"} 2\nwireguard_peers_by_family{interface=\"wg0\",family=\"ipv6\"} 2\nwireguard_peers_by_family{interface=\"wg1\",family=\"ipv4\"} 0\nwireguard_peers_by_family{interface=\"wg1\",family=\"ipv6\"} 0\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let options = Options {
+            export_peers_by_family: true,
+            ..Default::default()
+        };
+        assert!(wg.render_with_names(None, &options).ends_with(REF));
+
+        assert_eq!(allowed_ips_families("(none)"), (false, false));
+        assert_eq!(allowed_ips_families("0.0.0.0/0,::/0"), (true, true));
+    }
+
+    #[test]
+    fn test_render_static_labels() {
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t2\t3\toff
+";
+
+        let options = Options {
+            static_labels: vec![
+                ("region".to_owned(), "eu-west".to_owned()),
+                ("host".to_owned(), "vpn \\\"1\\\"".to_owned()),
+            ],
+            ..Default::default()
+        };
+
+        let prometheus = WireGuard::try_from(TEXT_A)
+            .unwrap()
+            .render_with_names(None, &options);
+        assert!(prometheus.contains("wireguard_sent_bytes_total{region=\"eu-west\",host=\"vpn \\\"1\\\"\",interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 3\n"));
+
+        // the series without labels of their own
+        assert!(render_concurrent_scrapes(1, &options).ends_with("\nwireguard_exporter_concurrent_scrapes{region=\"eu-west\",host=\"vpn \\\"1\\\"\"} 1\n"));
+
+        let mut handshake_ages = BTreeMap::new();
+        handshake_ages.insert("wg0", vec![10]);
+        let mut rendered = String::new();
+        write_handshake_age_histogram(&mut rendered, &handshake_ages, &options);
+        assert!(rendered.contains("\nwireguard_handshake_age_seconds_bucket{region=\"eu-west\",host=\"vpn \\\"1\\\"\",interface=\"wg0\",le=\"+Inf\"} 1\n"));
+    }
+
+    #[test]
+    fn test_render_no_allowed_ips() {
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t(none)\t0\t2\t3\toff
+";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        match &wg.interfaces["wg0"][0] {
+            Endpoint::Remote(ep) => assert_eq!(ep.allowed_ips, ""),
+            Endpoint::Local(_) => panic!("wrong endpoint kind"),
+        }
+
+        let options = Options {
+            export_allowed_ips_count: true,
+            ..Default::default()
+        };
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\"} 3\n"));
+        assert!(prometheus.contains("wireguard_peer_allowed_ips_count{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\"} 0\n"));
+
+        let options = Options {
+            separate_allowed_ips: true,
+            ..Default::default()
+        };
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(!prometheus.contains("allowed_ip_0"));
+        assert!(!prometheus.contains("(none)"));
+    }
+
+    #[test]
+    fn test_render_peers_by_connection_age() {
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t0\t0\toff
+wg1\tGd3Qj2C2Y1J3EhpL7XtAQZOC5D2mtSrF3F0zxx3xiW8=\t(none)\t(none)\t10.70.0.4/32\t0\t0\t0\toff
+";
+        const REF: &str = "# HELP wireguard_peers_by_connection_age Number of peers of the interface by seconds of continuous presence\n# TYPE wireguard_peers_by_connection_age gauge\nwireguard_peers_by_connection_age{interface=\"wg0\",bucket=\"<3600\"} 1\nwireguard_peers_by_connection_age{interface=\"wg0\",bucket=\"<86400\"} 0\nwireguard_peers_by_connection_age{interface=\"wg0\",bucket=\">=86400\"} 1\nwireguard_peers_by_connection_age{interface=\"wg1\",bucket=\"<3600\"} 1\nwireguard_peers_by_connection_age{interface=\"wg1\",bucket=\"<86400\"} 0\nwireguard_peers_by_connection_age{interface=\"wg1\",bucket=\">=86400\"} 0\n";
+
+        let options = Options {
+            export_peers_by_connection_age: true,
+            connection_age_buckets: vec![3600, 86400],
+            ..Default::default()
+        };
+
+        // the first peer has been there for days
+        {
+            let mut scrape_state = options.scrape_state.lock().unwrap();
+            scrape_state.observe_peer_present(
+                "wg0",
+                "2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=",
+                None,
+                1555771458,
+            );
+            scrape_state.end_scrape();
+        }
+
+        let prometheus = WireGuard::try_from(TEXT_A)
+            .unwrap()
+            .render_with_names(None, &options);
+        assert!(prometheus.ends_with(REF));
+    }
+
+    #[test]
+    fn test_render_remote_host() {
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t10.211.123.112:51820\t10.70.0.2/32\t1\t2\t3\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t10.211.123.113:51820\t10.70.0.3/32\t1\t2\t3\toff
+";
+
+        let options = Options {
+            export_remote_ip_and_port: true,
+            resolve_remote_ip: true,
+            ..Default::default()
+        };
+        {
+            let mut remote_host_cache = options.remote_host_cache.lock().unwrap();
+            remote_host_cache.insert(
+                "10.211.123.112".to_owned(),
+                Some("alice.example.com".to_owned()),
+                1,
+            );
+            // no PTR record
+            remote_host_cache.insert("10.211.123.113".to_owned(), None, 1);
+        }
+
+        let prometheus = WireGuard::try_from(TEXT_A)
+            .unwrap()
+            .render_with_names(None, &options);
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\",remote_ip=\"10.211.123.112\",remote_port=\"51820\",remote_host=\"alice.example.com\"} 3\n"));
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\",remote_ip=\"10.211.123.113\",remote_port=\"51820\"} 3\n"));
+    }
+
+    #[test]
+    fn test_render_first_seen() {
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff\n";
+
+        let options = Options {
+            export_first_seen: true,
+            ..Default::default()
+        };
+
+        // the peer is already known: the original timestamp is kept
+        options
+            .scrape_state
+            .lock()
+            .unwrap()
+            .observe_peer_first_seen(
+                "wg0",
+                "2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=",
+                None,
+                1555771458,
+            );
+
+        let prometheus = WireGuard::try_from(TEXT_A)
+            .unwrap()
+            .render_with_names(None, &options);
+        assert!(prometheus.ends_with("# HELP wireguard_peer_first_seen_timestamp_seconds UNIX timestamp seconds of the first scrape that included the peer since the exporter started\n# TYPE wireguard_peer_first_seen_timestamp_seconds gauge\nwireguard_peer_first_seen_timestamp_seconds{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 1555771458\n"));
+    }
+
+    #[test]
+    fn test_render_name_comma_replacement() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG: &str = "[Peer]
+# friendly_name = Doe, John
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+";
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+
+        let prometheus = wg.render_with_names(Some(&pehm), &Options::default());
+        assert!(prometheus.contains("friendly_name=\"Doe, John\""));
+
+        let options = Options {
+            name_comma_replacement: Some(" -".to_owned()),
+            ..Default::default()
+        };
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\",friendly_name=\"Doe - John\"} 0\n"));
+    }
+
+    #[test]
+    fn test_render_friendly_name_escaped_once() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        // prometheus_exporter_base does not escape the label values so the
+        // escaping done when parsing friendly_name is the only one
+        const CONFIG: &str = r#"[Peer]
+# friendly_name = the "quoted" one
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+"#;
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+
+        let prometheus = wg.render_with_names(Some(&pehm), &Options::default());
+        assert!(prometheus.contains(r#"wireguard_sent_bytes_total{interface="wg0",public_key="2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=",allowed_ips="10.70.0.2/32",friendly_name="the \"quoted\" one"} 0"#));
+        assert!(!prometheus.contains(r#"\\""#));
+    }
+
+    #[test]
+    fn test_render_persistent_keepalive() {
+        const TEXT_KEEPALIVE: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\t25
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t0\t0\toff
+";
+        const REF: &str = "# HELP wireguard_persistent_keepalive_seconds Persistent keepalive interval of the peer, 0 if off\n# TYPE wireguard_persistent_keepalive_seconds gauge\nwireguard_persistent_keepalive_seconds{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 25\nwireguard_persistent_keepalive_seconds{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\"} 0\n";
+
+        let wg = WireGuard::try_from(TEXT_KEEPALIVE).unwrap();
+        if let Endpoint::Remote(ep) = &wg.interfaces["wg0"][0] {
+            assert_eq!(ep.persistent_keepalive, Some(25));
+        } else {
+            panic!();
+        }
+
+        let options = Options {
+            export_persistent_keepalive: true,
+            ..Default::default()
+        };
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.ends_with(REF));
+
+        // anything else than a number or off is malformed
+        assert!(WireGuard::try_from(
+            "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\ton"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_render_preshared_key_presence() {
+        const TEXT_PSK: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\tsecret_preshared_key\t(none)\t10.70.0.2/32\t0\t0\t0\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t0\t0\toff
+";
+        const REF: &str = "# HELP wireguard_peer_has_preshared_key 1 if the peer has a preshared key, 0 otherwise\n# TYPE wireguard_peer_has_preshared_key gauge\nwireguard_peer_has_preshared_key{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 1\nwireguard_peer_has_preshared_key{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\"} 0\n";
+
+        let wg = WireGuard::try_from(TEXT_PSK).unwrap();
+        assert!(!format!("{:?}", wg).contains("secret_preshared_key"));
+
+        let options = Options {
+            export_preshared_key_presence: true,
+            ..Default::default()
+        };
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.ends_with(REF));
+        assert!(!prometheus.contains("secret_preshared_key"));
+
+        match WireGuard::try_from(
+            "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\tsecret_preshared_key\t(none)\t10.70.0.2/32\tx\t0\t0\toff",
+        ) {
+            Err(e) => assert!(!e.to_string().contains("secret_preshared_key")),
+            Ok(_) => panic!("the handshake is not a number"),
+        }
+    }
+
+    #[test]
+    fn test_render_interfaces_total() {
+        const REF: &str = "# HELP wireguard_interfaces_total Number of interfaces\n# TYPE wireguard_interfaces_total gauge\nwireguard_interfaces_total 3\n";
+
+        let mut wg = WireGuard::try_from(TEXT).unwrap();
+        let options = Options {
+            export_interfaces_total: true,
+            ..Default::default()
+        };
+        assert!(wg.render_with_names(None, &options).ends_with(REF));
+
+        wg.exclude_interfaces(&["wg0".to_owned()]);
+        assert!(wg
+            .render_with_names(None, &options)
+            .ends_with("wireguard_interfaces_total 2\n"));
+
+        assert!(!wg
+            .render_with_names(None, &Options::default())
+            .contains("wireguard_interfaces_total"));
+    }
+
+    #[test]
+    fn test_render_max_peers_per_interface() {
+        const TEXT_A: &str = "wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t0\t0\toff
+wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff
+wg0\t928vO9Lf4+Mo84cWu4k1oRyzf0AR7FTGoPKHGoTMSHk=\t(none)\t(none)\t10.70.0.80/32\t0\t0\t0\toff
+wg1\tMdVOIPKt9K2MPj/sO2NlWQbOnFJcL/qX80mmhQwsUlA=\t(none)\t(none)\t10.70.5.50/32\t0\t0\t0\toff
+";
+        const REF: &str = "# HELP wireguard_peers_truncated 1 if the interface has more peers than --max_peers_per_interface\n# TYPE wireguard_peers_truncated gauge\nwireguard_peers_truncated{interface=\"wg0\"} 1\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let options = Options {
+            max_peers_per_interface: 2,
+            ..Default::default()
+        };
+        let prometheus = wg.render_with_names(None, &options);
+        // the first peers by public key
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\""));
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"928vO9Lf4+Mo84cWu4k1oRyzf0AR7FTGoPKHGoTMSHk=\""));
+        assert!(!prometheus.contains("qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU="));
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg1\""));
+        assert!(prometheus.ends_with(REF));
+
+        // no limit by default
+        let prometheus = wg.render_with_names(None, &Options::default());
+        assert!(prometheus.contains("qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU="));
+        assert!(!prometheus.contains("wireguard_peers_truncated"));
+    }
+
+    #[test]
+    fn test_render_peer_connected() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let wg = WireGuard::try_from(&format!(
+            "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t{}\t0\t0\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t{}\t0\t0\toff
+wg0\t928vO9Lf4+Mo84cWu4k1oRyzf0AR7FTGoPKHGoTMSHk=\t(none)\t(none)\t10.70.0.80/32\t0\t0\t0\toff
+",
+            now - 10,
+            now - 1000
+        ) as &str)
+        .unwrap();
+        const REF: &str = "# HELP wireguard_peer_connected 1 if the latest handshake of the peer is within the handshake timeout, 0 otherwise\n# TYPE wireguard_peer_connected gauge\nwireguard_peer_connected{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 1\nwireguard_peer_connected{interface=\"wg0\",public_key=\"928vO9Lf4+Mo84cWu4k1oRyzf0AR7FTGoPKHGoTMSHk=\",allowed_ips=\"10.70.0.80/32\"} 0\nwireguard_peer_connected{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\"} 0\n";
+
+        let options = Options {
+            export_peer_connected: true,
+            handshake_timeout_seconds: 180,
+            ..Default::default()
+        };
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.ends_with(REF));
+
+        let prometheus = wg.render_with_names(None, &Options::default());
+        assert!(!prometheus.contains("wireguard_peer_connected"));
+    }
+
+    #[test]
+    fn test_render_interface_handshake_timeout() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let wg = WireGuard::try_from(&format!(
+            "lan\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t{}\t0\t0\toff
+mobile\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t{}\t0\t0\toff
+",
+            now - 1000,
+            now - 1000
+        ) as &str)
+        .unwrap();
+
+        let mut options = Options {
+            export_peer_connected: true,
+            handshake_timeout_seconds: 180,
+            ..Default::default()
+        };
+        options
+            .interface_handshake_timeout_seconds
+            .insert("mobile".to_owned(), 1200);
+        assert_eq!(options.handshake_timeout_seconds("lan"), 180);
+        assert_eq!(options.handshake_timeout_seconds("mobile"), 1200);
+
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.contains("wireguard_peer_connected{interface=\"lan\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 0\n"));
+        assert!(prometheus.contains("wireguard_peer_connected{interface=\"mobile\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\"} 1\n"));
+    }
+
+    #[test]
+    fn test_render_allowed_ips_count() {
+        const REF: &str = "# HELP wireguard_peer_allowed_ips_count Number of allowed ips of the peer\n# TYPE wireguard_peer_allowed_ips_count gauge\nwireguard_peer_allowed_ips_count{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32,10.70.0.66/32\"} 2\nwireguard_peer_allowed_ips_count{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\"} 1\n";
+
+        let wg = WireGuard::try_from(
+            "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32,10.70.0.66/32\t0\t0\t0\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t0\t0\toff
+",
+        )
+        .unwrap();
+
+        let options = Options {
+            export_allowed_ips_count: true,
+            ..Default::default()
+        };
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.ends_with(REF));
+
+        assert_eq!(allowed_ips_count("10.70.0.2/32, fd86:ea04:1115::2/128"), 2);
+        assert_eq!(allowed_ips_count("(none)"), 0);
+        assert_eq!(allowed_ips_count(""), 0);
+    }
+
+    #[test]
+    fn test_render_qos_class() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG: &str = "[Peer]
+# qos_class = gold
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+
+[Peer]
+# qos_class = gold
+PublicKey = 928vO9Lf4+Mo84cWu4k1oRyzf0AR7FTGoPKHGoTMSHk=
+AllowedIPs = 10.70.0.80/32
+
+[Peer]
+# qos_class = bronze
+PublicKey = qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=
+AllowedIPs = 10.70.0.3/32
+";
+        const REF: &str = "# HELP wireguard_qos_class_bytes_total Bytes sent to and received from the peers of the qos class\n# TYPE wireguard_qos_class_bytes_total counter\nwireguard_qos_class_bytes_total{interface=\"wg0\",qos_class=\"bronze\",direction=\"sent\"} 0\nwireguard_qos_class_bytes_total{interface=\"wg0\",qos_class=\"bronze\",direction=\"received\"} 0\nwireguard_qos_class_bytes_total{interface=\"wg0\",qos_class=\"gold\",direction=\"sent\"} 146128780\nwireguard_qos_class_bytes_total{interface=\"wg0\",qos_class=\"gold\",direction=\"received\"} 10571520\n";
+
+        let wg = WireGuard::try_from(TEXT).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+
+        let prometheus = wg.render_with_names(Some(&pehm), &Options::default());
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32,10.70.0.66/32\",qos_class=\"gold\"} 139524160\n"));
+        assert!(prometheus.ends_with(REF));
+    }
+
+    #[test]
+    fn test_render_configured_endpoint() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG: &str = "[Peer]
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+Endpoint = alice.example.com:51820
+
+[Peer]
+PublicKey = YdVOIPKt9K2MPsO2NlWQbOnFJcL/qX80mmhQwsUlA=
+AllowedIPs = 10.70.70.50/32
+Endpoint = vpn.example.com:51820 # the office
+";
+
+        let wg = WireGuard::try_from(TEXT).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+        let mut options = Options {
+            export_remote_ip_and_port: true,
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
+        // the live endpoint wins
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32,10.70.0.66/32\",remote_ip=\"37.159.76.245\",remote_port=\"29159\"} 139524160\n"));
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"pollo\",public_key=\"YdVOIPKt9K2MPsO2NlWQbOnFJcL/qX80mmhQwsUlA=\",allowed_ips=\"10.70.70.50/32\",configured_endpoint=\"vpn.example.com:51820\"} 0\n"));
+
+        options.export_remote_ip_and_port = false;
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
+        assert!(!prometheus.contains("configured_endpoint"));
+    }
+
+    #[test]
+    fn test_render_unnamed_peers() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG: &str = "[Peer]
+# friendly_name = alice
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+
+[Peer]
+PublicKey = qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=
+AllowedIPs = 10.70.0.3/32
+";
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t0\t0\toff
+wg0\tL2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\t(none)\t(none)\t10.70.0.4/32\t0\t0\t0\toff
+wg1\tMdVOIPKt9K2MPj/sO2NlWQbOnFJcL/qX80mmhQwsUlA=\t(none)\t(none)\t10.70.5.50/32\t0\t0\t0\toff
+";
+        // the peer without friendly_name is in the config so it's not unnamed
+        const REF: &str = "# HELP wireguard_peer_unnamed 1 for each peer missing from the config files\n# TYPE wireguard_peer_unnamed gauge\nwireguard_peer_unnamed{interface=\"wg0\",public_key=\"L2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\",allowed_ips=\"10.70.0.4/32\"} 1\nwireguard_peer_unnamed{interface=\"wg1\",public_key=\"MdVOIPKt9K2MPj/sO2NlWQbOnFJcL/qX80mmhQwsUlA=\",allowed_ips=\"10.70.5.50/32\"} 1\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+
+        let options = Options {
+            export_unnamed_peers: true,
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
+        assert!(prometheus.ends_with(REF));
+
+        // without the config files there is nothing to compare to
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(!prometheus.contains("wireguard_peer_unnamed"));
+    }
+
+    #[test]
+    fn test_render_configured_peers() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG: &str = "[Peer]
+# friendly_name = alice
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+
+[Peer]
+# friendly_name = bob
+PublicKey = qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=
+AllowedIPs = 10.70.0.3/32
+
+[Peer]
+PublicKey = L2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=
+AllowedIPs = 10.70.0.4/32
 ";
+        // alice is on another interface, not on the first one
+        const TEXT_A: &str = "wg1\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff
+";
+        const REF: &str = "# HELP wireguard_peers_configured_total Number of peers in the config files\n# TYPE wireguard_peers_configured_total gauge\nwireguard_peers_configured_total 3\n\n# HELP wireguard_peer_down 1 for each peer of the config files missing from every interface\n# TYPE wireguard_peer_down gauge\nwireguard_peer_down{public_key=\"L2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\"} 1\nwireguard_peer_down{public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",friendly_name=\"bob\"} 1\n";
 
-    const TEXT_ISSUE_19 : &'static str = "wg0\twJyy0Xcqk76dNQI8bnzaQvrtle5Od+wft1RBK3fC8kc=\tVfjHGauX8OxotDMm2vi3JdwOUTDsFbxCnyInJ/wAXlk=\t51820\toff
-wg0\t923V/iAdcz8BcqB0Xo6pDJzARGBJCQ6fWe+peixQyB4=\t(none)\t10.211.123.112:51820\t10.90.0.10/32,10.0.1.0/24\t0\t0\t0\toff
-wg0\t9M1fhLa9sIlT39z+SI/0a5H3mNSHYmM+NGA6sirD2nU=\t(none)\t10.211.123.113:51820\t10.90.0.3/32,10.198.171.0/24\t0\t0\t0\toff
-wg0\tgnRKXngxSppcYegsg38kEFn5Lmk4NcnRXLcZTtg2A2E=\t(none)\t10.211.123.114:51820\t10.90.0.11/32,10.189.143.0/24\t0\t0\t0\toff
-wg0\tYW7NBDEPXuW9GQlFWFzpgrivMxzdR55M8VOTX+E0thw=\t(none)\t10.211.123.115:51820\t10.90.0.12/32,10.0.2.0/24\t0\t0\t0\toff
-wg0\teVfg1BH1hcteASE16+TjShxAJNyFLQ9QIcnCaylD/AA=\t(none)\t10.211.123.116:51820\t10.90.0.13/32,10.0.3.0/24\t0\t0\t0\toff
-wg0\tlh1h+tWPahB+PAWW62ExHVVrOp9IwdjYwaGnPIXgNwY=\t(none)\t10.211.123.117:51820\t10.90.0.9/32,10.0.4.0/24\t0\t0\t0\toff
-wg0\tVQIrk1BiBfbOkkKGPiarEvhA4iPuszIL1lddvvFDvE0=\t(none)\t10.211.123.118:51820\t10.90.0.8/32,10.0.5.0/24\t0\t0\t0\toff
-wg0\tSMp58OwCNnwlzu+OdpA8xiNJzOwbl2gdMaD9CSZCC24=\t(none)\t10.211.123.119:51820\t10.90.0.14/32,10.0.6.0/24\t0\t0\t0\toff
-wg0\t+0+yMIHVCqyIf4by1gxAjqQ92iKv3bQ/JctNVUEpSlU=\t(none)\t10.211.123.120:51820\t10.90.0.7/32,10.0.7.0/24\t0\t0\t0\toff
-wg0\t2StYqQY9tyVkGcO4ykKTiTu6AQp/yIYx8I4hwBLO1jA=\t(none)\t10.211.123.121:51820\t10.90.0.15/32,10.0.8.0/24\t0\t0\t0\toff
-wg0\tqa0AMD2puDBBrs8NYQ+skIrIi/Q5NgQRZLEh5p80Mnc=\t(none)\t10.211.123.122:51820\t10.90.0.1/32,10.0.10.0/24\t0\t0\t0\toff
-wg0\tYwObmKDK4lfr5F6FHqJhDy9nkUQwbuK8wh4ac2VNSEU=\t(none)\t10.211.123.123:51820\t10.90.0.2/32,10.0.11.0/24\t0\t0\t0\toff
-wg0\tq07dm9n1UMLFbG6Dh+BNztCt7jVb9VtpVshQEf580kA=\t(none)\t10.211.123.124:51820\t10.90.0.6/32,10.0.13.0/24\t0\t0\t0\toff
-wg0\tyZOoC2t6pBcXvoczuiJqrQ+8CYvJCzcq8aqyp+APaAE=\t(none)\t10.211.123.125:51820\t10.90.0.16/32,10.0.14.0/24\t1574770531\t1232856\t12306832\toff
-wg0\tyjeBkrZqUThSSHySFzWCjxAH8cxtiWSI2I8JFD6t1UM=\t(none)\t10.211.123.126:51820\t10.90.0.5/32\t1574770705\t18576788764\t10642564136\toff
-wg0\tHtOSi37ALMnSkeAFqeWYZqlBnZqAJERhb5o/i3ZPEFI=\t(none)\t10.211.123.127:51820\t10.90.0.17/32\t1574770783\t62592693520\t1439257868\toff
-wg0\tsUsR6xufQQ8Tf0FuyY9tfEeYdhVMeFelr4ZMUrj+B0E=\t(none)\t10.211.123.128:51820\t10.90.0.18/32\t1574770693\t75066288152\t1624251784\toff";
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+
+        let options = Options {
+            export_configured_peers: true,
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
+        assert!(prometheus.ends_with(REF));
+
+        // without the config files there is nothing to compare to
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(!prometheus.contains("wireguard_peer_down"));
+    }
+
+    const CONFIG_DUPLICATE_NAMES: &str = "[Peer]
+# friendly_name = alice
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+
+[Peer]
+# friendly_name = alice
+PublicKey = qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=
+AllowedIPs = 10.70.0.3/32
+
+[Peer]
+# friendly_name = bob
+PublicKey = L2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=
+AllowedIPs = 10.70.0.4/32
+";
+    const TEXT_DUPLICATE_NAMES: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t0\t0\toff
+wg0\tL2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\t(none)\t(none)\t10.70.0.4/32\t0\t0\t0\toff
+wg1\tL2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\t(none)\t(none)\t10.70.0.4/32\t0\t0\t0\toff
+";
 
     #[test]
-    fn test_parse_issue_19() {
-        println!("starting debug");
-        let a = WireGuard::try_from(TEXT_ISSUE_19).unwrap();
-        assert!(a.interfaces.len() == 1);
-        assert!(a.interfaces["wg0"].len() == 18);
+    fn test_duplicate_friendly_names() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        let wg = WireGuard::try_from(TEXT_DUPLICATE_NAMES).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG_DUPLICATE_NAMES).unwrap();
+
+        // bob is on two interfaces but it's the same peer
+        let duplicates = wg.duplicate_friendly_names(&pehm, &PeerFilter::default());
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(
+            duplicates["alice"].iter().copied().collect::<Vec<_>>(),
+            vec![
+                "2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=",
+                "qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU="
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_duplicate_names_warn() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        let wg = WireGuard::try_from(TEXT_DUPLICATE_NAMES).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG_DUPLICATE_NAMES).unwrap();
+
+        // warn (the default) exports the names unchanged
+        let prometheus = wg.render_with_names(Some(&pehm), &Options::default());
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\",friendly_name=\"alice\"} 0\n"));
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\",friendly_name=\"alice\"} 0\n"));
+    }
+
+    #[test]
+    fn test_check_duplicate_names_fail() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        let wg = WireGuard::try_from(TEXT_DUPLICATE_NAMES).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG_DUPLICATE_NAMES).unwrap();
+
+        // only fail stops the scrape
+        assert!(wg
+            .check_duplicate_friendly_names(&pehm, &Options::default())
+            .is_ok());
+
+        let options = Options {
+            on_duplicate_name: DuplicateNameAction::Fail,
+            ..Default::default()
+        };
+        match wg.check_duplicate_friendly_names(&pehm, &options) {
+            Err(ExporterError::DuplicateFriendlyName { name, public_keys }) => {
+                assert_eq!(name, "alice");
+                assert_eq!(public_keys.len(), 2);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_render_duplicate_names_suffix() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        let wg = WireGuard::try_from(TEXT_DUPLICATE_NAMES).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG_DUPLICATE_NAMES).unwrap();
+
+        let options = Options {
+            on_duplicate_name: DuplicateNameAction::Suffix,
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\",friendly_name=\"alice (2S7mA0vE)\"} 0\n"));
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\",friendly_name=\"alice (qnoxQoQI)\"} 0\n"));
+        // unique names are left alone
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg1\",public_key=\"L2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\",allowed_ips=\"10.70.0.4/32\",friendly_name=\"bob\"} 0\n"));
+    }
+
+    #[test]
+    fn test_render_hashed_public_keys() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG: &str = "[Peer]
+# friendly_name = alice
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+";
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t1\t2\t3\toff\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+
+        let options = Options {
+            hash_public_keys: true,
+            ..Default::default()
+        };
+
+        let hash = hash_public_key("2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=");
+        assert_eq!(hash, "c541603b8e1b");
+
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
+        assert!(!prometheus.contains("2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk="));
+        // same hash in every metric and the name is still found
+        for metric in &[
+            "wireguard_sent_bytes_total",
+            "wireguard_received_bytes_total",
+            "wireguard_latest_handshake_seconds",
+        ] {
+            assert!(prometheus.contains(&format!(
+                "{}{{interface=\"wg0\",public_key=\"{}\",allowed_ips=\"10.70.0.2/32\",friendly_name=\"alice\"}}",
+                metric, hash
+            )));
+        }
+    }
+
+    #[test]
+    fn test_render_source() {
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t5\t6\toff\n";
+
+        let local = WireGuard::try_from(TEXT_A).unwrap();
+        let mut remote = WireGuard::try_from(TEXT_A).unwrap();
+        remote.set_source("http://10.0.0.1:9587/dump");
+
+        let mut wg = local;
+        wg.merge(&remote);
+
+        let prometheus = wg.render_with_names(None, &Options::default());
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 6\n"));
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",source=\"http://10.0.0.1:9587/dump\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 6\n"));
+
+        // the optional per-peer metrics carry the source too, no duplicate series
+        let options = Options {
+            export_preshared_key_presence: true,
+            export_allowed_ips_count: true,
+            export_peer_connected: true,
+            ..Default::default()
+        };
+        let prometheus = wg.render_with_names(None, &options);
+        for metric in &[
+            "wireguard_peer_has_preshared_key",
+            "wireguard_peer_allowed_ips_count",
+            "wireguard_peer_connected",
+        ] {
+            let series: Vec<&str> = prometheus
+                .lines()
+                .filter(|line| line.starts_with(&format!("{}{{", metric)))
+                .collect();
+            assert_eq!(series.len(), 2);
+            assert!(series[1].contains("source=\"http://10.0.0.1:9587/dump\""));
+            assert_ne!(series[0].split(' ').next(), series[1].split(' ').next());
+        }
+    }
+
+    #[test]
+    fn test_merge_duplicate_peers() {
+        const TEXT_A: &str = "wg0\t0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8=\t000q4qAC0ExW/BuGSmVR1nxH9JAXT6g9Wd3oEGy5lA=\t51820\toff
+wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t100\t5\t6\toff
+";
+        const TEXT_B: &str = "wg0\t0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8=\t000q4qAC0ExW/BuGSmVR1nxH9JAXT6g9Wd3oEGy5lA=\t51820\toff
+wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t200\t7\t8\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t0\t0\toff
+";
+
+        let mut wg = WireGuard::try_from(TEXT_A).unwrap();
+        wg.merge(&WireGuard::try_from(TEXT_B).unwrap());
+        assert_eq!(wg.interfaces["wg0"].len(), 3);
+
+        let prometheus = wg.render_with_names(None, &Options::default());
+        for public_key in &[
+            "2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=",
+            "qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=",
+        ] {
+            assert_eq!(
+                prometheus
+                    .matches(&format!(
+                        "wireguard_sent_bytes_total{{interface=\"wg0\",public_key=\"{}\"",
+                        public_key
+                    ))
+                    .count(),
+                1
+            );
+        }
+        // the most recent handshake wins, whatever the merge order
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 8\n"));
+
+        let mut wg = WireGuard::try_from(TEXT_B).unwrap();
+        wg.merge(&WireGuard::try_from(TEXT_A).unwrap());
+        assert_eq!(wg.render_with_names(None, &Options::default()), prometheus);
+    }
+
+    #[test]
+    fn test_render_server_public_key() {
+        // the private keys come first and differ from the public ones
+        const TEXT_A: &str = "wg0\tMIWxQ3zXQd2oE2kYyS0Kf1BrPvQ0i8zJ0cQb9rB2p2U=\tL2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\t51820\toff
+wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t10.211.123.112:51820\t10.70.0.2/32\t1\t2\t3\toff
+wg1\tgNiWCs8zMWY7Jr3pQ3zYk0mPlbEcRQDcbL8T0Ym3vXQ=\tGd3Qj2C2Y1J3EhpL7XtAQZOC5D2mtSrF3F0zxx3xiW8=\t51821\toff
+wg1\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t10.211.123.113:51820\t10.70.0.3/32\t1\t2\t3\toff
+";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+
+        let options = Options {
+            export_server_public_key: true,
+            ..Default::default()
+        };
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(!prometheus.contains("MIWxQ3zXQd2oE2kYyS0Kf1BrPvQ0i8zJ0cQb9rB2p2U="));
+        assert!(!prometheus.contains("gNiWCs8zMWY7Jr3pQ3zYk0mPlbEcRQDcbL8T0Ym3vXQ="));
+        assert!(prometheus.contains("wireguard_sent_bytes_total{interface=\"wg0\",server_public_key=\"L2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 3\n"));
+        assert!(prometheus.contains("wireguard_latest_handshake_seconds{interface=\"wg1\",server_public_key=\"Gd3Qj2C2Y1J3EhpL7XtAQZOC5D2mtSrF3F0zxx3xiW8=\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\"} 1\n"));
+
+        // off by default
+        let prometheus = wg.render_with_names(None, &Options::default());
+        assert!(!prometheus.contains("server_public_key"));
+    }
+
+    #[test]
+    fn test_render_peer_info() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG: &str = "[Peer]
+# friendly_name = alice
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+";
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t10.211.123.112:51820\t10.70.0.2/32\t1\t2\t3\toff\n";
+        const REF: &str = "# HELP wireguard_sent_bytes_total Bytes sent to the peer\n# TYPE wireguard_sent_bytes_total counter\nwireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\"} 3\n\n# HELP wireguard_received_bytes_total Bytes received from the peer\n# TYPE wireguard_received_bytes_total counter\nwireguard_received_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\"} 2\n\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\"} 1\n\n# HELP wireguard_interface_sent_bytes_total Bytes sent to all the peers of the interface\n# TYPE wireguard_interface_sent_bytes_total counter\nwireguard_interface_sent_bytes_total{interface=\"wg0\"} 3\n\n# HELP wireguard_interface_received_bytes_total Bytes received from all the peers of the interface\n# TYPE wireguard_interface_received_bytes_total counter\nwireguard_interface_received_bytes_total{interface=\"wg0\"} 2\n\n# HELP wireguard_total_sent_bytes_total Bytes sent to all the peers of all the interfaces\n# TYPE wireguard_total_sent_bytes_total counter\nwireguard_total_sent_bytes_total 3\n\n# HELP wireguard_total_received_bytes_total Bytes received from all the peers of all the interfaces\n# TYPE wireguard_total_received_bytes_total counter\nwireguard_total_received_bytes_total 2\n\n# HELP wireguard_peer_info Descriptive labels of the peer, always 1\n# TYPE wireguard_peer_info gauge\nwireguard_peer_info{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\",friendly_name=\"alice\",remote_ip=\"10.211.123.112\",remote_port=\"51820\"} 1\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+
+        let options = Options {
+            export_peer_info: true,
+            export_remote_ip_and_port: true,
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
+        assert_eq!(prometheus, REF);
+    }
+
+    #[test]
+    fn test_render_avg_bytes_per_peer() {
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t1000\t300\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t5\t0\toff
+wg1\tL2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\t(none)\t(none)\t10.70.0.4/32\t0\t7\t9\toff
+wg2\tprivate_key\tMdVOIPKt9K2MPj/sO2NlWQbOnFJcL/qX80mmhQwsUlA=\t51820\toff
+";
+        // wg2 has no peers so it's omitted
+        const REF: &str = "# HELP wireguard_interface_avg_sent_bytes_per_peer Bytes sent to the peers of the interface divided by the number of peers\n# TYPE wireguard_interface_avg_sent_bytes_per_peer gauge\nwireguard_interface_avg_sent_bytes_per_peer{interface=\"wg0\"} 150\nwireguard_interface_avg_sent_bytes_per_peer{interface=\"wg1\"} 9\n\n# HELP wireguard_interface_avg_received_bytes_per_peer Bytes received from the peers of the interface divided by the number of peers\n# TYPE wireguard_interface_avg_received_bytes_per_peer gauge\nwireguard_interface_avg_received_bytes_per_peer{interface=\"wg0\"} 502.5\nwireguard_interface_avg_received_bytes_per_peer{interface=\"wg1\"} 7\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+
+        let options = Options {
+            export_avg_bytes_per_peer: true,
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.ends_with(REF));
+    }
+
+    #[test]
+    fn test_render_handshake_age_histogram() {
+        let mut handshake_ages = BTreeMap::new();
+        handshake_ages.insert("wg0", vec![10, 100, 5000]);
+        // an interface whose peers never had a handshake
+        handshake_ages.insert("wg1", vec![]);
+
+        const REF: &str = "# HELP wireguard_handshake_age_seconds Seconds since the latest handshake of the peers of the interface (peers that never had a handshake are excluded)\n# TYPE wireguard_handshake_age_seconds histogram\nwireguard_handshake_age_seconds_bucket{interface=\"wg0\",le=\"0\"} 0\nwireguard_handshake_age_seconds_bucket{interface=\"wg0\",le=\"30\"} 1\nwireguard_handshake_age_seconds_bucket{interface=\"wg0\",le=\"60\"} 1\nwireguard_handshake_age_seconds_bucket{interface=\"wg0\",le=\"120\"} 2\nwireguard_handshake_age_seconds_bucket{interface=\"wg0\",le=\"300\"} 2\nwireguard_handshake_age_seconds_bucket{interface=\"wg0\",le=\"600\"} 2\nwireguard_handshake_age_seconds_bucket{interface=\"wg0\",le=\"1800\"} 2\nwireguard_handshake_age_seconds_bucket{interface=\"wg0\",le=\"3600\"} 2\nwireguard_handshake_age_seconds_bucket{interface=\"wg0\",le=\"+Inf\"} 3\nwireguard_handshake_age_seconds_sum{interface=\"wg0\"} 5110\nwireguard_handshake_age_seconds_count{interface=\"wg0\"} 3\nwireguard_handshake_age_seconds_bucket{interface=\"wg1\",le=\"0\"} 0\nwireguard_handshake_age_seconds_bucket{interface=\"wg1\",le=\"30\"} 0\nwireguard_handshake_age_seconds_bucket{interface=\"wg1\",le=\"60\"} 0\nwireguard_handshake_age_seconds_bucket{interface=\"wg1\",le=\"120\"} 0\nwireguard_handshake_age_seconds_bucket{interface=\"wg1\",le=\"300\"} 0\nwireguard_handshake_age_seconds_bucket{interface=\"wg1\",le=\"600\"} 0\nwireguard_handshake_age_seconds_bucket{interface=\"wg1\",le=\"1800\"} 0\nwireguard_handshake_age_seconds_bucket{interface=\"wg1\",le=\"3600\"} 0\nwireguard_handshake_age_seconds_bucket{interface=\"wg1\",le=\"+Inf\"} 0\nwireguard_handshake_age_seconds_sum{interface=\"wg1\"} 0\nwireguard_handshake_age_seconds_count{interface=\"wg1\"} 0\n";
+
+        let mut rendered = String::new();
+        write_handshake_age_histogram(&mut rendered, &handshake_ages, &Options::default());
+        assert_eq!(rendered, REF);
+    }
+
+    #[test]
+    fn test_render_allowed_ips_unexpected() {
+        use crate::expected_allowed_ips::expected_allowed_ips_try_from;
+
+        const EXPECTED: &str = r#"{
+    "2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=": ["10.70.0.66/32", "10.70.0.2/32"],
+    "qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=": "10.70.0.3/32"
+}"#;
+        // the second peer gained a whole subnet, the third is not in the baseline
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32,10.70.0.66/32\t0\t0\t0\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32,0.0.0.0/0\t0\t0\t0\toff
+wg0\tL2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\t(none)\t(none)\t10.70.0.4/32\t0\t0\t0\toff
+";
+        const REF: &str = "# HELP wireguard_peer_allowed_ips_unexpected 1 if the allowed ips of the peer differ from the expected ones, 0 otherwise\n# TYPE wireguard_peer_allowed_ips_unexpected gauge\nwireguard_peer_allowed_ips_unexpected{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32,10.70.0.66/32\"} 0\nwireguard_peer_allowed_ips_unexpected{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32,0.0.0.0/0\"} 1\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+
+        let options = Options {
+            expected_allowed_ips: expected_allowed_ips_try_from(EXPECTED).unwrap(),
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.ends_with(REF));
+    }
+
+    #[test]
+    fn test_to_openmetrics() {
+        let wg = WireGuard::try_from(
+            "wg0\t0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8=\t000q4qAC0ExW/BuGSmVR1nxH9JAXT6g9Wd3oEGy5lA=\t51820\toff
+wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t1574770531\t3\t2\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t0\t0\toff
+",
+        )
+        .unwrap();
+        const REF: &str = "# HELP wireguard_sent_bytes Bytes sent to the peer\n# TYPE wireguard_sent_bytes counter\nwireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 2\nwireguard_sent_bytes_total{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\"} 0\n# HELP wireguard_received_bytes Bytes received from the peer\n# TYPE wireguard_received_bytes counter\nwireguard_received_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 3\nwireguard_received_bytes_total{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\"} 0\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 1574770531\nwireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\"} 0\n# HELP wireguard_interface_sent_bytes Bytes sent to all the peers of the interface\n# TYPE wireguard_interface_sent_bytes counter\nwireguard_interface_sent_bytes_total{interface=\"wg0\"} 2\n# HELP wireguard_interface_received_bytes Bytes received from all the peers of the interface\n# TYPE wireguard_interface_received_bytes counter\nwireguard_interface_received_bytes_total{interface=\"wg0\"} 3\n# HELP wireguard_total_sent_bytes Bytes sent to all the peers of all the interfaces\n# TYPE wireguard_total_sent_bytes counter\nwireguard_total_sent_bytes_total 2\n# HELP wireguard_total_received_bytes Bytes received from all the peers of all the interfaces\n# TYPE wireguard_total_received_bytes counter\nwireguard_total_received_bytes_total 3\n# EOF\n";
+
+        let prometheus = wg.render_with_names(None, &Options::default());
+        assert_eq!(to_openmetrics(&prometheus, false), REF);
+
+        // the peer that never had a handshake has no timestamp
+        let openmetrics = to_openmetrics(&prometheus, true);
+        assert!(openmetrics.contains("allowed_ips=\"10.70.0.2/32\"} 1574770531 1574770531\n"));
+        assert!(openmetrics.contains("allowed_ips=\"10.70.0.3/32\"} 0\n"));
+        assert!(openmetrics.contains("} 3\nwireguard_received_bytes_total{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\"} 0\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 1574770531 1574770531\nwireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\"} 0\n# HELP wireguard_interface_sent_bytes "));
+
+        // the histograms and gauges are untouched
+        assert_eq!(
+            to_openmetrics(
+                "# HELP wireguard_up 1 if wg ran\n# TYPE wireguard_up gauge\nwireguard_up 1\n",
+                true
+            ),
+            "# HELP wireguard_up 1 if wg ran\n# TYPE wireguard_up gauge\nwireguard_up 1\n# EOF\n"
+        );
+    }
+
+    #[test]
+    fn test_is_valid_label_name() {
+        assert!(is_valid_label_name("region"));
+        assert!(is_valid_label_name("_host1"));
+        assert!(!is_valid_label_name(""));
+        assert!(!is_valid_label_name("1host"));
+        assert!(!is_valid_label_name("host-name"));
+        assert!(!is_valid_label_name("__name__"));
+    }
+
+    #[test]
+    fn test_render_build_info() {
+        let build_info = render_build_info(&Options::default());
+        assert!(build_info.starts_with("# HELP wireguard_exporter_build_info "));
+        assert!(build_info.contains(&format!(
+            "wireguard_exporter_build_info{{version=\"{}\",rustc=\"",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(build_info.ends_with("} 1\n"));
+    }
+
+    #[test]
+    fn test_peer_labels() {
+        let labels = [("public_key", "a"), ("allowed_ips", "10.70.0.2/32")];
+        let mut options = Options::default();
+
+        // the samples of the peers are written as PrometheusInstance renders them
+        for static_labels in [vec![], vec![("region".to_owned(), "eu".to_owned())]] {
+            for no_interface_label in [false, true] {
+                options.static_labels = static_labels.clone();
+                options.no_interface_label = no_interface_label;
+                let mut rendered = String::new();
+                write_sample(
+                    &mut rendered,
+                    "metric",
+                    &peer_labels("wg0", &labels, &options),
+                    1,
+                );
+                let instance = labels
+                    .iter()
+                    .fold(interface_instance("wg0", &options), |instance, label| {
+                        instance.with_label(label.0, label.1)
+                    });
+                let mut expected = String::new();
+                write_instance(&mut expected, "metric", &instance.with_value(1));
+                assert_eq!(rendered, expected);
+            }
+        }
+
+        options.static_labels.clear();
+        assert_eq!(peer_labels("wg0", &[], &options), "");
+
+        // every value is escaped, once
+        options.no_interface_label = false;
+        assert_eq!(
+            peer_labels(
+                "wg\"0",
+                &[
+                    ("site", "a\"b"),
+                    ("remote_host", "c\\d"),
+                    ("configured_endpoint", "e\nf"),
+                    ("qos_class", "g\"h"),
+                    ("source", "http://10.0.0.1:9587/dump?x=\"y\""),
+                ],
+                &options
+            ),
+            r#"{interface="wg\"0",site="a\"b",remote_host="c\\d",configured_endpoint="e\nf",qos_class="g\"h",source="http://10.0.0.1:9587/dump?x=\"y\""}"#
+        );
+    }
+
+    #[test]
+    fn test_escape_label_value() {
+        assert_eq!(escape_label_value(r#"a\b"c"#), r#"a\\b\"c"#);
+        assert_eq!(escape_label_value("two\nlines"), r#"two\nlines"#);
+    }
+
+    #[test]
+    fn test_parse_fwmark() {
+        assert_eq!(parse_fwmark("off"), None);
+        assert_eq!(parse_fwmark("(none)"), None);
+        assert_eq!(parse_fwmark("0xca6c"), Some(51820));
+        assert_eq!(parse_fwmark("51820"), Some(51820));
+        assert_eq!(parse_fwmark("0x0"), None);
+        assert_eq!(parse_fwmark("0xzz"), None);
+    }
+
+    #[test]
+    fn test_render_listen_port_and_fwmark() {
+        const TEXT_A: &str = "wg0\t(none)\tL2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\t51820\t0xca6c
+wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t10.211.123.112:51820\t10.70.0.2/32\t1\t2\t3\toff
+wg1\t(none)\tGd3Qj2C2Y1J3EhpL7XtAQZOC5D2mtSrF3F0zxx3xiW8=\t51821\toff
+";
+        const REF: &str = "# HELP wireguard_interface_listen_port UDP port the interface listens on\n# TYPE wireguard_interface_listen_port gauge\nwireguard_interface_listen_port{interface=\"wg0\"} 51820\nwireguard_interface_listen_port{interface=\"wg1\"} 51821\n\n# HELP wireguard_interface_fwmark fwmark of the packets sent by the interface, missing if off\n# TYPE wireguard_interface_fwmark gauge\nwireguard_interface_fwmark{interface=\"wg0\"} 51820\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        match &wg.interfaces["wg0"][0] {
+            Endpoint::Local(lep) => {
+                assert_eq!(
+                    lep.public_key,
+                    "L2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008="
+                );
+                assert_eq!(lep.fwmark, Some(0xca6c));
+            }
+            Endpoint::Remote(_) => panic!("expected the local endpoint"),
+        }
+
+        let options = Options {
+            export_listen_port_and_fwmark: true,
+            ..Default::default()
+        };
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.ends_with(REF));
+    }
+
+    #[test]
+    fn test_exclude_interfaces() {
+        const TEXT_A: &str = "wg0\t(none)\tL2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\t51820\toff
+wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t10.211.123.112:51820\t10.70.0.2/32\t1\t2\t3\toff
+wgmgmt\t(none)\tGd3Qj2C2Y1J3EhpL7XtAQZOC5D2mtSrF3F0zxx3xiW8=\t51821\toff
+wgmgmt\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t10.211.123.113:51820\t10.70.0.3/32\t1\t2\t3\toff
+";
+
+        let mut wg = WireGuard::try_from(TEXT_A).unwrap();
+        wg.exclude_interfaces(&["wgmgmt".to_owned(), "wg9".to_owned()]);
+
+        assert_eq!(wg.interfaces.len(), 1);
+        assert!(wg.interfaces.contains_key("wg0"));
+        let prometheus = wg.render_with_names(None, &Options::default());
+        assert!(!prometheus.contains("wgmgmt"));
+    }
+
+    #[test]
+    fn test_invalid_public_keys() {
+        const TEXT_A: &str = "wg0\t(none)\tL2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\t51820\toff
+wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t10.211.123.112:51820\t10.70.0.2/32\t1\t2\t3\toff
+wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHH\t(none)\t10.211.123.113:51820\t10.70.0.3/32\t1\t2\t3\toff
+wg0\tnot base64 at all!\t(none)\t10.211.123.114:51820\t10.70.0.4/32\t1\t2\t3\toff
+";
+
+        assert!(is_valid_public_key(
+            "2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk="
+        ));
+        // valid base64, but only 24 bytes
+        assert!(!is_valid_public_key("2S7mA0vEMethCNQrJpJKE81/JmhgtB+t"));
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        assert_eq!(wg.invalid_public_keys(), 2);
+
+        const REF: &str = "# HELP wireguard_invalid_public_keys_total Public keys that do not decode as 32 bytes of base64
+# TYPE wireguard_invalid_public_keys_total counter
+wireguard_invalid_public_keys_total 2
+";
+        assert_eq!(render_invalid_public_keys(2, &Options::default()), REF);
+    }
+
+    #[test]
+    fn test_render_json() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG: &str = "[Peer]
+# friendly_name = alice
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+";
+        const TEXT_A: &str = "wg0\tsecret_private_key\tL2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\t51820\toff
+wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t10.211.123.112:51820\t10.70.0.2/32\t1\t2\t3\t25
+";
+        const REF: &str = r#"{"wg0":[{"kind":"local","public_key":"L2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=","local_port":51820,"fwmark":null},{"kind":"remote","public_key":"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=","remote_ip":"10.211.123.112","remote_port":51820,"allowed_ips":"10.70.0.2/32","has_preshared_key":false,"latest_handshake":1,"sent_bytes":3,"received_bytes":2,"persistent_keepalive":25,"source":null,"friendly_name":"alice"}]}"#;
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+
+        let json = wg.render_json(Some(&pehm), &Options::default()).unwrap();
+        assert_eq!(json, REF);
+        assert!(!json.contains("secret_private_key"));
+    }
+
+    #[test]
+    fn test_render_json_friendly_name_not_escaped() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG: &str = r#"[Peer]
+# friendly_name = C:\vpn "alice"
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+"#;
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+
+        // escaped once, by JSON
+        let json = wg.render_json(Some(&pehm), &Options::default()).unwrap();
+        assert!(json.contains(r#""friendly_name":"C:\\vpn \"alice\"""#));
+    }
+
+    #[test]
+    fn test_render_no_interface_label() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG: &str = r#"[Peer]
+# friendly_name = alice,interface=
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+"#;
+        const TEXT_A: &str = "wg0\t0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8=\t000q4qAC0ExW/BuGSmVR1nxH9JAXT6g9Wd3oEGy5lA=\t51820\toff
+wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t3\t2\toff
+";
+        const REF: &str = "# HELP wireguard_sent_bytes_total Bytes sent to the peer\n# TYPE wireguard_sent_bytes_total counter\nwireguard_sent_bytes_total{public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\",friendly_name=\"alice,interface=\"} 2\n\n# HELP wireguard_received_bytes_total Bytes received from the peer\n# TYPE wireguard_received_bytes_total counter\nwireguard_received_bytes_total{public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\",friendly_name=\"alice,interface=\"} 3\n\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\",friendly_name=\"alice,interface=\"} 0\n\n# HELP wireguard_interface_sent_bytes_total Bytes sent to all the peers of the interface\n# TYPE wireguard_interface_sent_bytes_total counter\nwireguard_interface_sent_bytes_total 2\n\n# HELP wireguard_interface_received_bytes_total Bytes received from all the peers of the interface\n# TYPE wireguard_interface_received_bytes_total counter\nwireguard_interface_received_bytes_total 3\n\n# HELP wireguard_total_sent_bytes_total Bytes sent to all the peers of all the interfaces\n# TYPE wireguard_total_sent_bytes_total counter\nwireguard_total_sent_bytes_total 2\n\n# HELP wireguard_total_received_bytes_total Bytes received from all the peers of all the interfaces\n# TYPE wireguard_total_received_bytes_total counter\nwireguard_total_received_bytes_total 3\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+        let options = Options {
+            no_interface_label: true,
+            ..Default::default()
+        };
+        assert_eq!(wg.render_with_names(Some(&pehm), &options), REF);
+
+        let mut up = BTreeMap::new();
+        up.insert("all".to_owned(), true);
+        assert!(render_up(&up, &BTreeMap::new(), &options).ends_with("\nwireguard_up 1\n"));
+
+        let mut handshake_ages = BTreeMap::new();
+        handshake_ages.insert("wg0", vec![10]);
+        let mut rendered = String::new();
+        write_handshake_age_histogram(&mut rendered, &handshake_ages, &options);
+        assert!(rendered.contains("\nwireguard_handshake_age_seconds_bucket{le=\"+Inf\"} 1\n"));
+    }
+
+    #[test]
+    fn test_render_escaped_friendly_name() {
+        use crate::wireguard_config::{
+            merge_names_file, names_file_try_from, peer_entry_hashmap_try_from,
+        };
+
+        const CONFIG: &str = r#"[Peer]
+# friendly_name = C:\vpn "alice"
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+
+[Peer]
+# friendly_name = bob,laptop
+PublicKey = qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=
+AllowedIPs = 10.70.0.3/32
+"#;
+        const NAMES: &str = r#"{"928vO9Lf4+Mo84cWu4k1oRyzf0AR7FTGoPKHGoTMSHk=": "first\nsecond"}"#;
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t0\t0\toff
+wg0\t928vO9Lf4+Mo84cWu4k1oRyzf0AR7FTGoPKHGoTMSHk=\t(none)\t(none)\t10.70.0.4/32\t0\t0\t0\toff
+";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let mut pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+        merge_names_file(&mut pehm, names_file_try_from("names.json", NAMES).unwrap());
+        let options = Options {
+            name_comma_replacement: Some("\\".to_owned()),
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
+        assert!(prometheus.contains(r#"friendly_name="C:\\vpn \"alice\"""#));
+        // the replacement is escaped too
+        assert!(prometheus.contains(r#"friendly_name="bob\\laptop""#));
+        assert!(prometheus.contains(r#"friendly_name="first\nsecond""#));
+        // no series is split by a raw newline
+        assert!(prometheus
+            .lines()
+            .all(|l| l.is_empty() || l.starts_with('#') || l.starts_with("wireguard_")));
+    }
+
+    #[test]
+    fn test_is_connected() {
+        assert!(!is_connected(0, 1000, 180));
+        assert!(is_connected(1000, 1000, 180));
+        assert!(is_connected(820, 1000, 180));
+        assert!(!is_connected(819, 1000, 180));
+        // clock skew, the handshake is in the future
+        assert!(is_connected(1010, 1000, 180));
+    }
+
+    #[test]
+    fn test_render_connected_only() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let text = format!("wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t37.159.76.245:29159\t10.70.0.2/32\t{}\t10288508\t139524160\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t0\t0\toff
+wg0\t928vO9Lf4+Mo84cWu4k1oRyzf0AR7FTGoPKHGoTMSHk=\t(none)\t5.90.62.106:21741\t10.70.0.80/32\t1555344925\t283012\t6604620\toff
+", now);
+        const REF: &str = "# HELP wireguard_peers_total Number of peers of the interface by connection state\n# TYPE wireguard_peers_total gauge\nwireguard_peers_total{interface=\"wg0\",state=\"connected\"} 1\nwireguard_peers_total{interface=\"wg0\",state=\"disconnected\"} 2\n";
 
-        let e1 = match &a.interfaces["wg0"][1] {
-            Endpoint::Local(_) => panic!(),
-            Endpoint::Remote(re) => re,
+        let wg = WireGuard::try_from(&text as &str).unwrap();
+
+        let options = Options {
+            connected_only: true,
+            handshake_timeout_seconds: 180,
+            ..Default::default()
         };
-        assert_eq!(
-            e1.public_key,
-            "923V/iAdcz8BcqB0Xo6pDJzARGBJCQ6fWe+peixQyB4="
-        );
 
-        assert_eq!(e1.remote_ip, Some("10.211.123.112".to_owned()));
-        assert_eq!(e1.allowed_ips, "10.90.0.10/32,10.0.1.0/24".to_owned());
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus.contains("public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\""));
+        assert!(!prometheus.contains("public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\""));
+        assert!(!prometheus.contains("public_key=\"928vO9Lf4+Mo84cWu4k1oRyzf0AR7FTGoPKHGoTMSHk=\""));
+        assert!(prometheus.ends_with(REF));
+    }
 
-        let e17 = match &a.interfaces["wg0"][17] {
-            Endpoint::Local(_) => panic!(),
-            Endpoint::Remote(re) => re,
+    #[test]
+    fn test_render_totals_connected_only() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let text = format!("wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t37.159.76.245:29159\t10.70.0.2/32\t{}\t10288508\t139524160\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t0\t0\toff
+wg0\t928vO9Lf4+Mo84cWu4k1oRyzf0AR7FTGoPKHGoTMSHk=\t(none)\t5.90.62.106:21741\t10.70.0.80/32\t1555344925\t283012\t6604620\toff
+", now);
+
+        let wg = WireGuard::try_from(&text as &str).unwrap();
+
+        let options = Options {
+            connected_only: true,
+            handshake_timeout_seconds: 180,
+            ..Default::default()
         };
-        assert_eq!(
-            e17.public_key,
-            "sUsR6xufQQ8Tf0FuyY9tfEeYdhVMeFelr4ZMUrj+B0E="
-        );
 
-        assert_eq!(e17.remote_ip, Some("10.211.123.128".to_owned()));
-        assert_eq!(e17.allowed_ips, "10.90.0.18/32".to_owned());
-        assert_eq!(e17.latest_handshake, 1574770693);
-        assert_eq!(e17.sent_bytes, 1624251784);
-        assert_eq!(e17.received_bytes, 75066288152);
+        // only the connected peer is exported, the totals still sum the
+        // disconnected ones so they do not drop when a peer disconnects
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(!prometheus.contains("public_key=\"928vO9Lf4+Mo84cWu4k1oRyzf0AR7FTGoPKHGoTMSHk=\""));
+        assert!(prometheus
+            .contains("\nwireguard_interface_sent_bytes_total{interface=\"wg0\"} 146128780\n"));
+        assert!(prometheus
+            .contains("\nwireguard_interface_received_bytes_total{interface=\"wg0\"} 10571520\n"));
+        assert!(prometheus.contains("\nwireguard_total_sent_bytes_total 146128780\n"));
+        assert!(prometheus.contains("\nwireguard_total_received_bytes_total 10571520\n"));
+    }
 
-        let pe = PeerEntryHashMap::new();
+    #[test]
+    fn test_render_skip_never_connected() {
+        const TEXT_NEVER: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t37.159.76.245:29159\t10.70.0.2/32\t1555771458\t10288508\t139524160\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70.0.3/32\t0\t0\t0\toff
+";
+        const REF: &str = "# HELP wireguard_sent_bytes_total Bytes sent to the peer\n# TYPE wireguard_sent_bytes_total counter\nwireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 139524160\n\n# HELP wireguard_received_bytes_total Bytes received from the peer\n# TYPE wireguard_received_bytes_total counter\nwireguard_received_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 10288508\n\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 1555771458\n\n# HELP wireguard_interface_sent_bytes_total Bytes sent to all the peers of the interface\n# TYPE wireguard_interface_sent_bytes_total counter\nwireguard_interface_sent_bytes_total{interface=\"wg0\"} 139524160\n\n# HELP wireguard_interface_received_bytes_total Bytes received from all the peers of the interface\n# TYPE wireguard_interface_received_bytes_total counter\nwireguard_interface_received_bytes_total{interface=\"wg0\"} 10288508\n\n# HELP wireguard_total_sent_bytes_total Bytes sent to all the peers of all the interfaces\n# TYPE wireguard_total_sent_bytes_total counter\nwireguard_total_sent_bytes_total 139524160\n\n# HELP wireguard_total_received_bytes_total Bytes received from all the peers of all the interfaces\n# TYPE wireguard_total_received_bytes_total counter\nwireguard_total_received_bytes_total 10288508\n\n# HELP wireguard_peers_total Number of peers of the interface by connection state\n# TYPE wireguard_peers_total gauge\nwireguard_peers_total{interface=\"wg0\",state=\"connected\"} 0\nwireguard_peers_total{interface=\"wg0\",state=\"disconnected\"} 2\n";
 
-        let options = Options {
-            verbose: true,
-            prepend_sudo: true,
-            separate_allowed_ips: true,
-            extract_names_config_files: None,
-            interfaces: None,
-            export_remote_ip_and_port: true,
-            export_latest_handshake_delay: false,
+        let wg = WireGuard::try_from(TEXT_NEVER).unwrap();
+
+        let mut options = Options {
+            skip_never_connected: true,
+            count_never_connected: true,
+            ..Default::default()
         };
+        let prometheus = wg.render_with_names(None, &options);
+        assert_eq!(prometheus, REF);
 
-        let s = a.render_with_names(Some(&pe), &options);
-        println!("{}", s);
+        options.count_never_connected = false;
+        let prometheus = wg.render_with_names(None, &options);
+        assert!(prometheus
+            .ends_with("wireguard_peers_total{interface=\"wg0\",state=\"disconnected\"} 1\n"));
+    }
 
-        let s_ok = "# HELP wireguard_sent_bytes_total Bytes sent to the peer
-# TYPE wireguard_sent_bytes_total counter
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"923V/iAdcz8BcqB0Xo6pDJzARGBJCQ6fWe+peixQyB4=\",remote_ip=\"10.211.123.112\",allowed_ip_0=\"10.90.0.10\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.1.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"9M1fhLa9sIlT39z+SI/0a5H3mNSHYmM+NGA6sirD2nU=\",remote_ip=\"10.211.123.113\",allowed_ip_0=\"10.90.0.3\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.198.171.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"gnRKXngxSppcYegsg38kEFn5Lmk4NcnRXLcZTtg2A2E=\",remote_ip=\"10.211.123.114\",allowed_ip_0=\"10.90.0.11\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.189.143.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"YW7NBDEPXuW9GQlFWFzpgrivMxzdR55M8VOTX+E0thw=\",remote_ip=\"10.211.123.115\",allowed_ip_0=\"10.90.0.12\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.2.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"eVfg1BH1hcteASE16+TjShxAJNyFLQ9QIcnCaylD/AA=\",remote_ip=\"10.211.123.116\",allowed_ip_0=\"10.90.0.13\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.3.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"lh1h+tWPahB+PAWW62ExHVVrOp9IwdjYwaGnPIXgNwY=\",remote_ip=\"10.211.123.117\",allowed_ip_0=\"10.90.0.9\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.4.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"VQIrk1BiBfbOkkKGPiarEvhA4iPuszIL1lddvvFDvE0=\",remote_ip=\"10.211.123.118\",allowed_ip_0=\"10.90.0.8\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.5.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"SMp58OwCNnwlzu+OdpA8xiNJzOwbl2gdMaD9CSZCC24=\",remote_ip=\"10.211.123.119\",allowed_ip_0=\"10.90.0.14\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.6.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"+0+yMIHVCqyIf4by1gxAjqQ92iKv3bQ/JctNVUEpSlU=\",remote_ip=\"10.211.123.120\",allowed_ip_0=\"10.90.0.7\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.7.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2StYqQY9tyVkGcO4ykKTiTu6AQp/yIYx8I4hwBLO1jA=\",remote_ip=\"10.211.123.121\",allowed_ip_0=\"10.90.0.15\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.8.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"qa0AMD2puDBBrs8NYQ+skIrIi/Q5NgQRZLEh5p80Mnc=\",remote_ip=\"10.211.123.122\",allowed_ip_0=\"10.90.0.1\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.10.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"YwObmKDK4lfr5F6FHqJhDy9nkUQwbuK8wh4ac2VNSEU=\",remote_ip=\"10.211.123.123\",allowed_ip_0=\"10.90.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.11.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"q07dm9n1UMLFbG6Dh+BNztCt7jVb9VtpVshQEf580kA=\",remote_ip=\"10.211.123.124\",allowed_ip_0=\"10.90.0.6\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.13.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"yZOoC2t6pBcXvoczuiJqrQ+8CYvJCzcq8aqyp+APaAE=\",remote_ip=\"10.211.123.125\",allowed_ip_0=\"10.90.0.16\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.14.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 12306832
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"yjeBkrZqUThSSHySFzWCjxAH8cxtiWSI2I8JFD6t1UM=\",remote_ip=\"10.211.123.126\",allowed_ip_0=\"10.90.0.5\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 10642564136
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"HtOSi37ALMnSkeAFqeWYZqlBnZqAJERhb5o/i3ZPEFI=\",remote_ip=\"10.211.123.127\",allowed_ip_0=\"10.90.0.17\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 1439257868
-wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"sUsR6xufQQ8Tf0FuyY9tfEeYdhVMeFelr4ZMUrj+B0E=\",remote_ip=\"10.211.123.128\",allowed_ip_0=\"10.90.0.18\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 1624251784
+    #[test]
+    fn test_parse_remote_endpoint() {
+        assert_eq!(
+            parse_remote_endpoint("10.211.123.112:51820"),
+            Some(("10.211.123.112".to_owned(), 51820))
+        );
+        assert_eq!(
+            parse_remote_endpoint("[2001:db8::1]:51820"),
+            Some(("2001:db8::1".to_owned(), 51820))
+        );
+        assert_eq!(
+            parse_remote_endpoint("[fe80::1%wg0]:51820"),
+            Some(("fe80::1".to_owned(), 51820))
+        );
+        assert_eq!(
+            parse_remote_endpoint("[fe80::4a5d:60ff:fe8f:2ad5%eth0.10]:1234"),
+            Some(("fe80::4a5d:60ff:fe8f:2ad5".to_owned(), 1234))
+        );
+        assert_eq!(
+            parse_remote_endpoint("vpn.example.com:51820"),
+            Some(("vpn.example.com".to_owned(), 51820))
+        );
 
-# HELP wireguard_received_bytes_total Bytes received from the peer
-# TYPE wireguard_received_bytes_total counter
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"923V/iAdcz8BcqB0Xo6pDJzARGBJCQ6fWe+peixQyB4=\",remote_ip=\"10.211.123.112\",allowed_ip_0=\"10.90.0.10\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.1.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"9M1fhLa9sIlT39z+SI/0a5H3mNSHYmM+NGA6sirD2nU=\",remote_ip=\"10.211.123.113\",allowed_ip_0=\"10.90.0.3\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.198.171.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"gnRKXngxSppcYegsg38kEFn5Lmk4NcnRXLcZTtg2A2E=\",remote_ip=\"10.211.123.114\",allowed_ip_0=\"10.90.0.11\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.189.143.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"YW7NBDEPXuW9GQlFWFzpgrivMxzdR55M8VOTX+E0thw=\",remote_ip=\"10.211.123.115\",allowed_ip_0=\"10.90.0.12\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.2.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"eVfg1BH1hcteASE16+TjShxAJNyFLQ9QIcnCaylD/AA=\",remote_ip=\"10.211.123.116\",allowed_ip_0=\"10.90.0.13\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.3.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"lh1h+tWPahB+PAWW62ExHVVrOp9IwdjYwaGnPIXgNwY=\",remote_ip=\"10.211.123.117\",allowed_ip_0=\"10.90.0.9\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.4.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"VQIrk1BiBfbOkkKGPiarEvhA4iPuszIL1lddvvFDvE0=\",remote_ip=\"10.211.123.118\",allowed_ip_0=\"10.90.0.8\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.5.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"SMp58OwCNnwlzu+OdpA8xiNJzOwbl2gdMaD9CSZCC24=\",remote_ip=\"10.211.123.119\",allowed_ip_0=\"10.90.0.14\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.6.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"+0+yMIHVCqyIf4by1gxAjqQ92iKv3bQ/JctNVUEpSlU=\",remote_ip=\"10.211.123.120\",allowed_ip_0=\"10.90.0.7\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.7.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"2StYqQY9tyVkGcO4ykKTiTu6AQp/yIYx8I4hwBLO1jA=\",remote_ip=\"10.211.123.121\",allowed_ip_0=\"10.90.0.15\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.8.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"qa0AMD2puDBBrs8NYQ+skIrIi/Q5NgQRZLEh5p80Mnc=\",remote_ip=\"10.211.123.122\",allowed_ip_0=\"10.90.0.1\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.10.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"YwObmKDK4lfr5F6FHqJhDy9nkUQwbuK8wh4ac2VNSEU=\",remote_ip=\"10.211.123.123\",allowed_ip_0=\"10.90.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.11.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"q07dm9n1UMLFbG6Dh+BNztCt7jVb9VtpVshQEf580kA=\",remote_ip=\"10.211.123.124\",allowed_ip_0=\"10.90.0.6\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.13.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"yZOoC2t6pBcXvoczuiJqrQ+8CYvJCzcq8aqyp+APaAE=\",remote_ip=\"10.211.123.125\",allowed_ip_0=\"10.90.0.16\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.14.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 1232856
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"yjeBkrZqUThSSHySFzWCjxAH8cxtiWSI2I8JFD6t1UM=\",remote_ip=\"10.211.123.126\",allowed_ip_0=\"10.90.0.5\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 18576788764
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"HtOSi37ALMnSkeAFqeWYZqlBnZqAJERhb5o/i3ZPEFI=\",remote_ip=\"10.211.123.127\",allowed_ip_0=\"10.90.0.17\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 62592693520
-wireguard_received_bytes_total{interface=\"wg0\",public_key=\"sUsR6xufQQ8Tf0FuyY9tfEeYdhVMeFelr4ZMUrj+B0E=\",remote_ip=\"10.211.123.128\",allowed_ip_0=\"10.90.0.18\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 75066288152
+        assert_eq!(parse_remote_endpoint("(none)"), None);
+        assert_eq!(parse_remote_endpoint("10.211.123.112"), None);
+        assert_eq!(parse_remote_endpoint("10.211.123.112:99999"), None);
+        assert_eq!(parse_remote_endpoint("2001:db8::1:51820"), None);
+        assert_eq!(parse_remote_endpoint("[2001:db8::zz]:51820"), None);
+        assert_eq!(parse_remote_endpoint("[fe80::1%wg0:51820"), None);
+        assert_eq!(parse_remote_endpoint("bad host:51820"), None);
+    }
 
-# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake
-# TYPE wireguard_latest_handshake_seconds gauge
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"923V/iAdcz8BcqB0Xo6pDJzARGBJCQ6fWe+peixQyB4=\",remote_ip=\"10.211.123.112\",allowed_ip_0=\"10.90.0.10\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.1.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"9M1fhLa9sIlT39z+SI/0a5H3mNSHYmM+NGA6sirD2nU=\",remote_ip=\"10.211.123.113\",allowed_ip_0=\"10.90.0.3\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.198.171.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"gnRKXngxSppcYegsg38kEFn5Lmk4NcnRXLcZTtg2A2E=\",remote_ip=\"10.211.123.114\",allowed_ip_0=\"10.90.0.11\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.189.143.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"YW7NBDEPXuW9GQlFWFzpgrivMxzdR55M8VOTX+E0thw=\",remote_ip=\"10.211.123.115\",allowed_ip_0=\"10.90.0.12\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.2.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"eVfg1BH1hcteASE16+TjShxAJNyFLQ9QIcnCaylD/AA=\",remote_ip=\"10.211.123.116\",allowed_ip_0=\"10.90.0.13\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.3.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"lh1h+tWPahB+PAWW62ExHVVrOp9IwdjYwaGnPIXgNwY=\",remote_ip=\"10.211.123.117\",allowed_ip_0=\"10.90.0.9\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.4.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"VQIrk1BiBfbOkkKGPiarEvhA4iPuszIL1lddvvFDvE0=\",remote_ip=\"10.211.123.118\",allowed_ip_0=\"10.90.0.8\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.5.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"SMp58OwCNnwlzu+OdpA8xiNJzOwbl2gdMaD9CSZCC24=\",remote_ip=\"10.211.123.119\",allowed_ip_0=\"10.90.0.14\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.6.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"+0+yMIHVCqyIf4by1gxAjqQ92iKv3bQ/JctNVUEpSlU=\",remote_ip=\"10.211.123.120\",allowed_ip_0=\"10.90.0.7\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.7.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"2StYqQY9tyVkGcO4ykKTiTu6AQp/yIYx8I4hwBLO1jA=\",remote_ip=\"10.211.123.121\",allowed_ip_0=\"10.90.0.15\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.8.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"qa0AMD2puDBBrs8NYQ+skIrIi/Q5NgQRZLEh5p80Mnc=\",remote_ip=\"10.211.123.122\",allowed_ip_0=\"10.90.0.1\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.10.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"YwObmKDK4lfr5F6FHqJhDy9nkUQwbuK8wh4ac2VNSEU=\",remote_ip=\"10.211.123.123\",allowed_ip_0=\"10.90.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.11.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"q07dm9n1UMLFbG6Dh+BNztCt7jVb9VtpVshQEf580kA=\",remote_ip=\"10.211.123.124\",allowed_ip_0=\"10.90.0.6\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.13.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 0
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"yZOoC2t6pBcXvoczuiJqrQ+8CYvJCzcq8aqyp+APaAE=\",remote_ip=\"10.211.123.125\",allowed_ip_0=\"10.90.0.16\",allowed_subnet_0=\"32\",allowed_ip_1=\"10.0.14.0\",allowed_subnet_1=\"24\",remote_port=\"51820\"} 1574770531
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"yjeBkrZqUThSSHySFzWCjxAH8cxtiWSI2I8JFD6t1UM=\",remote_ip=\"10.211.123.126\",allowed_ip_0=\"10.90.0.5\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 1574770705
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"HtOSi37ALMnSkeAFqeWYZqlBnZqAJERhb5o/i3ZPEFI=\",remote_ip=\"10.211.123.127\",allowed_ip_0=\"10.90.0.17\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 1574770783
-wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"sUsR6xufQQ8Tf0FuyY9tfEeYdhVMeFelr4ZMUrj+B0E=\",remote_ip=\"10.211.123.128\",allowed_ip_0=\"10.90.0.18\",allowed_subnet_0=\"32\",remote_port=\"51820\"} 1574770693
-";
-        assert_eq!(s, s_ok);
+    #[test]
+    fn test_parse_unparseable_endpoint() {
+        let text = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t[fe80::1%wg0:51820\t10.70.0.2/32\t1\t2\t3\toff";
+        let wg = WireGuard::try_from(text).unwrap();
+        match &wg.interfaces["wg0"][0] {
+            Endpoint::Remote(ep) => {
+                assert_eq!(ep.remote_ip, None);
+                assert_eq!(ep.remote_port, None);
+                assert_eq!(ep.sent_bytes, 3);
+            }
+            Endpoint::Local(_) => panic!("expected a remote endpoint"),
+        }
     }
 
     #[test]
-    fn test_parse() {
-        let a = WireGuard::try_from(TEXT).unwrap();
-        println!("{:?}", a);
-        assert!(a.interfaces.len() == 3);
-        assert!(a.interfaces["wg0"].len() == 6);
+    fn test_parse_malformed_lines() {
+        // truncated remote line
+        let text = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t37.159.76.245:29159\t10.70.0.2/32\t1555771458\t10288508\t139524160\toff
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t(none)\t10.70";
+        match WireGuard::try_from(text) {
+            Err(ExporterError::MalformedDumpLine { line_no, columns }) => {
+                assert_eq!(line_no, 2);
+                assert_eq!(columns, 5);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
 
-        let e1 = match &a.interfaces["wg0"][1] {
-            Endpoint::Local(_) => panic!(),
-            Endpoint::Remote(re) => re,
-        };
+        // unparseable counter
+        let text = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\tmany\t0\toff";
+        assert!(matches!(
+            WireGuard::try_from(text),
+            Err(ExporterError::MalformedDumpLine { line_no: 1, .. })
+        ));
+
+        // bad port in the local line, the private key must not be in the error
+        let text = "wg0\t0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8=\t000q4qAC0ExW/BuGSmVR1nxH9JAXT6g9Wd3oEGy5lA=\t99999\toff";
+        match WireGuard::try_from(text) {
+            Err(e @ ExporterError::MalformedDumpLine { .. }) => {
+                assert!(!e
+                    .to_string()
+                    .contains("0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8="));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
 
+        // an extra column is an error too
+        let text = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff\tnew";
+        assert!(WireGuard::try_from(text).is_err());
+
+        // no column is echoed whatever the column count, a local line of
+        // wg show <interface> dump has no interface column
+        for text in [
+            "0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8=\t000q4qAC0ExW/BuGSmVR1nxH9JAXT6g9Wd3oEGy5lA=\t51820\toff",
+            "wg0\t0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8=\t000q4qAC0ExW/BuGSmVR1nxH9JAXT6g9Wd3oEGy5lA=\t51820\toff\tnew",
+            "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8=\t(none)\t10.70.0.2/32\t0\tmany\t0\toff",
+        ] {
+            let e = WireGuard::try_from(text).unwrap_err();
+            assert!(!e
+                .to_string()
+                .contains("0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8="));
+        }
         assert_eq!(
-            e1.public_key,
-            "2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk="
+            WireGuard::try_from(
+                "0000u8LWR682knVm350lnuqlCJzw5SNLW9Nf96P+m8=\t000q4qAC0ExW/BuGSmVR1nxH9JAXT6g9Wd3oEGy5lA=\t51820\toff"
+            )
+            .unwrap_err()
+            .to_string(),
+            "malformed wg dump line 1: 4 columns"
         );
 
-        assert_eq!(e1.remote_ip, Some("37.159.76.245".to_owned()));
-        assert_eq!(e1.allowed_ips, "10.70.0.2/32,10.70.0.66/32".to_owned());
+        // empty lines are skipped
+        assert!(WireGuard::try_from("\n\n").unwrap().interfaces.is_empty());
     }
 
     #[test]
-    fn test_parse_and_serialize() {
-        let a = WireGuard::try_from(TEXT).unwrap();
+    fn test_render_scrape_modes() {
+        const REF: &str = "# HELP wireguard_interface_scrape_mode How the interface data was obtained (wg show all or wg show <interface>)\n# TYPE wireguard_interface_scrape_mode gauge\nwireguard_interface_scrape_mode{interface=\"wg0\",mode=\"explicit\"} 1\nwireguard_interface_scrape_mode{interface=\"wg1\",mode=\"all\"} 1\n";
 
-        let options = Options {
-            verbose: true,
-            prepend_sudo: true,
-            separate_allowed_ips: false,
-            extract_names_config_files: None,
-            interfaces: None,
-            export_remote_ip_and_port: true,
-            export_latest_handshake_delay: true,
-        };
+        let mut scrape_modes = BTreeMap::new();
+        scrape_modes.insert("wg1".to_owned(), ScrapeMode::All);
+        scrape_modes.insert("wg0".to_owned(), ScrapeMode::Explicit);
 
-        let s = a.render_with_names(None, &options);
-        println!("{}", s);
+        assert_eq!(render_scrape_modes(&scrape_modes, &Options::default()), REF);
     }
 
     #[test]
-    fn test_render_to_prometheus_simple() {
-        const REF : &str= "# HELP wireguard_sent_bytes_total Bytes sent to the peer\n# TYPE wireguard_sent_bytes_total counter\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"to_change\",remote_ip=\"remote_ip\",remote_port=\"100\"} 1000\n\n# HELP wireguard_received_bytes_total Bytes received from the peer\n# TYPE wireguard_received_bytes_total counter\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"to_change\",remote_ip=\"remote_ip\",remote_port=\"100\"} 5000\n\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"to_change\",remote_ip=\"remote_ip\",remote_port=\"100\"} 500\n";
+    fn test_render_to_prometheus_total_bytes() {
+        const REF_TOTALS: &str = "# HELP wireguard_total_sent_bytes_total Bytes sent to all the peers of all the interfaces\n# TYPE wireguard_total_sent_bytes_total counter\nwireguard_total_sent_bytes_total 1300\n\n# HELP wireguard_total_received_bytes_total Bytes received from all the peers of all the interfaces\n# TYPE wireguard_total_received_bytes_total counter\nwireguard_total_received_bytes_total 20000000005000\n";
 
-        let re = Endpoint::Remote(RemoteEndpoint {
+        let re1 = Endpoint::Remote(RemoteEndpoint {
             public_key: "test".to_owned(),
-            remote_ip: Some("remote_ip".to_owned()),
-            remote_port: Some(100),
-            allowed_ips: "to_change".to_owned(),
+            remote_ip: None,
+            remote_port: None,
+            allowed_ips: "10.0.0.2/32".to_owned(),
+            has_preshared_key: false,
             latest_handshake: 500,
             sent_bytes: 1000,
             received_bytes: 5000,
-            persistent_keepalive: false,
+            persistent_keepalive: None,
+            source: None,
+            from_cache: false,
         });
+        let re2 = Endpoint::Remote(RemoteEndpoint {
+            public_key: "second_test".to_owned(),
+            remote_ip: None,
+            remote_port: None,
+            allowed_ips: "10.0.0.3/32".to_owned(),
+            has_preshared_key: false,
+            latest_handshake: 50,
+            sent_bytes: 300,
+            received_bytes: 20_000_000_000_000,
+            persistent_keepalive: None,
+            source: None,
+            from_cache: false,
+        });
+
         let mut wg = WireGuard {
             interfaces: HashMap::new(),
         };
+        wg.interfaces.insert("Pippo".to_owned(), vec![re1]);
+        wg.interfaces.insert("Pluto".to_owned(), vec![re2]);
 
-        let v = vec![re];
-        wg.interfaces.insert("Pippo".to_owned(), v);
+        let prometheus = wg.render_with_names(None, &Options::default());
+        assert!(prometheus.contains(REF_TOTALS));
+    }
 
+    #[test]
+    fn test_render_every_metric() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG: &str = "[Peer]
+# friendly_name = alice
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+
+[Peer]
+# friendly_json = {\"username\":\"bob\",\"id\":2}
+PublicKey = qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=
+AllowedIPs = 10.70.0.3/32
+
+[Peer]
+# friendly_name = carol
+PublicKey = L2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=
+AllowedIPs = 10.71.0.2/32
+";
+        const TEXT: &str = "wg0\t(none)\tGd3Qj2C2Y1J3EhpL7XtAQZOC5D2mtSrF3F0zxx3xiW8=\t51820\t0xca6c
+wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\tpsk\t10.211.123.112:51820\t10.70.0.2/32\t1\t2\t3\t25
+wg0\tqnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\t(none)\t10.211.123.113:51821\t10.70.0.3/32,fd86:ea04:1115::3/128\t0\t4\t5\toff
+wg1\t(none)\tMdVOIPKt9K2MPj/sO2NlWQbOnFJ6L/qX80mmhQwsUlA=\t51821\toff
+wg1\tY4i3+W0rB9AOpkZzKbQQ5L6tLhO9QfG6Nn3eM9jtg2U=\t(none)\t(none)\t10.72.0.2/32\t0\t0\t0\toff
+";
+        const REF: &str = "# HELP wireguard_sent_bytes_total Bytes sent to the peer\n# TYPE wireguard_sent_bytes_total counter\nwireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\"} 3\nwireguard_sent_bytes_total{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\"} 5\nwireguard_sent_bytes_total{interface=\"wg1\",public_key=\"Y4i3+W0rB9AOpkZzKbQQ5L6tLhO9QfG6Nn3eM9jtg2U=\"} 0\n\n# HELP wireguard_received_bytes_total Bytes received from the peer\n# TYPE wireguard_received_bytes_total counter\nwireguard_received_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\"} 2\nwireguard_received_bytes_total{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\"} 4\nwireguard_received_bytes_total{interface=\"wg1\",public_key=\"Y4i3+W0rB9AOpkZzKbQQ5L6tLhO9QfG6Nn3eM9jtg2U=\"} 0\n\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\"} 1\nwireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\"} 0\nwireguard_latest_handshake_seconds{interface=\"wg1\",public_key=\"Y4i3+W0rB9AOpkZzKbQQ5L6tLhO9QfG6Nn3eM9jtg2U=\"} 0\n\n# HELP wireguard_interface_sent_bytes_total Bytes sent to all the peers of the interface\n# TYPE wireguard_interface_sent_bytes_total counter\nwireguard_interface_sent_bytes_total{interface=\"wg0\"} 8\nwireguard_interface_sent_bytes_total{interface=\"wg1\"} 0\n\n# HELP wireguard_interface_received_bytes_total Bytes received from all the peers of the interface\n# TYPE wireguard_interface_received_bytes_total counter\nwireguard_interface_received_bytes_total{interface=\"wg0\"} 6\nwireguard_interface_received_bytes_total{interface=\"wg1\"} 0\n\n# HELP wireguard_total_sent_bytes_total Bytes sent to all the peers of all the interfaces\n# TYPE wireguard_total_sent_bytes_total counter\nwireguard_total_sent_bytes_total 8\n\n# HELP wireguard_total_received_bytes_total Bytes received from all the peers of all the interfaces\n# TYPE wireguard_total_received_bytes_total counter\nwireguard_total_received_bytes_total 6\n\n# HELP wireguard_peer_info Descriptive labels of the peer, always 1\n# TYPE wireguard_peer_info gauge\nwireguard_peer_info{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\",friendly_name=\"alice\",remote_ip=\"10.211.123.112\",remote_port=\"51820\"} 1\nwireguard_peer_info{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32,fd86:ea04:1115::3/128\",remote_ip=\"10.211.123.113\",id=\"2\",username=\"bob\",remote_port=\"51821\"} 1\nwireguard_peer_info{interface=\"wg1\",public_key=\"Y4i3+W0rB9AOpkZzKbQQ5L6tLhO9QfG6Nn3eM9jtg2U=\",allowed_ips=\"10.72.0.2/32\"} 1\n\n# HELP wireguard_persistent_keepalive_seconds Persistent keepalive interval of the peer, 0 if off\n# TYPE wireguard_persistent_keepalive_seconds gauge\nwireguard_persistent_keepalive_seconds{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\"} 25\nwireguard_persistent_keepalive_seconds{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\"} 0\nwireguard_persistent_keepalive_seconds{interface=\"wg1\",public_key=\"Y4i3+W0rB9AOpkZzKbQQ5L6tLhO9QfG6Nn3eM9jtg2U=\"} 0\n\n# HELP wireguard_peer_has_preshared_key 1 if the peer has a preshared key, 0 otherwise\n# TYPE wireguard_peer_has_preshared_key gauge\nwireguard_peer_has_preshared_key{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\"} 1\nwireguard_peer_has_preshared_key{interface=\"wg0\",public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\"} 0\nwireguard_peer_has_preshared_key{interface=\"wg1\",public_key=\"Y4i3+W0rB9AOpkZzKbQQ5L6tLhO9QfG6Nn3eM9jtg2U=\"} 0\n\n# HELP wireguard_interface_listen_port UDP port the interface listens on\n# TYPE wireguard_interface_listen_port gauge\nwireguard_interface_listen_port{interface=\"wg0\"} 51820\nwireguard_interface_listen_port{interface=\"wg1\"} 51821\n\n# HELP wireguard_interface_fwmark fwmark of the packets sent by the interface, missing if off\n# TYPE wireguard_interface_fwmark gauge\nwireguard_interface_fwmark{interface=\"wg0\"} 51820\n\n# HELP wireguard_peer_unnamed 1 for each peer missing from the config files\n# TYPE wireguard_peer_unnamed gauge\nwireguard_peer_unnamed{interface=\"wg1\",public_key=\"Y4i3+W0rB9AOpkZzKbQQ5L6tLhO9QfG6Nn3eM9jtg2U=\"} 1\n\n# HELP wireguard_peers_configured_total Number of peers in the config files\n# TYPE wireguard_peers_configured_total gauge\nwireguard_peers_configured_total 3\n\n# HELP wireguard_peer_down 1 for each peer of the config files missing from every interface\n# TYPE wireguard_peer_down gauge\nwireguard_peer_down{public_key=\"L2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\",friendly_name=\"carol\"} 1\n\n# HELP wireguard_interface_unique_remote_ips Number of distinct remote ips of the peers of the interface\n# TYPE wireguard_interface_unique_remote_ips gauge\nwireguard_interface_unique_remote_ips{interface=\"wg0\"} 2\nwireguard_interface_unique_remote_ips{interface=\"wg1\"} 0\n\n# HELP wireguard_interface_avg_sent_bytes_per_peer Bytes sent to the peers of the interface divided by the number of peers\n# TYPE wireguard_interface_avg_sent_bytes_per_peer gauge\nwireguard_interface_avg_sent_bytes_per_peer{interface=\"wg0\"} 4\nwireguard_interface_avg_sent_bytes_per_peer{interface=\"wg1\"} 0\n\n# HELP wireguard_interface_avg_received_bytes_per_peer Bytes received from the peers of the interface divided by the number of peers\n# TYPE wireguard_interface_avg_received_bytes_per_peer gauge\nwireguard_interface_avg_received_bytes_per_peer{interface=\"wg0\"} 3\nwireguard_interface_avg_received_bytes_per_peer{interface=\"wg1\"} 0\n\n# HELP wireguard_peer_configured_allowed_ips AllowedIPs of the peer as specified in the config file\n# TYPE wireguard_peer_configured_allowed_ips gauge\nwireguard_peer_configured_allowed_ips{public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\"} 1\nwireguard_peer_configured_allowed_ips{public_key=\"L2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=\",allowed_ips=\"10.71.0.2/32\"} 1\nwireguard_peer_configured_allowed_ips{public_key=\"qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=\",allowed_ips=\"10.70.0.3/32\"} 1\n";
+
+        let wg = WireGuard::try_from(TEXT).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
         let options = Options {
-            verbose: true,
-            prepend_sudo: true,
-            separate_allowed_ips: false,
-            extract_names_config_files: None,
-            interfaces: None,
             export_remote_ip_and_port: true,
-            export_latest_handshake_delay: false,
+            export_unique_remote_ips: true,
+            export_persistent_keepalive: true,
+            export_preshared_key_presence: true,
+            export_listen_port_and_fwmark: true,
+            export_unnamed_peers: true,
+            export_configured_peers: true,
+            export_configured_allowed_ips: true,
+            export_peer_info: true,
+            export_avg_bytes_per_peer: true,
+            ..Default::default()
         };
-
-        let prometheus = wg.render_with_names(None, &options);
-
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
         assert_eq!(prometheus, REF);
     }
 
@@ -586,33 +4479,39 @@ wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"sUsR6xufQQ8Tf0
     fn test_render_to_prometheus_complex() {
         use crate::wireguard_config::PeerEntry;
 
-        const REF :&'static str = "# HELP wireguard_sent_bytes_total Bytes sent to the peer\n# TYPE wireguard_sent_bytes_total counter\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32,fd86:ea04:::4/128\",remote_ip=\"remote_ip\",remote_port=\"100\"} 1000\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"second_test\",allowed_ips=\"10.0.0.4/32,fd86:ea04:::4/128,192.168.0.0/16\",friendly_name=\"this is my friendly name\",remote_ip=\"remote_ip\",remote_port=\"100\"} 14\n\n# HELP wireguard_received_bytes_total Bytes received from the peer\n# TYPE wireguard_received_bytes_total counter\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32,fd86:ea04:::4/128\",remote_ip=\"remote_ip\",remote_port=\"100\"} 5000\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"second_test\",allowed_ips=\"10.0.0.4/32,fd86:ea04:::4/128,192.168.0.0/16\",friendly_name=\"this is my friendly name\",remote_ip=\"remote_ip\",remote_port=\"100\"} 1000000000\n\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32,fd86:ea04:::4/128\",remote_ip=\"remote_ip\",remote_port=\"100\"} 500\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"second_test\",allowed_ips=\"10.0.0.4/32,fd86:ea04:::4/128,192.168.0.0/16\",friendly_name=\"this is my friendly name\",remote_ip=\"remote_ip\",remote_port=\"100\"} 50\n";
+        const REF : &str = "# HELP wireguard_sent_bytes_total Bytes sent to the peer\n# TYPE wireguard_sent_bytes_total counter\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"second_test\",allowed_ips=\"10.0.0.4/32,fd86:ea04:::4/128,192.168.0.0/16\",friendly_name=\"this is my friendly name\",remote_ip=\"remote_ip\",remote_port=\"100\"} 14\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32,fd86:ea04:::4/128\",remote_ip=\"remote_ip\",remote_port=\"100\"} 1000\n\n# HELP wireguard_received_bytes_total Bytes received from the peer\n# TYPE wireguard_received_bytes_total counter\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"second_test\",allowed_ips=\"10.0.0.4/32,fd86:ea04:::4/128,192.168.0.0/16\",friendly_name=\"this is my friendly name\",remote_ip=\"remote_ip\",remote_port=\"100\"} 1000000000\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32,fd86:ea04:::4/128\",remote_ip=\"remote_ip\",remote_port=\"100\"} 5000\n\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"second_test\",allowed_ips=\"10.0.0.4/32,fd86:ea04:::4/128,192.168.0.0/16\",friendly_name=\"this is my friendly name\",remote_ip=\"remote_ip\",remote_port=\"100\"} 50\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32,fd86:ea04:::4/128\",remote_ip=\"remote_ip\",remote_port=\"100\"} 500\n\n# HELP wireguard_interface_sent_bytes_total Bytes sent to all the peers of the interface\n# TYPE wireguard_interface_sent_bytes_total counter\nwireguard_interface_sent_bytes_total{interface=\"Pippo\"} 1014\n\n# HELP wireguard_interface_received_bytes_total Bytes received from all the peers of the interface\n# TYPE wireguard_interface_received_bytes_total counter\nwireguard_interface_received_bytes_total{interface=\"Pippo\"} 1000005000\n\n# HELP wireguard_total_sent_bytes_total Bytes sent to all the peers of all the interfaces\n# TYPE wireguard_total_sent_bytes_total counter\nwireguard_total_sent_bytes_total 1014\n\n# HELP wireguard_total_received_bytes_total Bytes received from all the peers of all the interfaces\n# TYPE wireguard_total_received_bytes_total counter\nwireguard_total_received_bytes_total 1000005000\n";
 
-        const REF_SPLIT :&'static str = "# HELP wireguard_sent_bytes_total Bytes sent to the peer\n# TYPE wireguard_sent_bytes_total counter\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"test\",remote_ip=\"remote_ip\",allowed_ip_0=\"10.0.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",remote_port=\"100\"} 1000\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"second_test\",friendly_name=\"this is my friendly name\",remote_ip=\"remote_ip\",allowed_ip_0=\"10.0.0.4\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",allowed_ip_2=\"192.168.0.0\",allowed_subnet_2=\"16\",remote_port=\"100\"} 14\n\n# HELP wireguard_received_bytes_total Bytes received from the peer\n# TYPE wireguard_received_bytes_total counter\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"test\",remote_ip=\"remote_ip\",allowed_ip_0=\"10.0.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",remote_port=\"100\"} 5000\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"second_test\",friendly_name=\"this is my friendly name\",remote_ip=\"remote_ip\",allowed_ip_0=\"10.0.0.4\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",allowed_ip_2=\"192.168.0.0\",allowed_subnet_2=\"16\",remote_port=\"100\"} 1000000000\n\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"test\",remote_ip=\"remote_ip\",allowed_ip_0=\"10.0.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",remote_port=\"100\"} 500\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"second_test\",friendly_name=\"this is my friendly name\",remote_ip=\"remote_ip\",allowed_ip_0=\"10.0.0.4\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",allowed_ip_2=\"192.168.0.0\",allowed_subnet_2=\"16\",remote_port=\"100\"} 50\n";
+        const REF_SPLIT : &str = "# HELP wireguard_sent_bytes_total Bytes sent to the peer\n# TYPE wireguard_sent_bytes_total counter\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"second_test\",friendly_name=\"this is my friendly name\",remote_ip=\"remote_ip\",allowed_ip_0=\"10.0.0.4\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",allowed_ip_2=\"192.168.0.0\",allowed_subnet_2=\"16\",remote_port=\"100\"} 14\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"test\",remote_ip=\"remote_ip\",allowed_ip_0=\"10.0.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",remote_port=\"100\"} 1000\n\n# HELP wireguard_received_bytes_total Bytes received from the peer\n# TYPE wireguard_received_bytes_total counter\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"second_test\",friendly_name=\"this is my friendly name\",remote_ip=\"remote_ip\",allowed_ip_0=\"10.0.0.4\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",allowed_ip_2=\"192.168.0.0\",allowed_subnet_2=\"16\",remote_port=\"100\"} 1000000000\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"test\",remote_ip=\"remote_ip\",allowed_ip_0=\"10.0.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",remote_port=\"100\"} 5000\n\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"second_test\",friendly_name=\"this is my friendly name\",remote_ip=\"remote_ip\",allowed_ip_0=\"10.0.0.4\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",allowed_ip_2=\"192.168.0.0\",allowed_subnet_2=\"16\",remote_port=\"100\"} 50\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"test\",remote_ip=\"remote_ip\",allowed_ip_0=\"10.0.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",remote_port=\"100\"} 500\n\n# HELP wireguard_interface_sent_bytes_total Bytes sent to all the peers of the interface\n# TYPE wireguard_interface_sent_bytes_total counter\nwireguard_interface_sent_bytes_total{interface=\"Pippo\"} 1014\n\n# HELP wireguard_interface_received_bytes_total Bytes received from all the peers of the interface\n# TYPE wireguard_interface_received_bytes_total counter\nwireguard_interface_received_bytes_total{interface=\"Pippo\"} 1000005000\n\n# HELP wireguard_total_sent_bytes_total Bytes sent to all the peers of all the interfaces\n# TYPE wireguard_total_sent_bytes_total counter\nwireguard_total_sent_bytes_total 1014\n\n# HELP wireguard_total_received_bytes_total Bytes received from all the peers of all the interfaces\n# TYPE wireguard_total_received_bytes_total counter\nwireguard_total_received_bytes_total 1000005000\n";
 
-        const REF_SPLIT_NO_REMOTE :&'static str = "# HELP wireguard_sent_bytes_total Bytes sent to the peer\n# TYPE wireguard_sent_bytes_total counter\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ip_0=\"10.0.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\"} 1000\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"second_test\",friendly_name=\"this is my friendly name\",allowed_ip_0=\"10.0.0.4\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",allowed_ip_2=\"192.168.0.0\",allowed_subnet_2=\"16\"} 14\n\n# HELP wireguard_received_bytes_total Bytes received from the peer\n# TYPE wireguard_received_bytes_total counter\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ip_0=\"10.0.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\"} 5000\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"second_test\",friendly_name=\"this is my friendly name\",allowed_ip_0=\"10.0.0.4\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",allowed_ip_2=\"192.168.0.0\",allowed_subnet_2=\"16\"} 1000000000\n\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"test\",allowed_ip_0=\"10.0.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\"} 500\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"second_test\",friendly_name=\"this is my friendly name\",allowed_ip_0=\"10.0.0.4\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",allowed_ip_2=\"192.168.0.0\",allowed_subnet_2=\"16\"} 50\n";
+        const REF_SPLIT_NO_REMOTE : &str = "# HELP wireguard_sent_bytes_total Bytes sent to the peer\n# TYPE wireguard_sent_bytes_total counter\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"second_test\",friendly_name=\"this is my friendly name\",allowed_ip_0=\"10.0.0.4\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",allowed_ip_2=\"192.168.0.0\",allowed_subnet_2=\"16\"} 14\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ip_0=\"10.0.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\"} 1000\n\n# HELP wireguard_received_bytes_total Bytes received from the peer\n# TYPE wireguard_received_bytes_total counter\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"second_test\",friendly_name=\"this is my friendly name\",allowed_ip_0=\"10.0.0.4\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",allowed_ip_2=\"192.168.0.0\",allowed_subnet_2=\"16\"} 1000000000\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ip_0=\"10.0.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\"} 5000\n\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"second_test\",friendly_name=\"this is my friendly name\",allowed_ip_0=\"10.0.0.4\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\",allowed_ip_2=\"192.168.0.0\",allowed_subnet_2=\"16\"} 50\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"test\",allowed_ip_0=\"10.0.0.2\",allowed_subnet_0=\"32\",allowed_ip_1=\"fd86:ea04:::4\",allowed_subnet_1=\"128\"} 500\n\n# HELP wireguard_interface_sent_bytes_total Bytes sent to all the peers of the interface\n# TYPE wireguard_interface_sent_bytes_total counter\nwireguard_interface_sent_bytes_total{interface=\"Pippo\"} 1014\n\n# HELP wireguard_interface_received_bytes_total Bytes received from all the peers of the interface\n# TYPE wireguard_interface_received_bytes_total counter\nwireguard_interface_received_bytes_total{interface=\"Pippo\"} 1000005000\n\n# HELP wireguard_total_sent_bytes_total Bytes sent to all the peers of all the interfaces\n# TYPE wireguard_total_sent_bytes_total counter\nwireguard_total_sent_bytes_total 1014\n\n# HELP wireguard_total_received_bytes_total Bytes received from all the peers of all the interfaces\n# TYPE wireguard_total_received_bytes_total counter\nwireguard_total_received_bytes_total 1000005000\n";
 
-        const REF_JSON :&'static str = "# HELP wireguard_sent_bytes_total Bytes sent to the peer\n# TYPE wireguard_sent_bytes_total counter\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32,fd86:ea04:::4/128\",remote_ip=\"remote_ip\",remote_port=\"100\"} 1000\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"second_test\",allowed_ips=\"10.0.0.4/32,fd86:ea04:::4/128,192.168.0.0/16\",remote_ip=\"remote_ip\",auth_date=\"1614869789\",first_name=\"Coordinator\",id=\"482217555\",last_name=\"DrProxy.me\",username=\"DrProxyMeCoordinator\",remote_port=\"100\"} 14\n\n# HELP wireguard_received_bytes_total Bytes received from the peer\n# TYPE wireguard_received_bytes_total counter\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32,fd86:ea04:::4/128\",remote_ip=\"remote_ip\",remote_port=\"100\"} 5000\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"second_test\",allowed_ips=\"10.0.0.4/32,fd86:ea04:::4/128,192.168.0.0/16\",remote_ip=\"remote_ip\",auth_date=\"1614869789\",first_name=\"Coordinator\",id=\"482217555\",last_name=\"DrProxy.me\",username=\"DrProxyMeCoordinator\",remote_port=\"100\"} 1000000000\n\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32,fd86:ea04:::4/128\",remote_ip=\"remote_ip\",remote_port=\"100\"} 500\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"second_test\",allowed_ips=\"10.0.0.4/32,fd86:ea04:::4/128,192.168.0.0/16\",remote_ip=\"remote_ip\",auth_date=\"1614869789\",first_name=\"Coordinator\",id=\"482217555\",last_name=\"DrProxy.me\",username=\"DrProxyMeCoordinator\",remote_port=\"100\"} 50\n";
+        const REF_JSON : &str = "# HELP wireguard_sent_bytes_total Bytes sent to the peer\n# TYPE wireguard_sent_bytes_total counter\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"second_test\",allowed_ips=\"10.0.0.4/32,fd86:ea04:::4/128,192.168.0.0/16\",remote_ip=\"remote_ip\",auth_date=\"1614869789\",first_name=\"Coordinator\",id=\"482217555\",last_name=\"DrProxy.me\",username=\"DrProxyMeCoordinator\",remote_port=\"100\"} 14\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32,fd86:ea04:::4/128\",remote_ip=\"remote_ip\",remote_port=\"100\"} 1000\n\n# HELP wireguard_received_bytes_total Bytes received from the peer\n# TYPE wireguard_received_bytes_total counter\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"second_test\",allowed_ips=\"10.0.0.4/32,fd86:ea04:::4/128,192.168.0.0/16\",remote_ip=\"remote_ip\",auth_date=\"1614869789\",first_name=\"Coordinator\",id=\"482217555\",last_name=\"DrProxy.me\",username=\"DrProxyMeCoordinator\",remote_port=\"100\"} 1000000000\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32,fd86:ea04:::4/128\",remote_ip=\"remote_ip\",remote_port=\"100\"} 5000\n\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"second_test\",allowed_ips=\"10.0.0.4/32,fd86:ea04:::4/128,192.168.0.0/16\",remote_ip=\"remote_ip\",auth_date=\"1614869789\",first_name=\"Coordinator\",id=\"482217555\",last_name=\"DrProxy.me\",username=\"DrProxyMeCoordinator\",remote_port=\"100\"} 50\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32,fd86:ea04:::4/128\",remote_ip=\"remote_ip\",remote_port=\"100\"} 500\n\n# HELP wireguard_interface_sent_bytes_total Bytes sent to all the peers of the interface\n# TYPE wireguard_interface_sent_bytes_total counter\nwireguard_interface_sent_bytes_total{interface=\"Pippo\"} 1014\n\n# HELP wireguard_interface_received_bytes_total Bytes received from all the peers of the interface\n# TYPE wireguard_interface_received_bytes_total counter\nwireguard_interface_received_bytes_total{interface=\"Pippo\"} 1000005000\n\n# HELP wireguard_total_sent_bytes_total Bytes sent to all the peers of all the interfaces\n# TYPE wireguard_total_sent_bytes_total counter\nwireguard_total_sent_bytes_total 1014\n\n# HELP wireguard_total_received_bytes_total Bytes received from all the peers of all the interfaces\n# TYPE wireguard_total_received_bytes_total counter\nwireguard_total_received_bytes_total 1000005000\n";
 
         let re1 = Endpoint::Remote(RemoteEndpoint {
             public_key: "test".to_owned(),
             remote_ip: Some("remote_ip".to_owned()),
             remote_port: Some(100),
             allowed_ips: "10.0.0.2/32,fd86:ea04:::4/128".to_owned(),
+            has_preshared_key: false,
             latest_handshake: 500,
             sent_bytes: 1000,
             received_bytes: 5000,
-            persistent_keepalive: false,
+            persistent_keepalive: None,
+            source: None,
+            from_cache: false,
         });
         let re2 = Endpoint::Remote(RemoteEndpoint {
             public_key: "second_test".to_owned(),
             remote_ip: Some("remote_ip".to_owned()),
             remote_port: Some(100),
             allowed_ips: "10.0.0.4/32,fd86:ea04:::4/128,192.168.0.0/16".to_owned(),
+            has_preshared_key: false,
             latest_handshake: 50,
             sent_bytes: 14,
             received_bytes: 1_000_000_000,
-            persistent_keepalive: false,
+            persistent_keepalive: None,
+            source: None,
+            from_cache: false,
         });
 
         let mut wg = WireGuard {
@@ -624,13 +4523,15 @@ wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"sUsR6xufQQ8Tf0
 
         let mut pehm = PeerEntryHashMap::new();
         let pe = PeerEntry {
-            public_key: "second_test",
+            public_key: "second_test".into(),
             allowed_ips: "ignored",
             friendly_description: Some(FriendlyDescription::Name(
                 "this is my friendly name".into(),
             )),
+            qos_class: None,
+            endpoint: None,
         };
-        pehm.insert(pe.public_key, pe.clone());
+        pehm.insert(pe.public_key.clone(), pe.clone());
 
         let mut options = Options {
             verbose: true,
@@ -640,11 +4541,17 @@ wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"sUsR6xufQQ8Tf0
             interfaces: None,
             export_remote_ip_and_port: true,
             export_latest_handshake_delay: false,
+            ..Default::default()
         };
 
         let prometheus = wg.render_with_names(Some(&pehm), &options);
         assert_eq!(prometheus, REF);
 
+        // the order of the peers in the wg output does not matter
+        wg.interfaces.get_mut("Pippo").unwrap().reverse();
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
+        assert_eq!(prometheus, REF);
+
         options.separate_allowed_ips = true;
 
         let prometheus = wg.render_with_names(Some(&pehm), &options);
@@ -674,11 +4581,13 @@ wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"sUsR6xufQQ8Tf0
         hm.insert("auth_date", serde_json::Value::Number(1614869789.into()));
 
         let pe = PeerEntry {
-            public_key: "second_test",
+            public_key: "second_test".into(),
             allowed_ips: "ignored",
             friendly_description: Some(FriendlyDescription::Json(hm)),
+            qos_class: None,
+            endpoint: None,
         };
-        pehm.insert(pe.public_key, pe.clone());
+        pehm.insert(pe.public_key.clone(), pe.clone());
 
         options.separate_allowed_ips = false;
         options.export_remote_ip_and_port = true;
@@ -686,4 +4595,144 @@ wireguard_latest_handshake_seconds{interface=\"wg0\",public_key=\"sUsR6xufQQ8Tf0
         let prometheus = wg.render_with_names(Some(&pehm), &options);
         assert_eq!(prometheus, REF_JSON);
     }
+
+    #[test]
+    fn test_render_nested_json() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG: &str = r#"[Peer]
+# friendly_json = {"tags":["prod","eu"],"site":{"id":3,"rack":{"row":"b"}},"owner":"ops","spare":[]}
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+"#;
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+
+        let prometheus = wg.render_with_names(Some(&pehm), &Options::default());
+        assert!(prometheus.contains(r#"wireguard_sent_bytes_total{interface="wg0",public_key="2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=",allowed_ips="10.70.0.2/32",owner="ops",site_id="3",site_rack_row="b",tags_0="prod",tags_1="eu"} 0"#));
+    }
+
+    #[test]
+    fn test_render_escaped_json() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG: &str = r#"[Peer]
+# friendly_json = {"path":"C:\\vpn","note":"two\nlines","quote":"a \"b\""}
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+"#;
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+
+        let prometheus = wg.render_with_names(Some(&pehm), &Options::default());
+        assert!(prometheus.contains(
+            r#"allowed_ips="10.70.0.2/32",note="two\nlines",path="C:\\vpn",quote="a \"b\""} 0"#
+        ));
+    }
+
+    #[test]
+    fn test_render_json_invalid_label_names() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG: &str = r#"[Peer]
+# friendly_json = {"my-tag":"a","1x":"b","with space":"c","__name__":"d","site":{"my-id":1,"id":2},"ok":"e"}
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+"#;
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+
+        // the names that are not valid label names are skipped, the
+        // flattened ones included
+        let prometheus = wg.render_with_names(Some(&pehm), &Options::default());
+        assert!(prometheus.contains(
+            "wireguard_sent_bytes_total{interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\",ok=\"e\",site_id=\"2\"} 0\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_json_reserved_label_names() {
+        use crate::wireguard_config::peer_entry_hashmap_try_from;
+
+        const CONFIG: &str = r#"[Peer]
+# friendly_json = {"interface":"a","public_key":"b","allowed_ips":"c","allowed":{"ip":["d"]},"region":"e","owner":"f"}
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+"#;
+        const TEXT_A: &str = "wg0\t2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\t(none)\t(none)\t10.70.0.2/32\t0\t0\t0\toff\n";
+
+        let wg = WireGuard::try_from(TEXT_A).unwrap();
+        let pehm = peer_entry_hashmap_try_from(CONFIG).unwrap();
+
+        // the labels of the exporter and the static ones are not rendered twice
+        let options = Options {
+            static_labels: vec![("region".to_owned(), "eu".to_owned())],
+            ..Default::default()
+        };
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
+        assert!(prometheus.contains(
+            "wireguard_sent_bytes_total{region=\"eu\",interface=\"wg0\",public_key=\"2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\",allowed_ips=\"10.70.0.2/32\",owner=\"f\"} 0\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_to_prometheus_unsupported_json() {
+        use crate::wireguard_config::PeerEntry;
+
+        const REF_SKIPPED: &str = "# HELP wireguard_sent_bytes_total Bytes sent to the peer\n# TYPE wireguard_sent_bytes_total counter\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32\",city=\"milan\"} 1000\n\n# HELP wireguard_received_bytes_total Bytes received from the peer\n# TYPE wireguard_received_bytes_total counter\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32\",city=\"milan\"} 5000\n\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32\",city=\"milan\"} 500\n\n# HELP wireguard_interface_sent_bytes_total Bytes sent to all the peers of the interface\n# TYPE wireguard_interface_sent_bytes_total counter\nwireguard_interface_sent_bytes_total{interface=\"Pippo\"} 1000\n\n# HELP wireguard_interface_received_bytes_total Bytes received from all the peers of the interface\n# TYPE wireguard_interface_received_bytes_total counter\nwireguard_interface_received_bytes_total{interface=\"Pippo\"} 5000\n\n# HELP wireguard_total_sent_bytes_total Bytes sent to all the peers of all the interfaces\n# TYPE wireguard_total_sent_bytes_total counter\nwireguard_total_sent_bytes_total 1000\n\n# HELP wireguard_total_received_bytes_total Bytes received from all the peers of all the interfaces\n# TYPE wireguard_total_received_bytes_total counter\nwireguard_total_received_bytes_total 5000\n";
+
+        const REF_PLACEHOLDER: &str = "# HELP wireguard_sent_bytes_total Bytes sent to the peer\n# TYPE wireguard_sent_bytes_total counter\nwireguard_sent_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32\",city=\"milan\",tags=\"unsupported_json_value\"} 1000\n\n# HELP wireguard_received_bytes_total Bytes received from the peer\n# TYPE wireguard_received_bytes_total counter\nwireguard_received_bytes_total{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32\",city=\"milan\",tags=\"unsupported_json_value\"} 5000\n\n# HELP wireguard_latest_handshake_seconds UNIX timestamp seconds of the last handshake\n# TYPE wireguard_latest_handshake_seconds gauge\nwireguard_latest_handshake_seconds{interface=\"Pippo\",public_key=\"test\",allowed_ips=\"10.0.0.2/32\",city=\"milan\",tags=\"unsupported_json_value\"} 500\n\n# HELP wireguard_interface_sent_bytes_total Bytes sent to all the peers of the interface\n# TYPE wireguard_interface_sent_bytes_total counter\nwireguard_interface_sent_bytes_total{interface=\"Pippo\"} 1000\n\n# HELP wireguard_interface_received_bytes_total Bytes received from all the peers of the interface\n# TYPE wireguard_interface_received_bytes_total counter\nwireguard_interface_received_bytes_total{interface=\"Pippo\"} 5000\n\n# HELP wireguard_total_sent_bytes_total Bytes sent to all the peers of all the interfaces\n# TYPE wireguard_total_sent_bytes_total counter\nwireguard_total_sent_bytes_total 1000\n\n# HELP wireguard_total_received_bytes_total Bytes received from all the peers of all the interfaces\n# TYPE wireguard_total_received_bytes_total counter\nwireguard_total_received_bytes_total 5000\n";
+
+        let re = Endpoint::Remote(RemoteEndpoint {
+            public_key: "test".to_owned(),
+            remote_ip: None,
+            remote_port: None,
+            allowed_ips: "10.0.0.2/32".to_owned(),
+            has_preshared_key: false,
+            latest_handshake: 500,
+            sent_bytes: 1000,
+            received_bytes: 5000,
+            persistent_keepalive: None,
+            source: None,
+            from_cache: false,
+        });
+
+        let mut wg = WireGuard {
+            interfaces: HashMap::new(),
+        };
+        wg.interfaces.insert("Pippo".to_owned(), vec![re]);
+
+        let mut hm = HashMap::new();
+        hm.insert("city", serde_json::Value::String("milan".to_owned()));
+        hm.insert("tags", serde_json::Value::Null);
+
+        let mut pehm = PeerEntryHashMap::new();
+        let pe = PeerEntry {
+            public_key: "test".into(),
+            allowed_ips: "ignored",
+            friendly_description: Some(FriendlyDescription::Json(hm)),
+            qos_class: None,
+            endpoint: None,
+        };
+        pehm.insert(pe.public_key.clone(), pe.clone());
+
+        let mut options = Options {
+            skip_unsupported_json: true,
+            ..Default::default()
+        };
+
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
+        assert_eq!(prometheus, REF_SKIPPED);
+
+        options.skip_unsupported_json = false;
+
+        let prometheus = wg.render_with_names(Some(&pehm), &options);
+        assert_eq!(prometheus, REF_PLACEHOLDER);
+    }
 }
@@ -1,16 +1,22 @@
-use crate::exporter_error::PeerEntryParseError;
+use crate::exporter_error::{NamesFileParseError, PeerEntryParseError};
 use crate::FriendlyDescription;
-use log::debug;
+use log::{debug, info, warn};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::convert::TryInto;
+use std::path::Path;
 
-#[derive(Debug, Default, Clone)]
-pub(crate) struct PeerEntry<'a> {
-    pub public_key: &'a str,
-    #[allow(dead_code)]
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PeerEntry<'a> {
+    // owned only when read from a PublicKeyFile
+    pub public_key: Cow<'a, str>,
     pub allowed_ips: &'a str,
     pub friendly_description: Option<FriendlyDescription<'a>>,
+    // bandwidth class of the peer, from the qos_class comment
+    pub qos_class: Option<&'a str>,
+    // the Endpoint of wg-quick, where the peer is supposed to connect
+    pub endpoint: Option<&'a str>,
 }
 
 fn after_char(s: &str, c_split: char) -> &str {
@@ -25,10 +31,13 @@ fn after_char(s: &str, c_split: char) -> &str {
     s
 }
 
+// wg-quick uses # but INI style ; comments are common too
+const COMMENT_CHARS: [char; 2] = ['#', ';'];
+
 fn after_char_strip_comment(s: &str, c_split: char) -> &str {
     let s = after_char(s, c_split);
 
-    if let Some(idx) = s.find('#') {
+    if let Some(idx) = s.find(COMMENT_CHARS) {
         s[..idx].trim()
     } else {
         s
@@ -36,7 +45,8 @@ fn after_char_strip_comment(s: &str, c_split: char) -> &str {
 }
 
 fn from_pound_line_to_key_value(line: &str) -> Option<(&str, &str)> {
-    // since the pound sign is 1 byte the below slice will work
+    // since the pound sign (or the semicolon) is 1 byte the below slice
+    // will work
     let line = &line[1..];
     let equals_pos = line.find('=');
     if let Some(equals_pos) = equals_pos {
@@ -54,29 +64,77 @@ impl<'a> TryFrom<&[&'a str]> for PeerEntry<'a> {
     type Error = PeerEntryParseError;
 
     fn try_from(lines: &[&'a str]) -> Result<PeerEntry<'a>, Self::Error> {
-        debug!("PeerEntry::TryFrom called with lines == {:#?}", lines);
+        PeerEntry::parse(lines, None).map(|(pe, _)| pe)
+    }
+}
 
-        let mut public_key = "";
+impl<'a> PeerEntry<'a> {
+    // Parses a [Peer] block. A friendly description that cannot be parsed
+    // does not fail the block: it is logged and the peer has no friendly
+    // description. The number of such errors is returned along the entry.
+    // A relative PublicKeyFile is relative to the directory of config_file.
+    fn parse(
+        lines: &[&'a str],
+        config_file: Option<&Path>,
+    ) -> Result<(PeerEntry<'a>, u64), PeerEntryParseError> {
+        debug!("PeerEntry::parse called with lines == {:#?}", lines);
+
+        let mut public_key = Cow::Borrowed("");
         let mut allowed_ips = "";
         let mut friendly_description = None;
+        let mut qos_class = None;
+        let mut endpoint = None;
+        let mut parse_errors = 0;
 
         for line in lines {
+            // the trim also drops the \r of the CRLF files
+            let line = line.trim();
             let line_lowercase = line.to_lowercase();
 
-            if line_lowercase.starts_with("publickey") {
-                public_key = after_char_strip_comment(line, '=').trim();
+            // systemd-networkd .netdev files point to the key instead
+            // of having it inline
+            if line_lowercase.starts_with("publickeyfile") {
+                let path = Path::new(after_char_strip_comment(line, '=').trim());
+                let path = match config_file.and_then(|config_file| config_file.parent()) {
+                    Some(config_dir) if path.is_relative() => config_dir.join(path),
+                    _ => path.to_owned(),
+                };
+                let key = std::fs::read_to_string(&path).map_err(|e| {
+                    PeerEntryParseError::PublicKeyFileReadError {
+                        config_file: config_file
+                            .map(|config_file| config_file.display().to_string()),
+                        path: path.display().to_string(),
+                        e,
+                    }
+                })?;
+                public_key = Cow::Owned(key.trim().to_owned());
+                debug!("public_key == {} (from {})", public_key, path.display());
+            } else if line_lowercase.starts_with("publickey") {
+                public_key = Cow::Borrowed(after_char_strip_comment(line, '=').trim());
                 debug!("public_key == {}", public_key);
             } else if line_lowercase.starts_with("allowedips") {
                 allowed_ips = after_char_strip_comment(line, '=').trim();
                 debug!("allowed_ips == {}", allowed_ips);
-            } else if line.trim().starts_with('#') {
+            } else if line_lowercase.starts_with("endpoint") {
+                endpoint = Some(after_char_strip_comment(line, '=').trim());
+                debug!("endpoint == {:?}", endpoint);
+            } else if line.starts_with(COMMENT_CHARS) {
+                // the value is taken as is, so a ; or a # in a friendly_json
+                // string is not a comment
                 if let Some((key, value)) = from_pound_line_to_key_value(line) {
                     // if it's a supported key, let' map it.
                     // we support one key now but this way
                     // we can support more in the future
                     match key {
-                        "friendly_name" => friendly_description = Some((key, value).try_into()?),
-                        "friendly_json" => friendly_description = Some((key, value).try_into()?),
+                        "friendly_name" | "friendly_json" => match (key, value).try_into() {
+                            Ok(fd) => friendly_description = Some(fd),
+                            Err(e) => {
+                                warn!("skipping the {} {}: {}", key, value, e);
+                                friendly_description = None;
+                                parse_errors += 1;
+                            }
+                        },
+                        "qos_class" => qos_class = Some(value),
                         _ => {}
                     }
                 }
@@ -99,25 +157,59 @@ impl<'a> TryFrom<&[&'a str]> for PeerEntry<'a> {
                 public_key,
                 allowed_ips,
                 friendly_description, // name can be None
+                qos_class,
+                endpoint,
             };
-            debug!("PeerEntry::TryFrom returning PeerEntryHasMap == {:?}", pe);
-            Ok(pe)
+            debug!("PeerEntry::parse returning PeerEntryHasMap == {:?}", pe);
+            Ok((pe, parse_errors))
         }
     }
 }
 
-pub(crate) type PeerEntryHashMap<'a> = HashMap<&'a str, PeerEntry<'a>>;
+pub type PeerEntryHashMap<'a> = HashMap<Cow<'a, str>, PeerEntry<'a>>;
 
-pub(crate) fn peer_entry_hashmap_try_from(
-    txt: &str,
-) -> Result<PeerEntryHashMap, PeerEntryParseError> {
-    debug!("txt == {}", txt);
+// A config without its path: a relative PublicKeyFile is relative to the
+// working directory. The exporter uses parse_config_files.
+pub fn peer_entry_hashmap_try_from(txt: &str) -> Result<PeerEntryHashMap<'_>, PeerEntryParseError> {
+    parse_peer_entries(txt).map(|(hm, _)| hm)
+}
+
+// Like peer_entry_hashmap_try_from but also returns the number of friendly
+// descriptions skipped because they could not be parsed.
+pub fn parse_peer_entries(txt: &str) -> Result<(PeerEntryHashMap<'_>, u64), PeerEntryParseError> {
     let mut hm = HashMap::new();
+    let parse_errors = parse_peer_entries_into(txt, None, &mut hm)?;
+    Ok((hm, parse_errors))
+}
+
+// Parses the (path, contents) config files in a single map, as if they were
+// one file. The relative PublicKeyFile paths are relative to the directory
+// of their config file.
+pub fn parse_config_files(
+    config_files: &[(String, String)],
+) -> Result<(PeerEntryHashMap<'_>, u64), PeerEntryParseError> {
+    let mut hm = HashMap::new();
+    let mut parse_errors = 0;
+    for (path, txt) in config_files {
+        parse_errors += parse_peer_entries_into(txt, Some(Path::new(path)), &mut hm)?;
+    }
+    Ok((hm, parse_errors))
+}
+
+fn parse_peer_entries_into<'a>(
+    txt: &'a str,
+    config_file: Option<&Path>,
+    hm: &mut PeerEntryHashMap<'a>,
+) -> Result<u64, PeerEntryParseError> {
+    debug!("txt == {}", txt);
+    let mut parse_errors = 0;
 
     let mut v_blocks = Vec::new();
     let mut cur_block: Option<Vec<&str>> = None;
 
     for line in txt.lines() {
+        // lines() leaves the \r of a last CRLF line without the \n
+        let line = line.trim_end();
         if line.starts_with('[') {
             if let Some(inner_cur_block) = cur_block {
                 // close the block
@@ -147,15 +239,93 @@ pub(crate) fn peer_entry_hashmap_try_from(
     debug!("peer_entry_hashmap_try_from v_blocks == {:?}", v_blocks);
 
     for block in &v_blocks {
-        let p: PeerEntry = PeerEntry::try_from(block as &[&str])?;
-        hm.insert(p.public_key, p);
+        let (p, block_parse_errors) = PeerEntry::parse(block as &[&str], config_file)?;
+        parse_errors += block_parse_errors;
+        match hm.get_mut(&p.public_key) {
+            // the same peer can be in more than one config file: the first
+            // friendly description wins
+            Some(first) => merge_duplicate_peer_entry(first, p),
+            None => {
+                hm.insert(p.public_key.clone(), p);
+            }
+        }
     }
 
     debug!("peer_entry_hashmap_try_from hm == {:?}", hm);
 
+    Ok(parse_errors)
+}
+
+fn merge_duplicate_peer_entry<'a>(first: &mut PeerEntry<'a>, duplicate: PeerEntry<'a>) {
+    info!(
+        "peer {} is defined more than once, friendly descriptions {:?} and {:?}",
+        first.public_key, first.friendly_description, duplicate.friendly_description
+    );
+
+    if first.friendly_description.is_none() {
+        first.friendly_description = duplicate.friendly_description;
+    }
+    if first.qos_class.is_none() {
+        first.qos_class = duplicate.qos_class;
+    }
+    if first.endpoint.is_none() {
+        first.endpoint = duplicate.endpoint;
+    }
+}
+
+// The names file maps the public keys to the friendly names, for example:
+// { "2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=": "alice" }
+// Its entries have no AllowedIPs since the file does not specify them.
+pub fn names_file_try_from<'a>(
+    file: &str,
+    txt: &str,
+) -> Result<PeerEntryHashMap<'a>, NamesFileParseError> {
+    match Path::new(file).extension().and_then(|ext| ext.to_str()) {
+        Some("json") => {}
+        _ => {
+            return Err(NamesFileParseError::UnsupportedExtension {
+                file: file.to_owned(),
+            })
+        }
+    }
+
+    let json: serde_json::Value = serde_json::from_str(txt)?;
+    let names = json.as_object().ok_or(NamesFileParseError::NotAnObject)?;
+
+    let mut hm = HashMap::new();
+    for (public_key, name) in names {
+        let name = name
+            .as_str()
+            .ok_or_else(|| NamesFileParseError::InvalidName {
+                public_key: public_key.to_owned(),
+            })?;
+
+        let pe = PeerEntry {
+            public_key: Cow::Owned(public_key.to_owned()),
+            friendly_description: Some(FriendlyDescription::Name(name.to_owned().into())),
+            ..Default::default()
+        };
+        hm.insert(pe.public_key.clone(), pe);
+    }
+
+    debug!("names_file_try_from hm == {:?}", hm);
+
     Ok(hm)
 }
 
+// The names in the names file take precedence over the ones in the
+// config files. The rest of the config file entries is kept.
+pub fn merge_names_file<'a>(pehm: &mut PeerEntryHashMap<'a>, names: PeerEntryHashMap<'a>) {
+    for (public_key, names_entry) in names {
+        match pehm.get_mut(&public_key) {
+            Some(peer_entry) => peer_entry.friendly_description = names_entry.friendly_description,
+            None => {
+                pehm.insert(public_key, names_entry);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::FriendlyDescription;
@@ -317,6 +487,63 @@ PublicKey = L2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=
         assert_eq!(a.1, "");
     }
 
+    #[test]
+    fn test_parse_semicolon_comments() {
+        const TEXT_SEMICOLON: &str = "[Peer]
+; friendly_name = alice
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk= ; laptop
+AllowedIPs = 10.70.0.2/32 ; work
+
+[Peer]
+    ;friendly_json = {\"note\":\"a; b # c\"}
+    PublicKey = qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=
+    AllowedIPs = 10.70.0.3/32 # phone
+";
+
+        let a = peer_entry_hashmap_try_from(TEXT_SEMICOLON).unwrap();
+
+        let alice = &a["2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk="];
+        assert_eq!(alice.allowed_ips, "10.70.0.2/32");
+        assert_eq!(
+            alice.friendly_description,
+            Some(FriendlyDescription::Name("alice".into()))
+        );
+
+        let b = &a["qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU="];
+        assert_eq!(b.allowed_ips, "10.70.0.3/32");
+        match &b.friendly_description {
+            Some(FriendlyDescription::Json(json)) => {
+                assert_eq!(json["note"], serde_json::Value::String("a; b # c".into()))
+            }
+            fd => panic!("expected a friendly_json, got {:?}", fd),
+        }
+    }
+
+    #[test]
+    fn test_parse_crlf() {
+        let lf = peer_entry_hashmap_try_from(TEXT_JSON).unwrap();
+
+        let text_crlf = TEXT_JSON.replace('\n', "\r\n");
+        let crlf = peer_entry_hashmap_try_from(&text_crlf).unwrap();
+        assert_eq!(crlf, lf);
+
+        // no final newline
+        let crlf = peer_entry_hashmap_try_from(text_crlf.trim_end_matches('\n')).unwrap();
+        assert_eq!(crlf, lf);
+
+        let pe = PeerEntry::try_from(&[
+            "# friendly_name = alice\r",
+            "PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\r",
+            "AllowedIPs = 10.70.0.2/32\r",
+        ] as &[&str])
+        .unwrap();
+        assert_eq!(pe.allowed_ips, "10.70.0.2/32");
+        assert_eq!(
+            pe.friendly_description,
+            Some(FriendlyDescription::Name("alice".into()))
+        );
+    }
+
     #[test]
     fn test_parse_ok() {
         let a: PeerEntryHashMap = peer_entry_hashmap_try_from(TEXT).unwrap();
@@ -382,6 +609,259 @@ PublicKey = L2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008=
         assert_eq!(None, entry.friendly_description);
     }
 
+    #[test]
+    fn test_parse_qos_class() {
+        const TEXT_QOS: &str = "[Peer]
+# friendly_name = alice
+# qos_class = gold
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+
+[Peer]
+PublicKey = qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=
+AllowedIPs = 10.70.0.3/32
+";
+        let a: PeerEntryHashMap = peer_entry_hashmap_try_from(TEXT_QOS).unwrap();
+        assert_eq!(
+            a["2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk="].qos_class,
+            Some("gold")
+        );
+        assert_eq!(
+            a["qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU="].qos_class,
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_netdev_public_key_file() {
+        let path = std::env::temp_dir().join(format!(
+            "prometheus_wireguard_exporter_test_{}.pub",
+            std::process::id()
+        ));
+        std::fs::write(&path, "2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\n").unwrap();
+
+        let netdev = format!(
+            "[NetDev]
+Name = wg0
+Kind = wireguard
+
+[WireGuardPeer]
+# friendly_name = alice
+PublicKeyFile = {}
+AllowedIPs = 10.70.0.2/32
+
+[WireGuardPeer]
+# friendly_name = bob
+PublicKey = qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=
+AllowedIPs = 10.70.0.3/32
+",
+            path.display()
+        );
+        let a = peer_entry_hashmap_try_from(&netdev);
+        std::fs::remove_file(&path).unwrap();
+        let a = a.unwrap();
+
+        assert_eq!(
+            Some(FriendlyDescription::Name("alice".into())),
+            a["2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk="].friendly_description
+        );
+        assert_eq!(
+            Some(FriendlyDescription::Name("bob".into())),
+            a["qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU="].friendly_description
+        );
+    }
+
+    #[test]
+    fn test_parse_netdev_missing_public_key_file() {
+        const NETDEV: &str = "[WireGuardPeer]
+PublicKeyFile = /nonexistent/peer.pub
+AllowedIPs = 10.70.0.2/32
+";
+        match peer_entry_hashmap_try_from(NETDEV) {
+            Err(PeerEntryParseError::PublicKeyFileReadError { path, .. }) => {
+                assert_eq!(path, "/nonexistent/peer.pub")
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_config_files_relative_public_key_file() {
+        let config_dir = std::env::temp_dir().join(format!(
+            "prometheus_wireguard_exporter_test_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(config_dir.join("keys")).unwrap();
+        std::fs::write(
+            config_dir.join("keys/alice.pub"),
+            "2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=\n",
+        )
+        .unwrap();
+
+        let config_file = config_dir.join("wg0.netdev").display().to_string();
+        let config_files = vec![
+            (
+                config_file.clone(),
+                "[WireGuardPeer]
+# friendly_name = alice
+PublicKeyFile = keys/alice.pub
+AllowedIPs = 10.70.0.2/32
+"
+                .to_owned(),
+            ),
+            (
+                "/etc/wireguard/wg1.conf".to_owned(),
+                "[Peer]
+# friendly_name = bob
+PublicKey = qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=
+AllowedIPs = 10.70.0.3/32
+"
+                .to_owned(),
+            ),
+        ];
+        let a = parse_config_files(&config_files);
+
+        let missing = vec![(
+            config_file.clone(),
+            "[WireGuardPeer]
+PublicKeyFile = keys/missing.pub
+AllowedIPs = 10.70.0.4/32
+"
+            .to_owned(),
+        )];
+        let missing = parse_config_files(&missing);
+        std::fs::remove_dir_all(&config_dir).unwrap();
+
+        let (a, parse_errors) = a.unwrap();
+        assert_eq!(parse_errors, 0);
+        assert_eq!(
+            Some(FriendlyDescription::Name("alice".into())),
+            a["2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk="].friendly_description
+        );
+        assert_eq!(
+            Some(FriendlyDescription::Name("bob".into())),
+            a["qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU="].friendly_description
+        );
+
+        match missing {
+            Err(e) => {
+                let message = e.to_string();
+                assert!(message.contains(&config_file));
+                assert!(
+                    message.contains(&config_dir.join("keys/missing.pub").display().to_string())
+                );
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_names_file() {
+        const NAMES: &str = r#"{
+    "2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=": "alice",
+    "wTjv6hS6fKfNK+SzOLo7O6BQjEb6AD1TN9GjwZ08IwA=": "the \"new\" one"
+}"#;
+        let mut a: PeerEntryHashMap = peer_entry_hashmap_try_from(TEXT).unwrap();
+        let names = names_file_try_from("/etc/wireguard/names.json", NAMES).unwrap();
+        merge_names_file(&mut a, names);
+
+        // the names file wins over the config file
+        let entry = &a["2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk="];
+        assert_eq!(
+            Some(FriendlyDescription::Name("alice".into())),
+            entry.friendly_description
+        );
+        assert_eq!(entry.allowed_ips, "10.70.0.2/32");
+
+        // peers missing from the config file are added
+        let entry = &a["wTjv6hS6fKfNK+SzOLo7O6BQjEb6AD1TN9GjwZ08IwA="];
+        assert_eq!(
+            Some(FriendlyDescription::Name(r#"the "new" one"#.into())),
+            entry.friendly_description
+        );
+
+        // the others are untouched
+        assert_eq!(
+            Some(FriendlyDescription::Name("cantarch".into())),
+            a["L2UoJZN7RmEKsMmqaJgKG0m1S2Zs2wd2ptAf+kb3008="].friendly_description
+        );
+    }
+
+    #[test]
+    fn test_names_file_errors() {
+        assert!(matches!(
+            names_file_try_from("names.yaml", "{}"),
+            Err(NamesFileParseError::UnsupportedExtension { .. })
+        ));
+        assert!(matches!(
+            names_file_try_from("names.json", "[]"),
+            Err(NamesFileParseError::NotAnObject)
+        ));
+        assert!(matches!(
+            names_file_try_from("names.json", r#"{ "key": 1 }"#),
+            Err(NamesFileParseError::InvalidName { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_duplicate_public_keys() {
+        // as if two config files were joined
+        const TEXT_DUPLICATES: &str = "
+[Peer]
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+
+[Peer]
+# friendly_name = frcognoarch
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+
+[Peer]
+# friendly_name = OnePlus 5T
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+";
+        let a: PeerEntryHashMap = peer_entry_hashmap_try_from(TEXT_DUPLICATES).unwrap();
+        assert_eq!(a.len(), 1);
+        assert_eq!(
+            a.get("2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=")
+                .unwrap()
+                .friendly_description,
+            Some(FriendlyDescription::Name("frcognoarch".into()))
+        );
+    }
+
+    #[test]
+    fn test_parse_broken_friendly_json() {
+        const TEXT_BROKEN_JSON: &str = "
+[Peer]
+# friendly_json = {\"username\":\"alice\"}
+PublicKey = 2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=
+AllowedIPs = 10.70.0.2/32
+
+[Peer]
+# friendly_json = {\"username\":
+PublicKey = qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=
+AllowedIPs = 10.70.0.3/32
+";
+        let (a, parse_errors) = parse_peer_entries(TEXT_BROKEN_JSON).unwrap();
+        assert_eq!(parse_errors, 1);
+        assert_eq!(a.len(), 2);
+        assert!(matches!(
+            a.get("2S7mA0vEMethCNQrJpJKE81/JmhgtB+tHHLYQhgM6kk=")
+                .unwrap()
+                .friendly_description,
+            Some(FriendlyDescription::Json(_))
+        ));
+        // the broken peer is still exported, without friendly description
+        assert_eq!(
+            a.get("qnoxQoQI8KKMupLnSSureORV0wMmH7JryZNsmGVISzU=")
+                .unwrap()
+                .friendly_description,
+            None
+        );
+    }
+
     #[test]
     #[should_panic(
         expected = "PublicKeyNotFound { lines: [\"# friendly_name = varch.local (laptop)\", \"AllowedIPs = 10.70.0.3/32\"] }"